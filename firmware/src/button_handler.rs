@@ -3,7 +3,8 @@ use rtic_monotonics::rp2040::prelude::*;
 
 use rp_pico::hal::gpio::{self, DynPinId, FunctionSio, Interrupt, Pin, PinId, PullUp, SioInput};
 
-use crate::commando_unit::{CommandEvent, Input};
+use master_core::commando_unit::{CommandEvent, Input};
+
 use crate::midi_master::MessageSender;
 use crate::Mono;
 
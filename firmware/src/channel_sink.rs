@@ -0,0 +1,65 @@
+use portable_atomic::{AtomicU32, Ordering};
+use rtic_sync::channel::TrySendError;
+
+use master_core::sink::Sink;
+
+use crate::midi_master::MessageSender;
+
+// How many messages were dropped because a channel was full, by channel
+pub struct Overflows {
+    pub midi: AtomicU32,
+    pub output: AtomicU32,
+    pub player: AtomicU32,
+    pub command: AtomicU32,
+    pub usb_midi: AtomicU32,
+}
+
+pub static OVERFLOWS: Overflows = Overflows {
+    midi: AtomicU32::new(0),
+    output: AtomicU32::new(0),
+    player: AtomicU32::new(0),
+    command: AtomicU32::new(0),
+    usb_midi: AtomicU32::new(0),
+};
+
+impl Overflows {
+    pub fn counts(&self) -> [(&'static str, u32); 5] {
+        [
+            ("midi", self.midi.load(Ordering::Relaxed)),
+            ("output", self.output.load(Ordering::Relaxed)),
+            ("player", self.player.load(Ordering::Relaxed)),
+            ("command", self.command.load(Ordering::Relaxed)),
+            ("usb_midi", self.usb_midi.load(Ordering::Relaxed)),
+        ]
+    }
+}
+
+// Lets the rtic channels be used wherever master_core wants a sink
+pub struct ChannelSink<T: 'static> {
+    sender: MessageSender<T>,
+    overflows: &'static AtomicU32,
+}
+
+impl<T> ChannelSink<T> {
+    pub fn new(sender: MessageSender<T>, overflows: &'static AtomicU32) -> Self {
+        ChannelSink { sender, overflows }
+    }
+}
+
+impl<T> Clone for ChannelSink<T> {
+    fn clone(&self) -> Self {
+        ChannelSink::new(self.sender.clone(), self.overflows)
+    }
+}
+
+impl<T> Sink<T> for ChannelSink<T> {
+    fn try_send(&mut self, item: T) -> Result<(), T> {
+        self.sender.try_send(item).map_err(|e| match e {
+            TrySendError::NoReceiver(item) => item,
+            TrySendError::Full(item) => {
+                self.overflows.fetch_add(1, Ordering::Relaxed);
+                item
+            }
+        })
+    }
+}
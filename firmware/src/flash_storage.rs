@@ -0,0 +1,93 @@
+use rp_pico::hal::rom_data;
+
+use master_core::storage::{Storage, SLOTS, SLOT_SIZE};
+
+const XIP_BASE: u32 = 0x1000_0000;
+const FLASH_SIZE: u32 = 2048 * 1024;
+// The top of flash is kept out of FLASH in memory.x so that code never ends up here
+const STORAGE_OFFSET: u32 = FLASH_SIZE - (SLOTS * SLOT_SIZE) as u32;
+
+const BLOCK_SIZE: u32 = 1 << 16;
+const BLOCK_ERASE_CMD: u8 = 0xd8;
+
+struct RomFunctions {
+    connect_internal_flash: unsafe extern "C" fn(),
+    flash_exit_xip: unsafe extern "C" fn(),
+    flash_range_erase: unsafe extern "C" fn(u32, usize, u32, u8),
+    flash_range_program: unsafe extern "C" fn(u32, *const u8, usize),
+    flash_flush_cache: unsafe extern "C" fn(),
+    flash_enter_cmd_xip: unsafe extern "C" fn(),
+}
+
+pub struct FlashStorage {}
+
+impl FlashStorage {
+    pub fn new() -> Self {
+        FlashStorage {}
+    }
+
+    fn offset(slot: usize) -> u32 {
+        STORAGE_OFFSET + (slot * SLOT_SIZE) as u32
+    }
+}
+
+impl Storage for FlashStorage {
+    fn read(&mut self, slot: usize, buf: &mut [u8; SLOT_SIZE]) -> Option<()> {
+        if slot >= SLOTS {
+            return None;
+        }
+        let start = (XIP_BASE + Self::offset(slot)) as *const u8;
+        for i in 0..SLOT_SIZE {
+            buf[i] = unsafe { core::ptr::read_volatile(start.add(i)) };
+        }
+        Some(())
+    }
+
+    // Blocks everything, including the watchdog feeder, for as long as the
+    // erase takes. Whoever calls this has to give the watchdog some slack.
+    fn write(&mut self, slot: usize, buf: &[u8; SLOT_SIZE]) -> Option<()> {
+        if slot >= SLOTS {
+            return None;
+        }
+        // Nothing in flash may be touched once XIP is off, so look everything up first
+        let rom = RomFunctions {
+            connect_internal_flash: rom_data::connect_internal_flash::ptr(),
+            flash_exit_xip: rom_data::flash_exit_xip::ptr(),
+            flash_range_erase: rom_data::flash_range_erase::ptr(),
+            flash_range_program: rom_data::flash_range_program::ptr(),
+            flash_flush_cache: rom_data::flash_flush_cache::ptr(),
+            flash_enter_cmd_xip: rom_data::flash_enter_cmd_xip::ptr(),
+        };
+        // boot2 sets up the fast XIP mode again afterwards, it is copied out
+        // of flash for the same reason.
+        let mut boot2 = [0u32; 64];
+        unsafe {
+            core::ptr::copy_nonoverlapping(XIP_BASE as *const u32, boot2.as_mut_ptr(), 64);
+        }
+        cortex_m::interrupt::free(|_| unsafe {
+            program_sector(&rom, boot2.as_ptr() as u32 + 1, Self::offset(slot), buf);
+        });
+        Some(())
+    }
+}
+
+#[inline(never)]
+#[link_section = ".data.ram_func"]
+unsafe fn program_sector(rom: &RomFunctions, boot2: u32, offset: u32, buf: &[u8; SLOT_SIZE]) {
+    (rom.connect_internal_flash)();
+    (rom.flash_exit_xip)();
+    (rom.flash_range_erase)(offset, SLOT_SIZE, BLOCK_SIZE, BLOCK_ERASE_CMD);
+    (rom.flash_range_program)(offset, buf.as_ptr(), SLOT_SIZE);
+    (rom.flash_flush_cache)();
+    (rom.flash_enter_cmd_xip)();
+    core::arch::asm!(
+        "blx {boot2}",
+        boot2 = in(reg) boot2,
+        out("r0") _,
+        out("r1") _,
+        out("r2") _,
+        out("r3") _,
+        out("r12") _,
+        out("lr") _,
+    );
+}
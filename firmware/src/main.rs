@@ -0,0 +1,1071 @@
+#![no_std]
+#![no_main]
+#![allow(dead_code)]
+#![allow(unused_imports)]
+#![allow(unreachable_code)]
+
+use panic_semihosting as _; // panic handler
+
+mod button_handler;
+mod channel_sink;
+mod flash_storage;
+mod midi_input;
+mod outs;
+mod pwm_pair;
+mod usb_midi;
+
+use rtic_monotonics::rp2040::prelude::*;
+
+rp2040_timer_monotonic!(Mono);
+
+#[rtic::app(device = rp_pico::hal::pac, dispatchers = [SW0_IRQ, SW1_IRQ, SW2_IRQ, SW3_IRQ])]
+mod midi_master {
+    use ::nb::Error;
+    use embedded_hal::can::nb;
+    use embedded_hal::digital::v2::{InputPin, OutputPin};
+    use fugit::Duration;
+    use rp_pico::hal::{self, gpio};
+
+    use fugit::RateExtU32;
+
+    use hal::gpio::bank0::Gpio25;
+    use hal::gpio::Pin;
+    use rp_pico::hal::gpio::Interrupt;
+    use rp_pico::hal::gpio::PullDown;
+    use rp_pico::hal::gpio::SioOutput;
+    use rp_pico::hal::gpio::{DynPinId, FunctionSio};
+    use usb_device::device::StringDescriptors;
+
+    use core::fmt::Write;
+    use rtic_monotonics::rp2040::prelude::*;
+
+    use usb_device::class_prelude::UsbBusAllocator;
+    use usb_device::device::UsbDevice;
+    use usb_device::prelude::UsbDeviceBuilder;
+    use usb_device::prelude::UsbVidPid;
+    use usb_device::{self, LangID};
+
+    use midly::{live::LiveEvent, MidiMessage};
+
+    use rtic::mutex::prelude::*;
+    use rtic_sync::{channel::*, make_channel};
+    use usbd_serial::SerialPort;
+
+    use hal::pwm;
+
+    use master_core::clock::TempoClock;
+    use master_core::commando_unit::{CommandEvent, CommandoUnit, Input, Operation};
+    use master_core::console::{
+        self, CalibrateCommand, Command, DrumCommand, DynamicsCommand, LineReader, PlayerStatus,
+        SongCommand, LINE_SIZE, MAX_REPLY_SIZE,
+    };
+    use master_core::midi_mapper::{Config, MidiMapper};
+    use master_core::outs::{Calibration, Cv, Gate, OutputRequest};
+    use master_core::player::{Player, PlayerAction, PlayerMessage, PATTERN_SIZE};
+    use master_core::prorgrammer::Programmer;
+    use master_core::sink::{Sink, Tee};
+    use master_core::song::Arranger;
+    use master_core::storage;
+    use master_core::sysex::{self, MAX_DUMPS_SIZE, MAX_SYSEX_SIZE};
+    use master_core::usb_midi::{event_packet, unpack_packets, Packer, PACKET_SIZE};
+
+    use crate::button_handler::ButtonHandler;
+    use crate::channel_sink::{ChannelSink, OVERFLOWS};
+    use crate::flash_storage::FlashStorage;
+    use crate::midi_input::{self, MidiInput, MidiPort};
+    use crate::outs::{CvPorts, GateMappings, OutputHandler};
+    use crate::pwm_pair::CvPair;
+    use crate::usb_midi::{MidiClass, MAX_PACKET_SIZE};
+    use crate::Mono;
+
+    const MESSAGE_CAPACITY: usize = 64;
+    const SYSEX_CAPACITY: usize = 2;
+    const CONSOLE_CAPACITY: usize = 2;
+    // A slot for every player and then some
+    const ARRANGED_CAPACITY: usize = 8;
+    const WATCHDOG_TIMEOUT_US: u32 = 50_000;
+    // Erasing a flash sector can take several hundred milliseconds and saving a
+    // player erases two
+    const FLASH_WATCHDOG_TIMEOUT_US: u32 = 2_000_000;
+    // How many milliseconds a reply over USB waits for the host before giving up
+    const USB_WRITE_ATTEMPTS: u32 = 100;
+    const PITCHED_CHANELL: midly::num::u4 = midly::num::u4::new(1);
+    const DRUM_CHANELL: midly::num::u4 = midly::num::u4::new(5);
+    pub type MessageSender<T> = Sender<'static, T, MESSAGE_CAPACITY>;
+    type MessageReceiver<T> = Receiver<'static, T, MESSAGE_CAPACITY>;
+    // Players go both to the mapper and out over USB
+    type MidiSink = Tee<ChannelSink<LiveEvent<'static>>, ChannelSink<LiveEvent<'static>>>;
+    type OutputSink = ChannelSink<OutputRequest>;
+    type PlayerSink = ChannelSink<PlayerMessage>;
+    pub type SysExMessage = heapless::Vec<u8, MAX_SYSEX_SIZE>;
+    pub type SysExSender = Sender<'static, (MidiPort, SysExMessage), SYSEX_CAPACITY>;
+    type SysExReceiver = Receiver<'static, (MidiPort, SysExMessage), SYSEX_CAPACITY>;
+    type UsbBus = hal::usb::UsbBus;
+    type ConsoleLine = heapless::String<LINE_SIZE>;
+    type UartType = hal::uart::UartPeripheral<
+        hal::uart::Enabled,
+        hal::pac::UART0,
+        (
+            Pin<gpio::bank0::Gpio0, gpio::FunctionUart, gpio::PullDown>,
+            Pin<gpio::bank0::Gpio1, gpio::FunctionUart, gpio::PullDown>,
+        ),
+    >;
+
+    // Microseconds since boot, which is what the tempo clock counts in
+    fn now() -> u64 {
+        Mono::now().ticks()
+    }
+
+    fn blink(
+        led: &mut Pin<DynPinId, FunctionSio<SioOutput>, PullDown>,
+        duration: Duration<u64, 1, 1_000_000>,
+        hold_down: bool,
+    ) {
+        led.set_high().unwrap();
+        let quick = Mono::now() + duration;
+        while Mono::now() < quick {}
+        led.set_low().unwrap();
+        if hold_down {
+            let quick = Mono::now() + duration / 2;
+            while Mono::now() < quick {}
+        }
+    }
+
+    #[local]
+    struct Local {
+        usb_device: UsbDevice<'static, UsbBus>,
+        output_handler: OutputHandler,
+        commando_player_sender: PlayerSink,
+        console_player_sender: PlayerSink,
+        console_output_sender: OutputSink,
+        console_storage: FlashStorage,
+        calibrations: [Calibration; 4],
+        clock_midi_sender: ChannelSink<LiveEvent<'static>>,
+        clock_player_sender: PlayerSink,
+        uart_input: MidiInput,
+        usb_input: MidiInput,
+        line_reader: LineReader,
+        console_sender: Sender<'static, ConsoleLine, CONSOLE_CAPACITY>,
+        storage: FlashStorage,
+        commando: CommandoUnit,
+        button_handler: ButtonHandler,
+        programmer: Programmer<PlayerSink, OutputSink>,
+    }
+
+    #[shared]
+    struct Shared {
+        led: gpio::Pin<DynPinId, gpio::FunctionSioOutput, gpio::PullDown>,
+        watchdog: hal::Watchdog,
+        uart: UartType,
+        usb_midi: MidiClass<'static, UsbBus>,
+        usb_serial: SerialPort<'static, UsbBus>,
+        midi_mapper: MidiMapper<OutputSink>,
+        tempo_clock: TempoClock,
+        arranger: Arranger,
+        players: [Player<MidiSink, OutputSink>; 5],
+        output_sender: MessageSender<OutputRequest>,
+        rec_switch: Pin<gpio::bank0::Gpio2, gpio::FunctionSioInput, gpio::PullUp>,
+        perform_switch: Pin<gpio::bank0::Gpio3, gpio::FunctionSioInput, gpio::PullUp>,
+    }
+
+    #[init(local = [usb_bus: Option<UsbBusAllocator<UsbBus>> = None])]
+    fn init(c: init::Context) -> (Shared, Local) {
+        unsafe {
+            hal::sio::spinlock_reset();
+        }
+
+        let mut resets = c.device.RESETS;
+        Mono::start(c.device.TIMER, &resets);
+
+        let watchdog_timeout = c.device.WATCHDOG.reason().read().timer().bit_is_set();
+
+        let mut watchdog = hal::Watchdog::new(c.device.WATCHDOG);
+        let clocks = hal::clocks::init_clocks_and_plls(
+            rp_pico::XOSC_CRYSTAL_FREQ,
+            c.device.XOSC,
+            c.device.CLOCKS,
+            c.device.PLL_SYS,
+            c.device.PLL_USB,
+            &mut resets,
+            &mut watchdog,
+        )
+        .ok()
+        .unwrap();
+
+        let sio = hal::Sio::new(c.device.SIO);
+        let pins = hal::gpio::Pins::new(
+            c.device.IO_BANK0,
+            c.device.PADS_BANK0,
+            sio.gpio_bank0,
+            &mut resets,
+        );
+
+        let mut led = pins.gpio25.into_push_pull_output().into_dyn_pin();
+        led.set_high().unwrap();
+        if watchdog_timeout {
+            led.set_low().unwrap();
+            let mut blinks = 5;
+            while blinks > 0 {
+                blink(&mut led, 100.millis(), true);
+                blinks -= 1;
+            }
+        }
+
+        let gate_pins = GateMappings {
+            open_hh: pins.gpio7.reconfigure(),
+            clap: pins.gpio9.reconfigure(),
+            snare: pins.gpio10.reconfigure(),
+            kick: pins.gpio27.reconfigure(),
+            fx: pins.gpio6.reconfigure(),
+            accent: pins.gpio5.reconfigure(),
+            closed_hh: pins.gpio8.reconfigure(),
+            start: pins.gpio26.reconfigure(),
+            stop: pins.gpio18.reconfigure(),
+            clock: pins.gpio28.reconfigure(),
+            gate_a: pins.gpio22.reconfigure(),
+            gate_b: pins.gpio21.reconfigure(),
+            gate_c: pins.gpio20.reconfigure(),
+            gate_d: pins.gpio19.reconfigure(),
+        };
+
+        let pwm_slices = hal::pwm::Slices::new(c.device.PWM, &mut resets);
+
+        // An uncalibrated unit plays as if its outputs were perfect
+        let calibrations = storage::load_calibration(&mut FlashStorage::new())
+            .unwrap_or([Calibration::linear(); 4]);
+        let cv_ports = CvPorts::new(
+            CvPair::new(
+                pwm_slices.pwm7,
+                pins.gpio14.into_function::<gpio::FunctionPwm>(),
+                pins.gpio15.into_function::<gpio::FunctionPwm>(),
+            ),
+            CvPair::new(
+                pwm_slices.pwm0,
+                pins.gpio16.into_function::<gpio::FunctionPwm>(),
+                pins.gpio17.into_function::<gpio::FunctionPwm>(),
+            ),
+            calibrations,
+        );
+
+        let mut output_handler = OutputHandler::new(gate_pins, cv_ports);
+        output_handler.reset();
+
+        let (output_sender, output_receiver) = make_channel!(OutputRequest, MESSAGE_CAPACITY);
+
+        let usb_bus: &'static _ = c.local.usb_bus.insert(UsbBusAllocator::new(UsbBus::new(
+            c.device.USBCTRL_REGS,
+            c.device.USBCTRL_DPRAM,
+            clocks.usb_clock,
+            true,
+            &mut resets,
+        )));
+        let usb_midi = MidiClass::new(usb_bus);
+        let usb_serial = SerialPort::new(usb_bus);
+        // pid.codes test VID/PID, fine as long as it stays on our own desks
+        let usb_device = UsbDeviceBuilder::new(usb_bus, UsbVidPid(0x1209, 0x0001))
+            .strings(&[StringDescriptors::new(LangID::EN)
+                .manufacturer("TheGrandmother")
+                .product("YAMM")
+                .serial_number("0001")])
+            .unwrap()
+            .composite_with_iads()
+            .build();
+
+        // let mut play_btn = pins.gpio11.into_pull_up_input();
+        // play_btn.set_interrupt_enabled(Interrupt::EdgeLow, true);
+        // play_btn.set_interrupt_enabled(Interrupt::EdgeHigh, true);
+        // play_btn.clear_interrupt(Interrupt::EdgeLow);
+        // play_btn.clear_interrupt(Interrupt::EdgeHigh);
+
+        // Set up UART on GP0 and GP1 (Pico pins 1 and 2)
+        let uart_pins = (
+            pins.gpio0.into_function::<gpio::FunctionUart>(),
+            pins.gpio1.into_function::<gpio::FunctionUart>(),
+        );
+
+        // Need to perform clock init before using UART or it will freeze.
+        let mut uart = hal::uart::UartPeripheral::new(c.device.UART0, uart_pins, &mut resets)
+            .enable(
+                hal::uart::UartConfig::new(
+                    31250.Hz(),
+                    hal::uart::DataBits::Eight,
+                    None,
+                    hal::uart::StopBits::One,
+                ),
+                hal::Clock::freq(&clocks.peripheral_clock),
+            )
+            .unwrap();
+        uart.enable_rx_interrupt();
+
+        let (midi_sender, midi_receiver) = make_channel!(LiveEvent<'static>, MESSAGE_CAPACITY);
+        let (player_sender, player_receiver) = make_channel!(PlayerMessage, MESSAGE_CAPACITY);
+        let (commando_sender, command_receiver) = make_channel!(CommandEvent, MESSAGE_CAPACITY);
+        let (sysex_sender, sysex_receiver) =
+            make_channel!((MidiPort, SysExMessage), SYSEX_CAPACITY);
+        let (usb_midi_sender, usb_midi_receiver) =
+            make_channel!(LiveEvent<'static>, MESSAGE_CAPACITY);
+        let (console_sender, console_receiver) = make_channel!(ConsoleLine, CONSOLE_CAPACITY);
+
+        let commando = CommandoUnit::new();
+
+        let play_pin = pins.gpio11.into_pull_up_input();
+        let step_pin = pins.gpio12.into_pull_up_input();
+        let rec_pin = pins.gpio13.into_pull_up_input();
+        let midi_mapper = MidiMapper::new(
+            Config::select_confg(
+                play_pin.is_low().unwrap_or(false),
+                step_pin.is_low().unwrap_or(false),
+                rec_pin.is_low().unwrap_or(false),
+            ),
+            ChannelSink::new(output_sender.clone(), &OVERFLOWS.output),
+        );
+
+        let button_handler =
+            ButtonHandler::new(play_pin, step_pin, rec_pin, commando_sender.clone());
+
+        let make_player = |channel| {
+            Player::new(
+                channel,
+                8,
+                Tee(
+                    ChannelSink::new(midi_sender.clone(), &OVERFLOWS.midi),
+                    ChannelSink::new(usb_midi_sender.clone(), &OVERFLOWS.usb_midi),
+                ),
+                ChannelSink::new(output_sender.clone(), &OVERFLOWS.output),
+            )
+        };
+        let players = [
+            make_player(0),
+            make_player(1),
+            make_player(2),
+            make_player(3),
+            make_player(4),
+        ];
+        let programmer = Programmer::new(
+            ChannelSink::new(player_sender.clone(), &OVERFLOWS.player),
+            ChannelSink::new(output_sender.clone(), &OVERFLOWS.output),
+        );
+
+        watchdog.start(fugit::ExtU32::micros(WATCHDOG_TIMEOUT_US));
+        watchdog_feeder::spawn().ok();
+        // usb_handler::spawn(uart_receiver).ok();
+        // test_suite::spawn(output_sender.clone()).ok();
+        command_handler::spawn(command_receiver).ok();
+        output_task::spawn(output_receiver).ok();
+        midi_handler::spawn(midi_receiver).ok();
+        player_handler::spawn(player_receiver).ok();
+        sysex_handler::spawn(sysex_receiver).ok();
+        usb_midi_writer::spawn(usb_midi_receiver).ok();
+        console_handler::spawn(console_receiver).ok();
+        clock_generator::spawn().ok();
+        // Bring back whatever was saved before the last power cycle or reset
+        player_sender
+            .clone()
+            .try_send(PlayerMessage::Broadcast(PlayerAction::Load))
+            .ok();
+
+        return (
+            Shared {
+                led,
+                watchdog,
+                uart,
+                usb_midi,
+                usb_serial,
+                midi_mapper,
+                tempo_clock: TempoClock::new(),
+                arranger: Arranger::new(),
+                players,
+                output_sender: output_sender.clone(),
+                rec_switch: pins.gpio2.reconfigure(),
+                perform_switch: pins.gpio3.reconfigure(),
+            },
+            Local {
+                usb_device,
+                output_handler,
+                commando_player_sender: ChannelSink::new(player_sender.clone(), &OVERFLOWS.player),
+                console_player_sender: ChannelSink::new(player_sender.clone(), &OVERFLOWS.player),
+                console_output_sender: ChannelSink::new(output_sender.clone(), &OVERFLOWS.output),
+                console_storage: FlashStorage::new(),
+                calibrations,
+                clock_midi_sender: ChannelSink::new(midi_sender.clone(), &OVERFLOWS.midi),
+                clock_player_sender: ChannelSink::new(player_sender.clone(), &OVERFLOWS.player),
+                uart_input: MidiInput::new(
+                    MidiPort::Din,
+                    ChannelSink::new(midi_sender.clone(), &OVERFLOWS.midi),
+                    ChannelSink::new(player_sender.clone(), &OVERFLOWS.player),
+                    ChannelSink::new(commando_sender.clone(), &OVERFLOWS.command),
+                    sysex_sender.clone(),
+                ),
+                usb_input: MidiInput::new(
+                    MidiPort::Usb,
+                    ChannelSink::new(midi_sender, &OVERFLOWS.midi),
+                    ChannelSink::new(player_sender.clone(), &OVERFLOWS.player),
+                    ChannelSink::new(commando_sender.clone(), &OVERFLOWS.command),
+                    sysex_sender,
+                ),
+                line_reader: LineReader::new(),
+                console_sender,
+                storage: FlashStorage::new(),
+                commando,
+                button_handler,
+                programmer,
+            },
+        );
+    }
+
+    #[task(priority=3, local = [output_handler], shared=[led])]
+    async fn output_task(c: output_task::Context, mut receiver: MessageReceiver<OutputRequest>) {
+        let mut waiting_time: Duration<u64, 1, 1000000> = 1.millis();
+        loop {
+            match Mono::timeout_after(waiting_time, receiver.recv()).await {
+                Ok(msg) => match msg {
+                    Ok(req) => {
+                        c.local.output_handler.handle_message(req);
+                    }
+                    Err(_) => {}
+                },
+                Err(_) => {}
+            };
+            let flashes = c.local.output_handler.check_flashes();
+            let updates = c.local.output_handler.update_ports();
+            waiting_time = match (flashes, updates) {
+                (Some(flashes), Some(updates)) => flashes.min(updates),
+                (flashes, updates) => flashes.or(updates).unwrap_or(100.millis()),
+            };
+        }
+    }
+
+    #[task(priority=2, shared=[midi_mapper])]
+    async fn midi_handler(
+        mut c: midi_handler::Context,
+        mut receiver: MessageReceiver<LiveEvent<'static>>,
+    ) {
+        loop {
+            match receiver.recv().await {
+                Ok(event) => c
+                    .shared
+                    .midi_mapper
+                    .lock(|midi_mapper| midi_mapper.handle_message(event)),
+                Err(_) => {}
+            }
+        }
+    }
+
+    #[task(priority=1, local = [commando_player_sender, commando, programmer], shared=[led, &rec_switch, &perform_switch])]
+    async fn command_handler(
+        c: command_handler::Context,
+        mut receiver: MessageReceiver<CommandEvent>,
+    ) {
+        loop {
+            // FOR SOME HORRID REASON I CANNOT USE ASYNC HERE!?
+            let recording = c.shared.rec_switch.is_high().unwrap_or(false);
+            let performing = c.shared.perform_switch.is_high().unwrap_or(false);
+            match receiver.try_recv() {
+                Ok(event) if recording || performing => {
+                    match c.local.commando.handle_event(event, performing) {
+                        Some(Operation::Perform(channel, action)) => {
+                            c.local
+                                .commando_player_sender
+                                .try_send(PlayerMessage::Action(channel, action))
+                                .ok();
+                        }
+                        Some(op) => c.local.programmer.handle_operation(op),
+                        None => {}
+                    };
+                }
+                Err(_) => {}
+                _ => {}
+            }
+            Mono::delay(1.millis()).await;
+        }
+    }
+
+    #[task(priority=2, local = [storage], shared=[led, watchdog, players, arranger])]
+    async fn player_handler(
+        mut c: player_handler::Context,
+        mut receiver: MessageReceiver<PlayerMessage>,
+    ) {
+        loop {
+            match receiver.recv().await {
+                Ok(action) => c.shared.players.lock(|players| {
+                    // What the song wants goes ahead of whatever brought it on
+                    let mut arranged: heapless::Deque<PlayerMessage, ARRANGED_CAPACITY> =
+                        heapless::Deque::new();
+                    let loops = core::array::from_fn(|i| players[i].loop_ticks());
+                    c.shared
+                        .arranger
+                        .lock(|arranger| arranger.handle_message(action, loops, &mut arranged));
+                    while let Some(msg) = arranged.pop_front() {
+                        for player in players.iter_mut() {
+                            player.handle_message(msg);
+                        }
+                    }
+                    for i in 0..players.len() {
+                        players[i].handle_message(action);
+                        if storage::is_save(action) {
+                            c.shared.watchdog.lock(|watchdog| {
+                                watchdog.start(fugit::ExtU32::micros(FLASH_WATCHDOG_TIMEOUT_US));
+                                storage::handle_message(c.local.storage, &mut players[i], action);
+                                watchdog.start(fugit::ExtU32::micros(WATCHDOG_TIMEOUT_US));
+                            });
+                        } else {
+                            storage::handle_message(c.local.storage, &mut players[i], action);
+                        }
+                    }
+                }),
+                Err(_) => {}
+            }
+        }
+    }
+
+    #[task(priority=1, shared=[players, uart, usb_midi])]
+    async fn sysex_handler(mut c: sysex_handler::Context, mut receiver: SysExReceiver) {
+        let mut reply = [0u8; MAX_DUMPS_SIZE];
+        let mut packer = Packer::new(0);
+        loop {
+            match receiver.recv().await {
+                Ok((port, msg)) => {
+                    let length = c
+                        .shared
+                        .players
+                        .lock(|players| sysex::handle(players, &msg, &mut reply));
+                    match (length, port) {
+                        (Some(length), MidiPort::Din) => {
+                            // A dump of every slot takes a couple of seconds at 31250 baud
+                            let mut rest = &reply[..length];
+                            while !rest.is_empty() {
+                                rest = c
+                                    .shared
+                                    .uart
+                                    .lock(|uart| uart.write_raw(rest).unwrap_or(rest));
+                                Mono::delay(1.millis()).await;
+                            }
+                        }
+                        (Some(length), MidiPort::Usb) => {
+                            let mut chunks = packer.packets(&reply[..length]);
+                            let mut buf = [0u8; MAX_PACKET_SIZE as usize];
+                            let mut pending = 0;
+                            let mut attempts = 0;
+                            loop {
+                                while pending + PACKET_SIZE <= buf.len() {
+                                    match chunks.next() {
+                                        Some(packet) => {
+                                            buf[pending..pending + PACKET_SIZE]
+                                                .copy_from_slice(&packet);
+                                            pending += PACKET_SIZE;
+                                        }
+                                        None => break,
+                                    }
+                                }
+                                if pending == 0 {
+                                    break;
+                                }
+                                match c.shared.usb_midi.lock(|midi| midi.write(&buf[..pending])) {
+                                    Ok(_) => {
+                                        pending = 0;
+                                        attempts = 0;
+                                    }
+                                    Err(_) if attempts < USB_WRITE_ATTEMPTS => attempts += 1,
+                                    Err(_) => break, // The host has stopped listening
+                                }
+                                Mono::delay(1.millis()).await;
+                            }
+                        }
+                        (None, _) => {}
+                    }
+                }
+                Err(_) => {}
+            }
+        }
+    }
+
+    #[task(priority=1, shared=[usb_midi])]
+    async fn usb_midi_writer(
+        mut c: usb_midi_writer::Context,
+        mut receiver: MessageReceiver<LiveEvent<'static>>,
+    ) {
+        let mut buf = [0u8; MAX_PACKET_SIZE as usize];
+        loop {
+            match receiver.recv().await {
+                Ok(event) => {
+                    // Whatever else is waiting goes out in the same transfer
+                    let mut length = 0;
+                    let mut next = Some(event);
+                    while let Some(event) = next {
+                        match event_packet(0, event) {
+                            Some(packet) => {
+                                buf[length..length + PACKET_SIZE].copy_from_slice(&packet);
+                                length += PACKET_SIZE;
+                            }
+                            None => {}
+                        }
+                        next = if length + PACKET_SIZE <= buf.len() {
+                            receiver.try_recv().ok()
+                        } else {
+                            None
+                        };
+                    }
+                    // Dropped if no host is picking them up
+                    c.shared
+                        .usb_midi
+                        .lock(|midi| midi.write(&buf[..length]).ok());
+                }
+                Err(_) => {}
+            }
+        }
+    }
+
+    #[task(local = [usb_device, usb_input, line_reader, console_sender], shared=[usb_midi, usb_serial, tempo_clock, &rec_switch, &perform_switch], binds=USBCTRL_IRQ)]
+    fn usb(mut c: usb::Context) {
+        let recording = c.shared.rec_switch.is_high().unwrap_or(false);
+        let performing = c.shared.perform_switch.is_high().unwrap_or(false);
+        let usb_device = c.local.usb_device;
+        let mut buf = [0u8; MAX_PACKET_SIZE as usize];
+        let mut typed = [0u8; MAX_PACKET_SIZE as usize];
+        let (read, typed_length) = (c.shared.usb_midi, c.shared.usb_serial).lock(|midi, serial| {
+            if !usb_device.poll(&mut [&mut *midi, &mut *serial]) {
+                return (0, 0);
+            }
+            let typed_length = serial.read(&mut typed).unwrap_or(0);
+            // Echo so that the terminal shows what is typed
+            serial.write(&typed[..typed_length]).ok();
+            (midi.read(&mut buf).unwrap_or(0), typed_length)
+        });
+
+        for byte in typed[..typed_length].iter() {
+            if c.local.line_reader.push(*byte) {
+                match ConsoleLine::try_from(c.local.line_reader.line()) {
+                    Ok(line) => c.local.console_sender.try_send(line).ok(),
+                    Err(_) => None,
+                };
+            }
+        }
+
+        let mut bytes = [0u8; MAX_PACKET_SIZE as usize];
+        let length = unpack_packets(&buf[..read], &mut bytes);
+        let usb_input = &mut *c.local.usb_input;
+        c.shared
+            .tempo_clock
+            .lock(|clock| usb_input.receive(&bytes[..length], recording, performing, clock, now()));
+    }
+
+    // Sends internal clock the same way clock from outside goes. Wakes up for
+    // every tick and often enough to notice being started.
+    #[task(priority=2, local = [clock_midi_sender, clock_player_sender], shared=[tempo_clock, &rec_switch, &perform_switch])]
+    async fn clock_generator(mut c: clock_generator::Context) {
+        loop {
+            let recording = c.shared.rec_switch.is_high().unwrap_or(false);
+            let performing = c.shared.perform_switch.is_high().unwrap_or(false);
+            let midi_sender = &mut *c.local.clock_midi_sender;
+            let player_sender = &mut *c.local.clock_player_sender;
+            let next = c.shared.tempo_clock.lock(|clock| {
+                while let Some(msg) = clock.poll(now()) {
+                    if !recording && !performing {
+                        midi_sender.try_send(LiveEvent::Realtime(msg)).ok();
+                    }
+                    match midi_input::player_action(msg) {
+                        Some(action) if !recording => {
+                            player_sender
+                                .try_send(PlayerMessage::Broadcast(action))
+                                .ok();
+                        }
+                        _ => {}
+                    }
+                }
+                if clock.is_running() {
+                    clock.next_tick()
+                } else {
+                    now() + 1_000
+                }
+            });
+            let wait = next.saturating_sub(now()).min(1_000);
+            Mono::delay(wait.micros()).await;
+        }
+    }
+
+    // Gives up when the host stops reading, nothing waits for the console
+    async fn write_serial(
+        serial: &mut impl rtic::Mutex<T = SerialPort<'static, UsbBus>>,
+        mut text: &[u8],
+    ) {
+        let mut attempts = 0;
+        while !text.is_empty() && attempts < USB_WRITE_ATTEMPTS {
+            match serial.lock(|serial| serial.write(text)) {
+                Ok(written) => {
+                    text = &text[written..];
+                    attempts = 0;
+                }
+                Err(_) => {
+                    attempts += 1;
+                    Mono::delay(1.millis()).await;
+                }
+            }
+        }
+    }
+
+    #[task(priority=1, local = [console_player_sender, console_output_sender, console_storage, calibrations], shared=[watchdog, players, midi_mapper, usb_serial, tempo_clock, arranger])]
+    async fn console_handler(
+        mut c: console_handler::Context,
+        mut receiver: Receiver<'static, ConsoleLine, CONSOLE_CAPACITY>,
+    ) {
+        let mut reply: heapless::String<MAX_REPLY_SIZE> = heapless::String::new();
+        let mut pattern = [0u8; PATTERN_SIZE];
+        loop {
+            match receiver.recv().await {
+                Ok(line) => {
+                    reply.clear();
+                    reply.push_str("\r\n").ok();
+                    match console::parse(&line) {
+                        Ok(Command::Help) => {
+                            console::write_help(&mut reply).ok();
+                        }
+                        Ok(Command::Players) => c.shared.players.lock(|players| {
+                            for player in players.iter() {
+                                console::write_status(&mut reply, PlayerStatus::of(player)).ok();
+                            }
+                        }),
+                        Ok(Command::Dump(channel)) => {
+                            let length = c.shared.players.lock(|players| {
+                                players
+                                    .iter()
+                                    .find(|p| p.channel() == channel)
+                                    .map(|p| p.export_pattern(&mut pattern))
+                            });
+                            match length {
+                                Some(length) => {
+                                    console::write_pattern(&mut reply, &pattern[..length]).ok()
+                                }
+                                None => write!(reply, "no player on channel {}\r\n", channel).ok(),
+                            };
+                        }
+                        Ok(Command::Config(config)) => {
+                            c.shared
+                                .midi_mapper
+                                .lock(|midi_mapper| midi_mapper.set_config(config));
+                            reply.push_str("ok\r\n").ok();
+                        }
+                        // Start and stop the internal clock, players get them from there
+                        Ok(Command::Player(PlayerMessage::Broadcast(PlayerAction::Play)))
+                            if c.shared.tempo_clock.lock(|clock| clock.start(now())) =>
+                        {
+                            reply.push_str("ok\r\n").ok();
+                        }
+                        Ok(Command::Player(PlayerMessage::Broadcast(PlayerAction::Stop)))
+                            if c.shared.tempo_clock.lock(|clock| clock.stop()) =>
+                        {
+                            reply.push_str("ok\r\n").ok();
+                        }
+                        Ok(Command::Player(msg)) => {
+                            match c.local.console_player_sender.try_send(msg) {
+                                Ok(_) => reply.push_str("ok\r\n"),
+                                Err(_) => reply.push_str("busy\r\n"),
+                            }
+                            .ok();
+                        }
+                        Ok(Command::Tempo(bpm)) => {
+                            let (bpm, source) = c.shared.tempo_clock.lock(|clock| {
+                                match bpm {
+                                    Some(bpm) => {
+                                        clock.set_bpm(bpm, now());
+                                    }
+                                    None => {}
+                                }
+                                (clock.bpm(), clock.source(now()))
+                            });
+                            console::write_tempo(&mut reply, bpm, source).ok();
+                        }
+                        Ok(Command::Glide(glide)) => c.shared.midi_mapper.lock(|midi_mapper| {
+                            match glide {
+                                Some((cv, portamento)) => {
+                                    midi_mapper.set_portamento(cv, portamento)
+                                }
+                                None => {}
+                            }
+                            let portamentos =
+                                [Cv::A, Cv::B, Cv::C, Cv::D].map(|cv| midi_mapper.portamento(cv));
+                            console::write_glides(&mut reply, portamentos).ok();
+                        }),
+                        Ok(Command::Lfo(lfo)) => c.shared.midi_mapper.lock(|midi_mapper| {
+                            let assigned = match lfo {
+                                Some((cv, settings)) => midi_mapper.set_lfo(cv, settings),
+                                None => Some(()),
+                            };
+                            match assigned {
+                                Some(_) => {}
+                                None => {
+                                    reply.push_str("port is in use by the config\r\n").ok();
+                                }
+                            }
+                            let lfos = [Cv::A, Cv::B, Cv::C, Cv::D].map(|cv| midi_mapper.lfo(cv));
+                            console::write_lfos(&mut reply, lfos).ok();
+                        }),
+                        Ok(Command::Envelope(envelope)) => {
+                            c.shared.midi_mapper.lock(|midi_mapper| {
+                                let assigned = match envelope {
+                                    Some((cv, settings)) => midi_mapper.set_envelope(cv, settings),
+                                    None => Some(()),
+                                };
+                                match assigned {
+                                    Some(_) => {}
+                                    None => {
+                                        reply.push_str("port is in use or follows itself\r\n").ok();
+                                    }
+                                }
+                                let envelopes =
+                                    [Cv::A, Cv::B, Cv::C, Cv::D].map(|cv| midi_mapper.envelope(cv));
+                                console::write_envelopes(&mut reply, envelopes).ok();
+                            })
+                        }
+                        Ok(Command::Bend(range)) => {
+                            let range = c.shared.midi_mapper.lock(|midi_mapper| {
+                                match range {
+                                    Some(range) => midi_mapper.set_bend_range(range),
+                                    None => {}
+                                }
+                                midi_mapper.bend_range()
+                            });
+                            console::write_bend_range(&mut reply, range).ok();
+                        }
+                        Ok(Command::Priority(priority)) => {
+                            c.shared.midi_mapper.lock(|midi_mapper| {
+                                match priority {
+                                    Some((ch, priority)) => {
+                                        midi_mapper.set_priority(ch, priority);
+                                    }
+                                    None => {}
+                                }
+                                console::write_priorities(&mut reply, midi_mapper.priorities())
+                                    .ok();
+                            })
+                        }
+                        Ok(Command::Voices(allocation)) => {
+                            c.shared.midi_mapper.lock(|midi_mapper| {
+                                match allocation {
+                                    Some((ch, allocation)) => {
+                                        midi_mapper.set_allocation(ch, allocation);
+                                    }
+                                    None => {}
+                                }
+                                console::write_allocations(&mut reply, midi_mapper.allocations())
+                                    .ok();
+                            })
+                        }
+                        Ok(Command::Unison(unison)) => c.shared.midi_mapper.lock(|midi_mapper| {
+                            match unison {
+                                Some((ch, unison)) => {
+                                    midi_mapper.set_unison(ch, unison);
+                                }
+                                None => {}
+                            }
+                            console::write_unisons(&mut reply, midi_mapper.unisons()).ok();
+                        }),
+                        Ok(Command::Detune(detune)) => c.shared.midi_mapper.lock(|midi_mapper| {
+                            match detune {
+                                Some((cv, cents)) => midi_mapper.set_detune(cv, cents),
+                                None => {}
+                            }
+                            let detunes =
+                                [Cv::A, Cv::B, Cv::C, Cv::D].map(|cv| midi_mapper.detune(cv));
+                            console::write_detunes(&mut reply, detunes).ok();
+                        }),
+                        Ok(Command::Drums(command)) => c.shared.midi_mapper.lock(|midi_mapper| {
+                            match command {
+                                DrumCommand::Show => {}
+                                DrumCommand::Preset(drum_map) => midi_mapper.set_drum_map(drum_map),
+                                DrumCommand::Map(note, gate) => {
+                                    let mut drum_map = midi_mapper.drum_map();
+                                    match drum_map.set(note, gate) {
+                                        Some(_) => midi_mapper.set_drum_map(drum_map),
+                                        None => {
+                                            reply.push_str("drum map is full\r\n").ok();
+                                        }
+                                    }
+                                }
+                            }
+                            console::write_drum_map(&mut reply, midi_mapper.drum_map()).ok();
+                        }),
+                        Ok(Command::Dynamics(command)) => {
+                            c.shared.midi_mapper.lock(|midi_mapper| {
+                                let mut dynamics = midi_mapper.dynamics();
+                                let assigned = match command {
+                                    DynamicsCommand::Show => Some(()),
+                                    DynamicsCommand::Accent(vel) => {
+                                        dynamics.accent = vel;
+                                        midi_mapper.set_dynamics(dynamics)
+                                    }
+                                    DynamicsCommand::Velocity(cv) => {
+                                        dynamics.velocity = cv;
+                                        midi_mapper.set_dynamics(dynamics)
+                                    }
+                                };
+                                match assigned {
+                                    Some(_) => {}
+                                    None => {
+                                        reply.push_str("port is in use by the config\r\n").ok();
+                                    }
+                                }
+                                console::write_dynamics(&mut reply, midi_mapper.dynamics()).ok();
+                            })
+                        }
+                        Ok(Command::Choke(choke)) => c.shared.midi_mapper.lock(|midi_mapper| {
+                            match choke {
+                                Some((group, gates)) => {
+                                    let mut chokes = midi_mapper.chokes();
+                                    chokes.groups[group as usize] = gates;
+                                    midi_mapper.set_chokes(chokes);
+                                }
+                                None => {}
+                            }
+                            console::write_chokes(&mut reply, midi_mapper.chokes()).ok();
+                        }),
+                        Ok(Command::Trigger(trigger)) => c.shared.midi_mapper.lock(|midi_mapper| {
+                            match trigger {
+                                Some((gate, ms)) => {
+                                    let mut triggers = midi_mapper.triggers();
+                                    match triggers.set(gate, ms) {
+                                        Some(_) => midi_mapper.set_triggers(triggers),
+                                        None => {
+                                            reply
+                                                .push_str("voice gates follow their notes\r\n")
+                                                .ok();
+                                        }
+                                    }
+                                }
+                                None => {}
+                            }
+                            console::write_triggers(&mut reply, midi_mapper.triggers()).ok();
+                        }),
+                        Ok(Command::Calibrate(command)) => {
+                            let calibrations = &mut *c.local.calibrations;
+                            let outputs = &mut *c.local.console_output_sender;
+                            match command {
+                                CalibrateCommand::Show => {}
+                                CalibrateCommand::Reference(cv, volts) => {
+                                    outputs
+                                        .try_send(OutputRequest::Reference(cv, Some(volts)))
+                                        .ok();
+                                }
+                                CalibrateCommand::Trim(cv, volts, trim) => {
+                                    calibrations[cv.index()].trims[volts as usize] = trim;
+                                    outputs
+                                        .try_send(OutputRequest::Calibrate(
+                                            cv,
+                                            calibrations[cv.index()],
+                                        ))
+                                        .ok();
+                                    outputs
+                                        .try_send(OutputRequest::Reference(cv, Some(volts)))
+                                        .ok();
+                                }
+                                CalibrateCommand::Done => {
+                                    for cv in [Cv::A, Cv::B, Cv::C, Cv::D] {
+                                        outputs.try_send(OutputRequest::Reference(cv, None)).ok();
+                                    }
+                                }
+                                CalibrateCommand::Save => {
+                                    let saved = c.shared.watchdog.lock(|watchdog| {
+                                        watchdog.start(fugit::ExtU32::micros(
+                                            FLASH_WATCHDOG_TIMEOUT_US,
+                                        ));
+                                        let saved = storage::save_calibration(
+                                            c.local.console_storage,
+                                            calibrations,
+                                        );
+                                        watchdog.start(fugit::ExtU32::micros(WATCHDOG_TIMEOUT_US));
+                                        saved
+                                    });
+                                    match saved {
+                                        Ok(_) => {}
+                                        Err(_) => {
+                                            reply.push_str("could not save\r\n").ok();
+                                        }
+                                    }
+                                }
+                            }
+                            console::write_calibration(&mut reply, calibrations).ok();
+                        }
+                        Ok(Command::Scale(scale)) => c.shared.midi_mapper.lock(|midi_mapper| {
+                            match scale {
+                                Some((cv, quantizer)) => midi_mapper.set_quantizer(cv, quantizer),
+                                None => {}
+                            }
+                            let quantizers =
+                                [Cv::A, Cv::B, Cv::C, Cv::D].map(|cv| midi_mapper.quantizer(cv));
+                            console::write_scales(&mut reply, quantizers).ok();
+                        }),
+                        Ok(Command::Song(command)) => c.shared.arranger.lock(|arranger| {
+                            let song = arranger.song();
+                            match command {
+                                SongCommand::Show => {}
+                                SongCommand::Enable(enabled) => arranger.set_enabled(enabled),
+                                SongCommand::Clear => arranger.clear(song),
+                                SongCommand::Append(entry) => match arranger.append(song, entry) {
+                                    Some(_) => {}
+                                    None => {
+                                        reply.push_str("song is full\r\n").ok();
+                                    }
+                                },
+                            }
+                            console::write_song(&mut reply, arranger).ok();
+                        }),
+                        Ok(Command::Overflows) => {
+                            console::write_overflows(&mut reply, &OVERFLOWS.counts()).ok();
+                        }
+                        Err(error) => {
+                            console::write_error(&mut reply, error).ok();
+                        }
+                    }
+                    reply.push_str("> ").ok();
+                    write_serial(&mut c.shared.usb_serial, reply.as_bytes()).await;
+                }
+                Err(_) => {}
+            }
+        }
+    }
+
+    #[task(local=[button_handler], shared=[led], binds=IO_IRQ_BANK0 )]
+    fn gpio_handler(c: gpio_handler::Context) {
+        c.local.button_handler.handle_irq()
+    }
+
+    #[task(local = [uart_input], shared=[led, &rec_switch, &perform_switch, uart, tempo_clock], binds=UART0_IRQ)]
+    fn uart(mut c: uart::Context) {
+        let mut bob = [0u8; 256];
+        let recording = c.shared.rec_switch.is_high().unwrap_or(false);
+        let performing = c.shared.perform_switch.is_high().unwrap_or(false);
+        let read = c.shared.uart.lock(|uart| {
+            if !uart.uart_is_readable() {
+                return Err(Error::WouldBlock);
+            }
+            // The error borrows bob, only whether there was one is kept
+            match uart.read_raw(&mut bob) {
+                Ok(bytes) => {
+                    if bytes > 0 {
+                        uart.write_raw(&bob).ok();
+                    }
+                    Ok(bytes)
+                }
+                Err(Error::WouldBlock) => Err(Error::WouldBlock),
+                Err(Error::Other(_)) => Err(Error::Other(())),
+            }
+        });
+        match read {
+            Ok(bytes) => {
+                let uart_input = &mut *c.local.uart_input;
+                c.shared.tempo_clock.lock(|clock| {
+                    uart_input.receive(&bob[..bytes], recording, performing, clock, now())
+                });
+            }
+            Err(Error::WouldBlock) => {}
+            Err(Error::Other(_)) => {}
+        };
+    }
+
+    #[task(priority = 4, shared = [watchdog])]
+    async fn watchdog_feeder(mut c: watchdog_feeder::Context) {
+        loop {
+            c.shared.watchdog.lock(|watchdog| watchdog.feed());
+            Mono::delay(1_000.micros()).await;
+        }
+    }
+}
@@ -0,0 +1,179 @@
+use midly::live::{LiveEvent, SystemCommon, SystemRealtime};
+use midly::MidiMessage;
+
+use master_core::clock::TempoClock;
+use master_core::commando_unit::{CommandEvent, Input};
+use master_core::player::{PlayerAction, PlayerMessage};
+use master_core::sink::Sink;
+use master_core::sysex::{Reading, SysExReader};
+use master_core::utils::midi_utils::event_length;
+
+use crate::channel_sink::ChannelSink;
+use crate::midi_master::{SysExMessage, SysExSender};
+
+// Where a SysEx request came from, so that the reply goes back the same way
+#[derive(Copy, Clone, PartialEq)]
+pub enum MidiPort {
+    Din,
+    Usb,
+}
+
+// Everything that comes in over one port ends up here. Each port has its own
+// SysEx reader so that a dump on one is not broken up by traffic on the other.
+pub struct MidiInput {
+    port: MidiPort,
+    sysex_reader: SysExReader,
+    midi_sender: ChannelSink<LiveEvent<'static>>,
+    player_sender: ChannelSink<PlayerMessage>,
+    command_sender: ChannelSink<CommandEvent>,
+    sysex_sender: SysExSender,
+}
+
+impl MidiInput {
+    pub fn new(
+        port: MidiPort,
+        midi_sender: ChannelSink<LiveEvent<'static>>,
+        player_sender: ChannelSink<PlayerMessage>,
+        command_sender: ChannelSink<CommandEvent>,
+        sysex_sender: SysExSender,
+    ) -> Self {
+        MidiInput {
+            port,
+            sysex_reader: SysExReader::new(),
+            midi_sender,
+            player_sender,
+            command_sender,
+            sysex_sender,
+        }
+    }
+
+    // Clock in it makes the internal one step aside
+    pub fn receive(
+        &mut self,
+        bytes: &[u8],
+        recording: bool,
+        performing: bool,
+        clock: &mut TempoClock,
+        now: u64,
+    ) {
+        // SysEx can be spread over several reads so it is picked out first
+        let mut rest = [0u8; 256];
+        let mut rest_length = 0;
+        for byte in bytes.iter() {
+            match self.sysex_reader.push(*byte) {
+                Reading::NotMine => {
+                    if rest_length < rest.len() {
+                        rest[rest_length] = *byte;
+                        rest_length += 1;
+                    }
+                }
+                Reading::Busy => {}
+                Reading::Done => {
+                    match SysExMessage::from_slice(self.sysex_reader.message()) {
+                        Ok(msg) => self.sysex_sender.try_send((self.port, msg)).ok(),
+                        Err(_) => None,
+                    };
+                }
+            }
+        }
+        let mut bytes_consumed = 0;
+        while bytes_consumed < rest_length {
+            match LiveEvent::parse(&rest[bytes_consumed..rest_length]) {
+                Ok(event) => {
+                    bytes_consumed += event_length(event);
+                    match event {
+                        // What the internal clock was driving has to stop first
+                        LiveEvent::Realtime(msg) if player_action(msg).is_some() => {
+                            match clock.external(msg, now) {
+                                Some(stop) => {
+                                    self.handle_event(
+                                        LiveEvent::Realtime(stop),
+                                        recording,
+                                        performing,
+                                    );
+                                }
+                                None => {}
+                            }
+                        }
+                        _ => {}
+                    }
+                    self.handle_event(event, recording, performing);
+                }
+                Err(_) => {
+                    return;
+                }
+            }
+        }
+    }
+
+    fn handle_event(&mut self, event: LiveEvent, recording: bool, performing: bool) {
+        if !recording && !performing {
+            self.midi_sender.try_send(event.to_static()).ok();
+        }
+        match event {
+            LiveEvent::Midi { message, .. } if recording || performing => match message {
+                MidiMessage::NoteOn { key, .. } => {
+                    self.command_sender
+                        .try_send(CommandEvent::Down(Input::MidiKey(key.into())))
+                        .ok();
+                }
+                MidiMessage::NoteOff { key, .. } => {
+                    self.command_sender
+                        .try_send(CommandEvent::Up(Input::MidiKey(key.into())))
+                        .ok();
+                }
+                _ => {}
+            },
+            // Program change picks the pattern slot of the player on that channel
+            LiveEvent::Midi {
+                channel,
+                message: MidiMessage::ProgramChange { program },
+            } => {
+                self.player_sender
+                    .try_send(PlayerMessage::Action(
+                        channel.as_int(),
+                        PlayerAction::SelectSlot(program.as_int()),
+                    ))
+                    .ok();
+            }
+            // The arranger looks for these among the player messages
+            LiveEvent::Common(msg) if !recording => {
+                let action = match msg {
+                    SystemCommon::SongPosition(position) => {
+                        Some(PlayerAction::SongPosition(position.as_int()))
+                    }
+                    SystemCommon::SongSelect(song) => Some(PlayerAction::SongSelect(song.as_int())),
+                    _ => None,
+                };
+                match action {
+                    Some(action) => {
+                        self.player_sender
+                            .try_send(PlayerMessage::Broadcast(action))
+                            .ok();
+                    }
+                    None => {}
+                }
+            }
+            LiveEvent::Realtime(msg) if !recording => match player_action(msg) {
+                Some(action) => {
+                    self.player_sender
+                        .try_send(PlayerMessage::Broadcast(action))
+                        .ok();
+                }
+                None => {}
+            },
+            _ => {}
+        }
+    }
+}
+
+// What the players make of clock, wherever it comes from
+pub fn player_action(msg: SystemRealtime) -> Option<PlayerAction> {
+    match msg {
+        SystemRealtime::TimingClock => Some(PlayerAction::Tick),
+        SystemRealtime::Start => Some(PlayerAction::Play),
+        SystemRealtime::Continue => Some(PlayerAction::Continue),
+        SystemRealtime::Stop => Some(PlayerAction::Stop),
+        _ => None,
+    }
+}
@@ -0,0 +1,399 @@
+use embedded_hal::digital::v2::PinState as V2PinState;
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+use fugit::{Duration, Instant};
+use rp_pico::hal::gpio;
+use rp_pico::hal::gpio::bank0::*;
+use rp_pico::hal::gpio::PinState;
+use rp_pico::hal::gpio::Pins;
+use rtic_monotonics::rp2040::prelude::*;
+
+use master_core::envelope::{Envelope, EnvelopeSettings};
+use master_core::glide::{Glide, Slew};
+use master_core::lfo::{Lfo, LfoSettings};
+use master_core::outs::{
+    cents_to_voltage, note_to_voltage, val_to_voltage, Calibration, Cv, Gate, OutputRequest,
+};
+
+use crate::pwm_pair::{CvPair, SliceAB, SliceCD};
+use crate::Mono;
+
+pub struct GateMappings {
+    pub kick: gpio::Pin<Gpio27, gpio::FunctionSioOutput, gpio::PullDown>,
+    pub open_hh: gpio::Pin<Gpio7, gpio::FunctionSioOutput, gpio::PullDown>,
+    pub clap: gpio::Pin<Gpio9, gpio::FunctionSioOutput, gpio::PullDown>,
+    pub snare: gpio::Pin<Gpio10, gpio::FunctionSioOutput, gpio::PullDown>,
+    pub fx: gpio::Pin<Gpio6, gpio::FunctionSioOutput, gpio::PullDown>,
+    pub closed_hh: gpio::Pin<Gpio8, gpio::FunctionSioOutput, gpio::PullDown>,
+
+    pub accent: gpio::Pin<Gpio5, gpio::FunctionSioOutput, gpio::PullDown>,
+
+    pub start: gpio::Pin<Gpio26, gpio::FunctionSioOutput, gpio::PullDown>,
+    pub stop: gpio::Pin<Gpio18, gpio::FunctionSioOutput, gpio::PullDown>,
+    pub clock: gpio::Pin<Gpio28, gpio::FunctionSioOutput, gpio::PullDown>,
+
+    pub gate_a: gpio::Pin<Gpio22, gpio::FunctionSioOutput, gpio::PullDown>,
+    pub gate_b: gpio::Pin<Gpio21, gpio::FunctionSioOutput, gpio::PullDown>,
+    pub gate_c: gpio::Pin<Gpio20, gpio::FunctionSioOutput, gpio::PullDown>,
+    pub gate_d: gpio::Pin<Gpio19, gpio::FunctionSioOutput, gpio::PullDown>,
+}
+
+impl GateMappings {
+    pub fn reset_drums(&mut self) {
+        self.open_hh.set_low().unwrap();
+        self.clap.set_low().unwrap();
+        self.snare.set_low().unwrap();
+        self.kick.set_low().unwrap();
+        self.fx.set_low().unwrap();
+        self.accent.set_low().unwrap();
+        self.closed_hh.set_low().unwrap();
+    }
+
+    pub fn reset_gates(&mut self) {
+        self.gate_a.set_low().unwrap();
+        self.gate_b.set_low().unwrap();
+        self.gate_c.set_low().unwrap();
+        self.gate_d.set_low().unwrap();
+    }
+
+    pub fn reset_bus(&mut self) {
+        self.start.set_low().unwrap();
+        self.stop.set_low().unwrap();
+        self.clock.set_low().unwrap();
+    }
+
+    pub fn reset_all(&mut self) {
+        self.reset_drums();
+        self.reset_gates();
+        self.reset_bus();
+    }
+
+    pub(crate) fn set_state(&mut self, gate: Gate, state: bool) -> Option<()> {
+        match gate {
+            Gate::Kick => self.kick.set_state(V2PinState::from(state)).ok(),
+            Gate::OpenHH => self.open_hh.set_state(V2PinState::from(state)).ok(),
+            Gate::Clap => self.clap.set_state(V2PinState::from(state)).ok(),
+            Gate::Snare => self.snare.set_state(V2PinState::from(state)).ok(),
+            Gate::FX => self.fx.set_state(V2PinState::from(state)).ok(),
+            Gate::ClosedHH => self.closed_hh.set_state(V2PinState::from(state)).ok(),
+            Gate::Accent => self.accent.set_state(V2PinState::from(state)).ok(),
+            Gate::Start => self.start.set_state(V2PinState::from(state)).ok(),
+            Gate::Stop => self.stop.set_state(V2PinState::from(state)).ok(),
+            Gate::Clock => self.clock.set_state(V2PinState::from(state)).ok(),
+            Gate::GateA => self.gate_a.set_state(V2PinState::from(state)).ok(),
+            Gate::GateB => self.gate_b.set_state(V2PinState::from(state)).ok(),
+            Gate::GateC => self.gate_c.set_state(V2PinState::from(state)).ok(),
+            Gate::GateD => self.gate_d.set_state(V2PinState::from(state)).ok(),
+        }
+    }
+}
+
+pub struct CvPorts {
+    pub ab_pair: CvPair<SliceAB>,
+    pub cd_pair: CvPair<SliceCD>,
+    calibrations: [Calibration; 4],
+    references: [Option<u8>; 4], // Ports held at a calibration point
+    // Volts, on the way there while gliding. None while a port puts out values.
+    notes: [Option<f32>; 4],
+    slews: [Option<Slew>; 4],
+    bends: [f32; 4],   // Volts
+    detunes: [f32; 4], // Volts
+    lfos: [Option<Lfo>; 4],
+    envelopes: [Option<Envelope>; 4],
+}
+
+impl CvPorts {
+    pub fn new(
+        ab_pair: CvPair<SliceAB>,
+        cd_pair: CvPair<SliceCD>,
+        calibrations: [Calibration; 4],
+    ) -> Self {
+        CvPorts {
+            ab_pair,
+            cd_pair,
+            calibrations,
+            references: [None; 4],
+            notes: [None; 4],
+            slews: [None; 4],
+            bends: [0.0; 4],
+            detunes: [0.0; 4],
+            lfos: [None; 4],
+            envelopes: [None; 4],
+        }
+    }
+
+    fn reset(&mut self) {
+        self.ab_pair.set_a(0.0);
+        self.ab_pair.set_b(0.0);
+        self.cd_pair.set_a(0.0);
+        self.cd_pair.set_b(0.0);
+    }
+
+    fn set_output(&mut self, cv: Cv, voltage: f32) -> Option<()> {
+        match cv {
+            Cv::A => self.ab_pair.set_a(voltage),
+            Cv::B => self.ab_pair.set_b(voltage),
+            Cv::C => self.cd_pair.set_b(voltage),
+            Cv::D => self.cd_pair.set_a(voltage),
+        }
+    }
+
+    fn set_note(&mut self, cv: Cv, note: u8) -> Option<()> {
+        self.notes[cv.index()] = Some(note_to_voltage(note));
+        self.slews[cv.index()] = None;
+        self.set_pitch(cv)
+    }
+
+    // Starts from wherever the port is, a port that had no note just jumps
+    fn glide_note(&mut self, cv: Cv, note: u8, glide: Glide) -> Option<()> {
+        let to = note_to_voltage(note);
+        match self.notes[cv.index()] {
+            Some(from) => {
+                self.slews[cv.index()] = Some(Slew::new(from, to, Mono::now().ticks(), glide));
+                Some(())
+            }
+            None => self.set_note(cv, note),
+        }
+    }
+
+    fn set_detune(&mut self, cv: Cv, cents: i8) -> Option<()> {
+        self.detunes[cv.index()] = cents_to_voltage(cents);
+        self.set_pitch(cv)
+    }
+
+    fn set_lfo(&mut self, cv: Cv, settings: Option<LfoSettings>) -> Option<()> {
+        let seed = Mono::now().ticks() as u32 ^ cv.index() as u32;
+        self.lfos[cv.index()] = settings.map(|settings| Lfo::new(settings, seed));
+        self.envelopes[cv.index()] = None;
+        self.notes[cv.index()] = None;
+        self.slews[cv.index()] = None;
+        match settings {
+            Some(_) => Some(()),
+            None => self.set_val(cv, 0.0),
+        }
+    }
+
+    // New times and levels apply to an envelope that is already going
+    fn set_envelope(&mut self, cv: Cv, settings: Option<EnvelopeSettings>) -> Option<()> {
+        match (settings, self.envelopes[cv.index()].as_mut()) {
+            (Some(settings), Some(envelope)) => envelope.set_adsr(settings.adsr),
+            (Some(settings), None) => {
+                self.envelopes[cv.index()] = Some(Envelope::new(settings.adsr));
+                self.lfos[cv.index()] = None;
+                self.notes[cv.index()] = None;
+                self.slews[cv.index()] = None;
+            }
+            (None, _) => {
+                self.envelopes[cv.index()] = None;
+                return self.set_val(cv, 0.0);
+            }
+        }
+        Some(())
+    }
+
+    fn trigger_envelope(&mut self, cv: Cv, open: bool) -> Option<()> {
+        let envelope = self.envelopes[cv.index()].as_mut()?;
+        envelope.gate(open, Mono::now().ticks());
+        Some(())
+    }
+
+    fn lfo_clock(&mut self, ticks: u32) -> Option<()> {
+        let now = Mono::now().ticks();
+        for lfo in self.lfos.iter_mut().flatten() {
+            lfo.clock(ticks, now);
+        }
+        Some(())
+    }
+
+    // Moves glides, LFOs and envelopes along, true while any of them is running
+    fn update(&mut self) -> bool {
+        let now = Mono::now().ticks();
+        let mut running = false;
+        for cv in [Cv::A, Cv::B, Cv::C, Cv::D] {
+            match self.slews[cv.index()] {
+                Some(slew) => {
+                    self.notes[cv.index()] = Some(slew.at(now));
+                    if slew.is_done(now) {
+                        self.slews[cv.index()] = None;
+                    } else {
+                        running = true;
+                    }
+                    self.set_pitch(cv);
+                }
+                None => {}
+            }
+            match self.lfos[cv.index()].as_mut() {
+                Some(lfo) => {
+                    lfo.advance(now);
+                    let value = lfo.value();
+                    if self.references[cv.index()].is_none() {
+                        self.set_output(cv, val_to_voltage(value));
+                    }
+                    running = true;
+                }
+                None => {}
+            }
+            match self.envelopes[cv.index()].as_mut() {
+                Some(envelope) => {
+                    envelope.advance(now);
+                    let value = envelope.value();
+                    running |= !envelope.is_idle();
+                    if self.references[cv.index()].is_none() {
+                        self.set_output(cv, val_to_voltage(value));
+                    }
+                }
+                None => {}
+            }
+        }
+        return running;
+    }
+
+    fn set_bend(&mut self, cv: Cv, volts: f32) -> Option<()> {
+        self.bends[cv.index()] = volts;
+        self.set_pitch(cv)
+    }
+
+    // Bend and detune are added before calibrating since they are part of the
+    // pitch
+    fn set_pitch(&mut self, cv: Cv) -> Option<()> {
+        let i = cv.index();
+        match (self.notes[i], self.references[i]) {
+            (Some(note), None) => {
+                let voltage = self.calibrations[i].apply(note + self.bends[i] + self.detunes[i]);
+                self.set_output(cv, voltage)
+            }
+            _ => None,
+        }
+    }
+
+    fn set_val(&mut self, cv: Cv, val: f32) -> Option<()> {
+        self.notes[cv.index()] = None;
+        self.slews[cv.index()] = None;
+        if self.references[cv.index()].is_some() {
+            return None;
+        }
+        self.set_output(cv, val_to_voltage(val))
+    }
+
+    fn calibrate(&mut self, cv: Cv, calibration: Calibration) -> Option<()> {
+        self.calibrations[cv.index()] = calibration;
+        self.reference(cv, self.references[cv.index()])
+    }
+
+    // Goes through the calibration, so what comes out should measure the
+    // whole volt once the trim is right
+    fn reference(&mut self, cv: Cv, point: Option<u8>) -> Option<()> {
+        self.references[cv.index()] = point;
+        match point {
+            Some(volts) => {
+                let voltage = self.calibrations[cv.index()].apply(volts as f32);
+                self.set_output(cv, voltage)
+            }
+            None => self.set_pitch(cv),
+        }
+    }
+}
+
+type Time = Instant<u64, 1, 1_000_000>;
+const TIMERS: usize = 8;
+const UPDATE_STEP: u64 = 1;
+
+pub struct OutputHandler {
+    gates: GateMappings,
+    ports: CvPorts,
+    flashes: [Option<(Gate, Time, Duration<u64, 1, 1000000>)>; TIMERS],
+    flash_time: Duration<u64, 1, 1000000>,
+}
+
+impl OutputHandler {
+    pub fn new(gates: GateMappings, cv_ports: CvPorts) -> Self {
+        return Self {
+            gates,
+            ports: cv_ports,
+            flashes: [None; TIMERS],
+            flash_time: 20.millis(),
+        };
+    }
+
+    pub fn reset(&mut self) {
+        self.gates.reset_all();
+        self.ports.reset();
+    }
+
+    // Waits until the first flash or trigger is due to close
+    pub fn check_flashes(&mut self) -> Option<Duration<u64, 1, 1000000>> {
+        let mut shortest_wait = None;
+        for i in 0..TIMERS {
+            match self.flashes[i] {
+                Some((gate, start, length)) => {
+                    let age = Mono::now() - start;
+                    if age >= length {
+                        self.gates.set_state(gate, false);
+                        self.flashes[i] = None
+                    } else {
+                        let left = length - age;
+                        if shortest_wait == None || Some(left) < shortest_wait {
+                            shortest_wait = Some(left);
+                        }
+                    }
+                }
+                None => {}
+            }
+        }
+        return shortest_wait;
+    }
+
+    // Asks to be called again in UPDATE_STEP while something is gliding or
+    // an LFO is running
+    pub fn update_ports(&mut self) -> Option<Duration<u64, 1, 1000000>> {
+        match self.ports.update() {
+            true => Some(UPDATE_STEP.millis()),
+            false => None,
+        }
+    }
+
+    // Not super efficient, same gate can appear multiple times
+    // Is fine. Probably.
+    fn add_flash(&mut self, gate: Gate, length: Duration<u64, 1, 1000000>) {
+        for i in 0..TIMERS {
+            match self.flashes[i] {
+                Some((g, _, _)) if g == gate => {
+                    self.gates.set_state(gate, true);
+                    self.flashes[i] = Some((gate, Mono::now(), length));
+                    return;
+                }
+                Some(_) => {}
+                None => {
+                    self.gates.set_state(gate, true);
+                    self.flashes[i] = Some((gate, Mono::now(), length));
+                    return;
+                }
+            }
+        }
+    }
+
+    pub fn handle_message(&mut self, message: OutputRequest) -> Option<()> {
+        match message {
+            OutputRequest::GateOn(gate) => self.gates.set_state(gate, true),
+            OutputRequest::GateOff(gate) => self.gates.set_state(gate, false),
+            OutputRequest::SetNote(port, note) => self.ports.set_note(port, note),
+            OutputRequest::GlideNote(port, note, glide) => self.ports.glide_note(port, note, glide),
+            OutputRequest::SetVal(port, val) => self.ports.set_val(port, val),
+            OutputRequest::SetBend(port, volts) => self.ports.set_bend(port, volts),
+            OutputRequest::SetDetune(port, cents) => self.ports.set_detune(port, cents),
+            OutputRequest::SetLfo(port, settings) => self.ports.set_lfo(port, settings),
+            OutputRequest::LfoClock(ticks) => self.ports.lfo_clock(ticks),
+            OutputRequest::SetEnvelope(port, settings) => self.ports.set_envelope(port, settings),
+            OutputRequest::TriggerEnvelope(port, open) => self.ports.trigger_envelope(port, open),
+            OutputRequest::Calibrate(port, calibration) => self.ports.calibrate(port, calibration),
+            OutputRequest::Reference(port, point) => self.ports.reference(port, point),
+            OutputRequest::Flash(gate) => {
+                self.add_flash(gate, self.flash_time);
+                None
+            }
+            OutputRequest::Trigger(gate, ms) => {
+                self.add_flash(gate, (ms as u64).millis());
+                None
+            }
+        }
+    }
+}
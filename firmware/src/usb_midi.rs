@@ -0,0 +1,131 @@
+use usb_device::class_prelude::*;
+use usb_device::Result;
+
+/*
+A USB MIDI 1.0 device with one cable in each direction. The descriptors
+follow appendix B of the class spec: an empty audio control interface, a
+MIDI streaming interface with an embedded and an external jack per
+direction, and a bulk endpoint per direction.
+*/
+
+const AUDIO: u8 = 0x01;
+const AUDIO_CONTROL: u8 = 0x01;
+const MIDI_STREAMING: u8 = 0x03;
+
+const CS_INTERFACE: u8 = 0x24;
+const CS_ENDPOINT: u8 = 0x25;
+const HEADER: u8 = 0x01;
+const MS_GENERAL: u8 = 0x01;
+const MIDI_IN_JACK: u8 = 0x02;
+const MIDI_OUT_JACK: u8 = 0x03;
+const EMBEDDED: u8 = 0x01;
+const EXTERNAL: u8 = 0x02;
+
+const IN_EMBEDDED_ID: u8 = 1;
+const IN_EXTERNAL_ID: u8 = 2;
+const OUT_EMBEDDED_ID: u8 = 3;
+const OUT_EXTERNAL_ID: u8 = 4;
+
+// Class specific MIDI streaming descriptors: header, four jacks and two
+// endpoints with their class specific parts
+const MS_TOTAL_LENGTH: u16 = 7 + 6 + 6 + 9 + 9 + 9 + 5 + 9 + 5;
+
+pub const MAX_PACKET_SIZE: u16 = 64;
+
+pub struct MidiClass<'a, B: UsbBus> {
+    audio_if: InterfaceNumber,
+    midi_if: InterfaceNumber,
+    read_ep: EndpointOut<'a, B>,
+    write_ep: EndpointIn<'a, B>,
+}
+
+impl<'a, B: UsbBus> MidiClass<'a, B> {
+    pub fn new(alloc: &'a UsbBusAllocator<B>) -> Self {
+        MidiClass {
+            audio_if: alloc.interface(),
+            midi_if: alloc.interface(),
+            read_ep: alloc.bulk(MAX_PACKET_SIZE),
+            write_ep: alloc.bulk(MAX_PACKET_SIZE),
+        }
+    }
+
+    // Whole packets only, buf should hold MAX_PACKET_SIZE bytes
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.read_ep.read(buf)
+    }
+
+    // At most MAX_PACKET_SIZE bytes at a time, WouldBlock until the host has
+    // picked up the last ones
+    pub fn write(&mut self, packets: &[u8]) -> Result<usize> {
+        self.write_ep.write(packets)
+    }
+}
+
+impl<B: UsbBus> UsbClass<B> for MidiClass<'_, B> {
+    fn get_configuration_descriptors(&self, writer: &mut DescriptorWriter) -> Result<()> {
+        writer.iad(self.audio_if, 2, AUDIO, AUDIO_CONTROL, 0x00, None)?;
+
+        writer.interface(self.audio_if, AUDIO, AUDIO_CONTROL, 0x00)?;
+        writer.write(
+            CS_INTERFACE,
+            &[
+                HEADER,
+                0x00,
+                0x01, // Revision 1.0
+                0x09,
+                0x00, // Total length, just this descriptor
+                0x01, // One streaming interface
+                self.midi_if.into(),
+            ],
+        )?;
+
+        writer.interface(self.midi_if, AUDIO, MIDI_STREAMING, 0x00)?;
+        let [total_lo, total_hi] = MS_TOTAL_LENGTH.to_le_bytes();
+        writer.write(CS_INTERFACE, &[HEADER, 0x00, 0x01, total_lo, total_hi])?;
+        writer.write(
+            CS_INTERFACE,
+            &[MIDI_IN_JACK, EMBEDDED, IN_EMBEDDED_ID, 0x00],
+        )?;
+        writer.write(
+            CS_INTERFACE,
+            &[MIDI_IN_JACK, EXTERNAL, IN_EXTERNAL_ID, 0x00],
+        )?;
+        writer.write(
+            CS_INTERFACE,
+            &[
+                MIDI_OUT_JACK,
+                EMBEDDED,
+                OUT_EMBEDDED_ID,
+                0x01, // One input pin
+                IN_EXTERNAL_ID,
+                0x01,
+                0x00,
+            ],
+        )?;
+        writer.write(
+            CS_INTERFACE,
+            &[
+                MIDI_OUT_JACK,
+                EXTERNAL,
+                OUT_EXTERNAL_ID,
+                0x01,
+                IN_EMBEDDED_ID,
+                0x01,
+                0x00,
+            ],
+        )?;
+
+        // Audio class endpoints carry two extra bytes, refresh and sync address
+        writer.endpoint_ex(&self.read_ep, |buf| {
+            buf[..2].copy_from_slice(&[0x00, 0x00]);
+            Ok(2)
+        })?;
+        writer.write(CS_ENDPOINT, &[MS_GENERAL, 0x01, IN_EMBEDDED_ID])?;
+        writer.endpoint_ex(&self.write_ep, |buf| {
+            buf[..2].copy_from_slice(&[0x00, 0x00]);
+            Ok(2)
+        })?;
+        writer.write(CS_ENDPOINT, &[MS_GENERAL, 0x01, OUT_EMBEDDED_ID])?;
+        Ok(())
+    }
+}
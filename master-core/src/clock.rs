@@ -0,0 +1,145 @@
+use midly::live::SystemRealtime;
+
+/*
+Generates MIDI clock when nobody else does. Time is whatever the caller counts
+in microseconds. The clock gives way as soon as external clock shows up,
+stopping whatever it was driving, and can be started again once that has been
+quiet for EXTERNAL_TIMEOUT. If it was running when it gave way it starts again
+on its own then, unless the external clock said Stop before going quiet.
+*/
+
+pub const PPQ: u64 = 24;
+pub const DEFAULT_BPM: u16 = 120;
+pub const MIN_BPM: u16 = 20;
+pub const MAX_BPM: u16 = 300;
+// Longer than the time between two ticks at MIN_BPM
+pub const EXTERNAL_TIMEOUT: u64 = 500_000;
+
+const MICROS_PER_MINUTE: u64 = 60_000_000;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Source {
+    Internal,
+    External,
+}
+
+pub struct TempoClock {
+    bpm: u16,
+    running: bool,
+    // Ticks are counted from a fixed point so that rounding does not add up
+    since: u64,
+    ticks: u64,
+    last_external: Option<u64>,
+    // Start or stop waiting to be handed out by poll
+    pending: Option<SystemRealtime>,
+    // Was running when external clock took over
+    resume: bool,
+}
+
+impl TempoClock {
+    pub fn new() -> Self {
+        TempoClock {
+            bpm: DEFAULT_BPM,
+            running: false,
+            since: 0,
+            ticks: 0,
+            last_external: None,
+            pending: None,
+            resume: false,
+        }
+    }
+
+    pub fn bpm(&self) -> u16 {
+        self.bpm
+    }
+
+    pub fn set_bpm(&mut self, bpm: u16, now: u64) -> Option<()> {
+        if !(MIN_BPM..=MAX_BPM).contains(&bpm) {
+            return None;
+        }
+        if self.running {
+            self.since = self.next_tick().max(now);
+            self.ticks = 0;
+        }
+        self.bpm = bpm;
+        return Some(());
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    pub fn source(&self, now: u64) -> Source {
+        match self.last_external {
+            Some(time) if now.wrapping_sub(time) < EXTERNAL_TIMEOUT => Source::External,
+            _ => Source::Internal,
+        }
+    }
+
+    // Call for every clock, start, continue or stop that comes in from outside.
+    // Gives a Stop to send ahead of it when this takes over from the internal
+    // clock while it runs.
+    pub fn external(&mut self, msg: SystemRealtime, now: u64) -> Option<SystemRealtime> {
+        self.last_external = Some(now);
+        self.pending = None;
+        let took_over = self.running;
+        self.running = false;
+        self.resume = (self.resume || took_over) && msg != SystemRealtime::Stop;
+        if took_over {
+            return Some(SystemRealtime::Stop);
+        }
+        return None;
+    }
+
+    // False while external clock is around
+    pub fn start(&mut self, now: u64) -> bool {
+        if self.running || self.source(now) == Source::External {
+            return false;
+        }
+        self.running = true;
+        self.since = now;
+        self.ticks = 0;
+        self.pending = Some(SystemRealtime::Start);
+        self.resume = false;
+        return true;
+    }
+
+    pub fn stop(&mut self) -> bool {
+        self.resume = false;
+        if !self.running {
+            return false;
+        }
+        self.running = false;
+        self.pending = Some(SystemRealtime::Stop);
+        return true;
+    }
+
+    // When the next tick is due
+    pub fn next_tick(&self) -> u64 {
+        self.since + self.ticks * MICROS_PER_MINUTE / (self.bpm as u64 * PPQ)
+    }
+
+    // Gives one message per call, call again right away until it says None
+    pub fn poll(&mut self, now: u64) -> Option<SystemRealtime> {
+        if self.pending.is_some() {
+            return self.pending.take();
+        }
+        if self.resume && self.source(now) == Source::Internal {
+            self.resume = false;
+            self.running = true;
+            self.since = now;
+            self.ticks = 0;
+            return Some(SystemRealtime::Start);
+        }
+        if !self.running || now < self.next_tick() {
+            return None;
+        }
+        // Start over rather than catch up with a burst of ticks
+        if now - self.next_tick() > MICROS_PER_MINUTE / (self.bpm as u64 * PPQ) {
+            self.since = now;
+            self.ticks = 0;
+        }
+        self.ticks += 1;
+        return Some(SystemRealtime::TimingClock);
+    }
+}
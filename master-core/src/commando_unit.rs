@@ -13,6 +13,8 @@ pub enum Operation {
     Commit,
     Abort,
     Perform(u8, PlayerAction),
+    Save,
+    Load,
 }
 use Operation::*;
 
@@ -206,10 +208,12 @@ impl CommandoUnit {
                     },
                     Step => match j {
                         Rec => Done(Back),
+                        Play => Done(Load),
                         _ => Invalid,
                     },
                     Rec => match j {
                         Step => Done(ClearPattern),
+                        Play => Done(Save),
                         _ => Invalid,
                     },
                     _ => Invalid,
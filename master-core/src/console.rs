@@ -0,0 +1,991 @@
+use core::fmt::{self, Write};
+
+use heapless::Vec;
+
+use crate::clock::{Source, MAX_BPM, MIN_BPM};
+use crate::drums::{
+    can_trigger, Chokes, DrumMap, Dynamics, GateSet, Triggers, CHOKE_GROUPS, MAX_TRIGGER_MS,
+};
+use crate::envelope::{Adsr, EnvelopeSettings, MAX_STAGE_MS, MAX_SUSTAIN};
+use crate::glide::{Glide, GlideMode, Portamento, MAX_GLIDE_MS};
+use crate::lfo::{LfoSettings, Rate, Shape, MAX_CENTIHERTZ, MIN_CENTIHERTZ};
+use crate::midi_mapper::{Allocation, Assign, Config, Steal, MAX_BEND_RANGE, PITCH_CHANNELS};
+use crate::outs::{Calibration, Cv, Gate, CALIBRATION_POINTS, MAX_DETUNE_CENTS, MAX_TRIM};
+use crate::player::{
+    is_divisor, Player, PlayerAction, PlayerMessage, ALWAYS, EVENT_SIZE, MAX_LENGTH, MAX_RATCHET,
+    MAX_SWING, PATTERN_SLOTS, STEP_CAP, STRAIGHT,
+};
+use crate::priority::{Order, Priority};
+use crate::quantizer::{Quantizer, Scale};
+use crate::sink::{MidiSink, OutputSink};
+use crate::song::{Arranger, Entry, PLAYERS, SONGS};
+
+pub const LINE_SIZE: usize = 64;
+const NEWLINE: &str = "\r\n";
+// Header plus a line per event and ratchet, enough for the longest pattern
+pub const MAX_REPLY_SIZE: usize = 64 + MAX_LENGTH * STEP_CAP * 23 + MAX_LENGTH * 15;
+
+// One command per line, channels are 0 to 15. Actions without a channel go to
+// every player.
+const HELP: &str = "help
+players
+dump <ch>
+config <four_poly|four_indie|two_poly|two_mono|two_fancy_mono|one_duo|one_mono>
+play | stop
+mute | hold | restart | snap | clear | save | load [ch]
+  save and load cover every slot, saves from before slots load into slot 0
+length <ch> <n>
+divisor <ch> <n dividing 96>
+swing [ch] <50-75|default>
+chance <ch> <step> <key> <0-100>
+ratchet <ch> <step> <1-8>
+slot [ch] <0-3>
+song [on | off | clear | select <n>]
+song add <repeats> <slot for ch 0> .. <slot for ch 4>
+tempo [bpm]
+bend [range in semitones]
+priority [<ch 0-3> <last|lowest|highest> [retrigger]]
+voices [<ch 0-3> <roundrobin|reuse> <oldest|quietest>]
+unison [<ch 0-3> <on|off>]
+detune [<a-d> <cents -100 to 100>]
+drums [octave | gm]
+drums <note 0-127> <kick|snare|clap|chh|ohh|fx|accent|start|stop|clock|a|b|c|d|off>
+accent [<velocity 1-127> | off]
+drumvel [<a-d> | off]
+choke [<group 0-3> <gates like chh ohh> | <group 0-3> off]
+trigger [<drum gate> <ms 1-250> | <drum gate> off]
+env [<a-d> off]
+env <a-d> <gate from a-d> <attack ms> <decay ms> <sustain 0-100> <release ms>
+lfo [<a-d> off]
+lfo <a-d> <sine|triangle|saw|square|random> <hz like 0.5 | note length like 1/4>
+glide [<a-d> off]
+glide <a-d> <time <ms> | rate <ms per octave>> [legato]
+calibrate [done | save]
+calibrate <a-d> <volts 0-5> [trim in mV]
+scale [<a-d> <off|major|minor|dorian|phrygian|lydian|mixolydian|locrian|majpent|minpent> [root]]
+scale <a-d> <12 digits of 0 or 1, root first> [root]
+overflows";
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Command {
+    Help,
+    Players,
+    Dump(u8),
+    Config(Config),
+    Player(PlayerMessage),
+    Tempo(Option<u16>),
+    Bend(Option<u8>),
+    Priority(Option<(u8, Priority)>),
+    Voices(Option<(u8, Allocation)>),
+    Unison(Option<(u8, bool)>),
+    Detune(Option<(Cv, i8)>),
+    Drums(DrumCommand),
+    Dynamics(DynamicsCommand),
+    Choke(Option<(u8, GateSet)>),
+    Trigger(Option<(Gate, Option<u8>)>),
+    Lfo(Option<(Cv, Option<LfoSettings>)>),
+    Envelope(Option<(Cv, Option<EnvelopeSettings>)>),
+    Glide(Option<(Cv, Portamento)>),
+    Scale(Option<(Cv, Quantizer)>),
+    Calibrate(CalibrateCommand),
+    Song(SongCommand),
+    Overflows,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum SongCommand {
+    Show,
+    Enable(bool),
+    Append(Entry),
+    Clear,
+}
+
+// While a port is held at a reference voltage notes do not reach it
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum CalibrateCommand {
+    Show,
+    Reference(Cv, u8),
+    Trim(Cv, u8, i16),
+    Done,
+    Save,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum DrumCommand {
+    Show,
+    Preset(DrumMap),
+    Map(u8, Option<Gate>),
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum DynamicsCommand {
+    Show,
+    Accent(Option<u8>),
+    Velocity(Option<Cv>),
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ParseError {
+    Empty,
+    UnknownCommand,
+    MissingArgument,
+    BadArgument,
+}
+
+impl ParseError {
+    pub fn describe(self) -> &'static str {
+        match self {
+            ParseError::Empty => "",
+            ParseError::UnknownCommand => "unknown command, try help",
+            ParseError::MissingArgument => "missing argument",
+            ParseError::BadArgument => "bad argument",
+        }
+    }
+}
+
+fn number(word: &str) -> Result<u8, ParseError> {
+    word.parse().map_err(|_| ParseError::BadArgument)
+}
+
+fn swing(word: &str) -> Result<u8, ParseError> {
+    match number(word)? {
+        swing if (STRAIGHT..=MAX_SWING).contains(&swing) => Ok(swing),
+        _ => Err(ParseError::BadArgument),
+    }
+}
+
+fn slot(word: &str) -> Result<u8, ParseError> {
+    match number(word)? {
+        slot if (slot as usize) < PATTERN_SLOTS => Ok(slot),
+        _ => Err(ParseError::BadArgument),
+    }
+}
+
+fn channel(word: &str) -> Result<u8, ParseError> {
+    match number(word)? {
+        ch if ch < 16 => Ok(ch),
+        _ => Err(ParseError::BadArgument),
+    }
+}
+
+fn key(word: &str) -> Result<u8, ParseError> {
+    match number(word)? {
+        key if key < 128 => Ok(key),
+        _ => Err(ParseError::BadArgument),
+    }
+}
+
+// The channels a config can give pitch ports to
+fn pitch_channel(word: &str) -> Result<u8, ParseError> {
+    match number(word)? {
+        ch if ch < PITCH_CHANNELS => Ok(ch),
+        _ => Err(ParseError::BadArgument),
+    }
+}
+
+fn choke_group(word: &str) -> Result<u8, ParseError> {
+    match number(word)? {
+        group if (group as usize) < CHOKE_GROUPS => Ok(group),
+        _ => Err(ParseError::BadArgument),
+    }
+}
+
+fn trigger_length(word: &str) -> Result<Option<u8>, ParseError> {
+    match word {
+        "off" => Ok(None),
+        word => match number(word)? {
+            ms if (1..=MAX_TRIGGER_MS).contains(&ms) => Ok(Some(ms)),
+            _ => Err(ParseError::BadArgument),
+        },
+    }
+}
+
+fn note_order(word: &str) -> Result<Order, ParseError> {
+    Order::from_name(word).ok_or(ParseError::BadArgument)
+}
+
+const PORTS: [(&str, Cv); 4] = [("a", Cv::A), ("b", Cv::B), ("c", Cv::C), ("d", Cv::D)];
+const ROOTS: [&str; 12] = [
+    "c", "c#", "d", "d#", "e", "f", "f#", "g", "g#", "a", "a#", "b",
+];
+
+fn port(word: &str) -> Result<Cv, ParseError> {
+    match PORTS.iter().find(|(name, _)| *name == word) {
+        Some((_, cv)) => Ok(*cv),
+        None => Err(ParseError::BadArgument),
+    }
+}
+
+fn root(word: &str) -> Result<u8, ParseError> {
+    match ROOTS.iter().position(|name| *name == word) {
+        Some(root) => Ok(root as u8),
+        None => Err(ParseError::BadArgument),
+    }
+}
+
+fn glide(mode: &str, ms: &str) -> Result<Glide, ParseError> {
+    let mode = match mode {
+        "time" => GlideMode::Time,
+        "rate" => GlideMode::Rate,
+        _ => return Err(ParseError::BadArgument),
+    };
+    match ms.parse() {
+        Ok(ms) if ms <= MAX_GLIDE_MS => Ok(Glide { mode, ms }),
+        _ => Err(ParseError::BadArgument),
+    }
+}
+
+fn rate(word: &str) -> Result<Rate, ParseError> {
+    match word.split_once('/') {
+        Some((beats, per)) => Rate::from_division(number(beats)? as u32, number(per)? as u32)
+            .ok_or(ParseError::BadArgument),
+        None => match word.parse::<f32>() {
+            Ok(hz) if hz > 0.0 => match (hz * 100.0 + 0.5) as u16 {
+                centihertz if (MIN_CENTIHERTZ..=MAX_CENTIHERTZ).contains(&centihertz) => {
+                    Ok(Rate::Free(centihertz))
+                }
+                _ => Err(ParseError::BadArgument),
+            },
+            _ => Err(ParseError::BadArgument),
+        },
+    }
+}
+
+fn stage_ms(word: &str) -> Result<u16, ParseError> {
+    match word.parse() {
+        Ok(ms) if ms <= MAX_STAGE_MS => Ok(ms),
+        _ => Err(ParseError::BadArgument),
+    }
+}
+
+fn point(word: &str) -> Result<u8, ParseError> {
+    match number(word)? {
+        point if (point as usize) < CALIBRATION_POINTS => Ok(point),
+        _ => Err(ParseError::BadArgument),
+    }
+}
+
+fn trim(word: &str) -> Result<i16, ParseError> {
+    match word.parse::<i16>() {
+        Ok(trim) if (-MAX_TRIM..=MAX_TRIM).contains(&trim) => Ok(trim),
+        _ => Err(ParseError::BadArgument),
+    }
+}
+
+// A name or the mask written out from the root up, 101011010101 is major
+fn scale(word: &str) -> Result<Scale, ParseError> {
+    match Scale::from_name(word) {
+        Some(scale) => return Ok(scale),
+        None => {}
+    }
+    if word.len() != 12 {
+        return Err(ParseError::BadArgument);
+    }
+    let mut mask = 0;
+    for (i, digit) in word.bytes().enumerate() {
+        match digit {
+            b'1' => mask |= 1 << i,
+            b'0' => {}
+            _ => return Err(ParseError::BadArgument),
+        }
+    }
+    Ok(Scale::User(mask))
+}
+
+// Actions that take an optional channel
+fn player_action(word: &str) -> Option<PlayerAction> {
+    match word {
+        "mute" => Some(PlayerAction::ToggleMute),
+        "hold" => Some(PlayerAction::ToggleHold),
+        "restart" => Some(PlayerAction::SoftRestart),
+        "snap" => Some(PlayerAction::Snap),
+        "clear" => Some(PlayerAction::ClearPattern),
+        "save" => Some(PlayerAction::Save),
+        "load" => Some(PlayerAction::Load),
+        _ => None,
+    }
+}
+
+// How many arguments a command wants at most
+fn arguments(word: &str) -> Option<usize> {
+    match word {
+        "help" | "players" | "overflows" | "play" | "stop" => Some(0),
+        "dump" | "config" | "tempo" | "bend" | "song" | "accent" | "drumvel" => Some(1),
+        "unison" | "detune" | "drums" | "choke" | "trigger" => Some(2),
+        "scale" | "calibrate" | "lfo" | "priority" | "voices" => Some(3),
+        "glide" => Some(4),
+        "env" => Some(6),
+        "length" | "divisor" | "swing" | "slot" => Some(2),
+        "ratchet" => Some(3),
+        "chance" => Some(4),
+        _ => player_action(word).map(|_| 1),
+    }
+}
+
+pub fn parse(line: &str) -> Result<Command, ParseError> {
+    let mut words: Vec<&str, 8> = Vec::new();
+    for word in line.split_whitespace() {
+        words.push(word).map_err(|_| ParseError::BadArgument)?;
+    }
+    match words.as_slice() {
+        [] => Err(ParseError::Empty),
+        ["help"] => Ok(Command::Help),
+        ["players"] => Ok(Command::Players),
+        ["overflows"] => Ok(Command::Overflows),
+        ["tempo"] => Ok(Command::Tempo(None)),
+        ["tempo", bpm] => match bpm.parse() {
+            Ok(bpm) if (MIN_BPM..=MAX_BPM).contains(&bpm) => Ok(Command::Tempo(Some(bpm))),
+            _ => Err(ParseError::BadArgument),
+        },
+        ["calibrate"] => Ok(Command::Calibrate(CalibrateCommand::Show)),
+        ["calibrate", "done"] => Ok(Command::Calibrate(CalibrateCommand::Done)),
+        ["calibrate", "save"] => Ok(Command::Calibrate(CalibrateCommand::Save)),
+        ["calibrate", p, volts] => Ok(Command::Calibrate(CalibrateCommand::Reference(
+            port(p)?,
+            point(volts)?,
+        ))),
+        ["calibrate", p, volts, millivolts] => Ok(Command::Calibrate(CalibrateCommand::Trim(
+            port(p)?,
+            point(volts)?,
+            trim(millivolts)?,
+        ))),
+        ["glide"] => Ok(Command::Glide(None)),
+        ["glide", p, "off"] => Ok(Command::Glide(Some((port(p)?, Portamento::off())))),
+        ["glide", p, mode, ms] => Ok(Command::Glide(Some((
+            port(p)?,
+            Portamento {
+                enabled: true,
+                legato: false,
+                glide: glide(mode, ms)?,
+            },
+        )))),
+        ["glide", p, mode, ms, "legato"] => Ok(Command::Glide(Some((
+            port(p)?,
+            Portamento {
+                enabled: true,
+                legato: true,
+                glide: glide(mode, ms)?,
+            },
+        )))),
+        ["env"] => Ok(Command::Envelope(None)),
+        ["env", p, "off"] => Ok(Command::Envelope(Some((port(p)?, None)))),
+        ["env", p, source, attack, decay, sustain, release] => match number(sustain)? {
+            sustain if sustain <= MAX_SUSTAIN => Ok(Command::Envelope(Some((
+                port(p)?,
+                Some(EnvelopeSettings {
+                    source: port(source)?,
+                    adsr: Adsr {
+                        attack: stage_ms(attack)?,
+                        decay: stage_ms(decay)?,
+                        sustain,
+                        release: stage_ms(release)?,
+                    },
+                }),
+            )))),
+            _ => Err(ParseError::BadArgument),
+        },
+        ["lfo"] => Ok(Command::Lfo(None)),
+        ["lfo", p, "off"] => Ok(Command::Lfo(Some((port(p)?, None)))),
+        ["lfo", p, shape, speed] => match Shape::from_name(shape) {
+            Some(shape) => Ok(Command::Lfo(Some((
+                port(p)?,
+                Some(LfoSettings {
+                    shape,
+                    rate: rate(speed)?,
+                }),
+            )))),
+            None => Err(ParseError::BadArgument),
+        },
+        ["scale"] => Ok(Command::Scale(None)),
+        ["scale", p, name] => Ok(Command::Scale(Some((
+            port(p)?,
+            Quantizer::new(scale(name)?, 0),
+        )))),
+        ["scale", p, name, note] => Ok(Command::Scale(Some((
+            port(p)?,
+            Quantizer::new(scale(name)?, root(note)?),
+        )))),
+        ["bend"] => Ok(Command::Bend(None)),
+        ["bend", range] => match number(range)? {
+            range if range <= MAX_BEND_RANGE => Ok(Command::Bend(Some(range))),
+            _ => Err(ParseError::BadArgument),
+        },
+        ["priority"] => Ok(Command::Priority(None)),
+        ["priority", ch, order] => Ok(Command::Priority(Some((
+            pitch_channel(ch)?,
+            Priority {
+                order: note_order(order)?,
+                retrigger: false,
+            },
+        )))),
+        ["priority", ch, order, "retrigger"] => Ok(Command::Priority(Some((
+            pitch_channel(ch)?,
+            Priority {
+                order: note_order(order)?,
+                retrigger: true,
+            },
+        )))),
+        ["voices"] => Ok(Command::Voices(None)),
+        ["voices", ch, assign, steal] => match (Assign::from_name(assign), Steal::from_name(steal))
+        {
+            (Some(assign), Some(steal)) => Ok(Command::Voices(Some((
+                pitch_channel(ch)?,
+                Allocation { assign, steal },
+            )))),
+            _ => Err(ParseError::BadArgument),
+        },
+        ["unison"] => Ok(Command::Unison(None)),
+        ["unison", ch, "on"] => Ok(Command::Unison(Some((pitch_channel(ch)?, true)))),
+        ["unison", ch, "off"] => Ok(Command::Unison(Some((pitch_channel(ch)?, false)))),
+        ["detune"] => Ok(Command::Detune(None)),
+        ["detune", p, cents] => match cents.parse::<i8>() {
+            Ok(cents) if (-MAX_DETUNE_CENTS..=MAX_DETUNE_CENTS).contains(&cents) => {
+                Ok(Command::Detune(Some((port(p)?, cents))))
+            }
+            _ => Err(ParseError::BadArgument),
+        },
+        ["drums"] => Ok(Command::Drums(DrumCommand::Show)),
+        ["drums", name] => DrumMap::from_name(name)
+            .map(|map| Command::Drums(DrumCommand::Preset(map)))
+            .ok_or(ParseError::BadArgument),
+        ["drums", note, "off"] => Ok(Command::Drums(DrumCommand::Map(key(note)?, None))),
+        ["drums", note, gate] => match Gate::from_name(gate) {
+            Some(gate) => Ok(Command::Drums(DrumCommand::Map(key(note)?, Some(gate)))),
+            None => Err(ParseError::BadArgument),
+        },
+        ["accent"] | ["drumvel"] => Ok(Command::Dynamics(DynamicsCommand::Show)),
+        ["accent", "off"] => Ok(Command::Dynamics(DynamicsCommand::Accent(None))),
+        ["accent", vel] => match number(vel)? {
+            vel if (1..128).contains(&vel) => {
+                Ok(Command::Dynamics(DynamicsCommand::Accent(Some(vel))))
+            }
+            _ => Err(ParseError::BadArgument),
+        },
+        ["drumvel", "off"] => Ok(Command::Dynamics(DynamicsCommand::Velocity(None))),
+        ["drumvel", p] => Ok(Command::Dynamics(DynamicsCommand::Velocity(Some(port(p)?)))),
+        ["choke"] => Ok(Command::Choke(None)),
+        ["choke", group, "off"] => Ok(Command::Choke(Some((
+            choke_group(group)?,
+            GateSet::empty(),
+        )))),
+        ["choke", group, gates @ ..] if !gates.is_empty() => {
+            let mut set = GateSet::empty();
+            for gate in gates {
+                set.insert(Gate::from_name(gate).ok_or(ParseError::BadArgument)?);
+            }
+            Ok(Command::Choke(Some((choke_group(group)?, set))))
+        }
+        ["trigger"] => Ok(Command::Trigger(None)),
+        ["trigger", gate, length] => match Gate::from_name(gate) {
+            Some(gate) if can_trigger(gate) => {
+                Ok(Command::Trigger(Some((gate, trigger_length(length)?))))
+            }
+            _ => Err(ParseError::BadArgument),
+        },
+        ["dump", ch] => Ok(Command::Dump(channel(ch)?)),
+        ["config", name] => Config::from_name(name)
+            .map(Command::Config)
+            .ok_or(ParseError::BadArgument),
+        ["play"] => Ok(Command::Player(PlayerMessage::Broadcast(
+            PlayerAction::Play,
+        ))),
+        ["stop"] => Ok(Command::Player(PlayerMessage::Broadcast(
+            PlayerAction::Stop,
+        ))),
+        ["length", ch, n] => match number(n)? {
+            n if n > 0 && n as usize <= MAX_LENGTH => Ok(Command::Player(PlayerMessage::Action(
+                channel(ch)?,
+                PlayerAction::SetLength(n),
+            ))),
+            _ => Err(ParseError::BadArgument),
+        },
+        ["divisor", ch, n] => match number(n)? {
+            n if is_divisor(n) => Ok(Command::Player(PlayerMessage::Action(
+                channel(ch)?,
+                PlayerAction::SetDivisor(n),
+            ))),
+            _ => Err(ParseError::BadArgument),
+        },
+        ["swing", amount] => Ok(Command::Player(PlayerMessage::Broadcast(
+            PlayerAction::SetDefaultSwing(swing(amount)?),
+        ))),
+        ["swing", ch, "default"] => Ok(Command::Player(PlayerMessage::Action(
+            channel(ch)?,
+            PlayerAction::SetSwing(None),
+        ))),
+        ["swing", ch, amount] => Ok(Command::Player(PlayerMessage::Action(
+            channel(ch)?,
+            PlayerAction::SetSwing(Some(swing(amount)?)),
+        ))),
+        ["song"] => Ok(Command::Song(SongCommand::Show)),
+        ["song", "on"] => Ok(Command::Song(SongCommand::Enable(true))),
+        ["song", "off"] => Ok(Command::Song(SongCommand::Enable(false))),
+        ["song", "clear"] => Ok(Command::Song(SongCommand::Clear)),
+        ["song", "select", n] => match number(n)? {
+            n if (n as usize) < SONGS => Ok(Command::Player(PlayerMessage::Broadcast(
+                PlayerAction::SongSelect(n),
+            ))),
+            _ => Err(ParseError::BadArgument),
+        },
+        ["song", "select"] => Err(ParseError::MissingArgument),
+        ["song", "add", repeats, slots @ ..] if slots.len() == PLAYERS => {
+            let mut picked = [0; PLAYERS];
+            for (i, word) in slots.iter().enumerate() {
+                picked[i] = slot(word)?;
+            }
+            Entry::new(picked, number(repeats)?)
+                .map(|entry| Command::Song(SongCommand::Append(entry)))
+                .ok_or(ParseError::BadArgument)
+        }
+        ["song", "add", ..] => Err(ParseError::MissingArgument),
+        ["slot", n] => Ok(Command::Player(PlayerMessage::Broadcast(
+            PlayerAction::SelectSlot(slot(n)?),
+        ))),
+        ["slot", ch, n] => Ok(Command::Player(PlayerMessage::Action(
+            channel(ch)?,
+            PlayerAction::SelectSlot(slot(n)?),
+        ))),
+        ["ratchet", ch, step, n] => match (number(step)?, number(n)?) {
+            (step, n) if (step as usize) < MAX_LENGTH && n > 0 && n <= MAX_RATCHET => {
+                Ok(Command::Player(PlayerMessage::Action(
+                    channel(ch)?,
+                    PlayerAction::SetRatchet(step as u32, n),
+                )))
+            }
+            _ => Err(ParseError::BadArgument),
+        },
+        ["chance", ch, step, key, percent] => match (number(step)?, number(key)?, number(percent)?)
+        {
+            (step, key, percent)
+                if (step as usize) < MAX_LENGTH && key < 128 && percent <= ALWAYS =>
+            {
+                Ok(Command::Player(PlayerMessage::Action(
+                    channel(ch)?,
+                    PlayerAction::SetChance(step as u32, key, percent),
+                )))
+            }
+            _ => Err(ParseError::BadArgument),
+        },
+        [word] if player_action(word).is_some() => Ok(Command::Player(PlayerMessage::Broadcast(
+            player_action(word).unwrap(),
+        ))),
+        [word, ch] if player_action(word).is_some() => Ok(Command::Player(PlayerMessage::Action(
+            channel(ch)?,
+            player_action(word).unwrap(),
+        ))),
+        [word, rest @ ..] => match arguments(word) {
+            Some(wanted) if rest.len() < wanted => Err(ParseError::MissingArgument),
+            Some(_) => Err(ParseError::BadArgument),
+            None => Err(ParseError::UnknownCommand),
+        },
+    }
+}
+
+pub fn write_help<W: Write>(out: &mut W) -> fmt::Result {
+    for line in HELP.lines() {
+        write!(out, "{}{}", line, NEWLINE)?;
+    }
+    Ok(())
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct PlayerStatus {
+    pub channel: u8,
+    pub length: u8,
+    pub divisor: u8,
+    pub step: u32,
+    pub swing: u8,
+    pub slot: usize,
+    pub next_slot: Option<usize>,
+    pub playing: bool,
+    pub muted: bool,
+    pub held: bool,
+}
+
+impl PlayerStatus {
+    pub fn of<M: MidiSink, O: OutputSink>(player: &Player<M, O>) -> Self {
+        PlayerStatus {
+            channel: player.channel(),
+            length: player.length(),
+            divisor: player.divisor(),
+            step: player.step(),
+            swing: player.swing(),
+            slot: player.slot(),
+            next_slot: player.next_slot(),
+            playing: player.is_playing(),
+            muted: player.is_muted(),
+            held: player.is_held(),
+        }
+    }
+}
+
+fn on_off(value: bool) -> &'static str {
+    if value {
+        "on"
+    } else {
+        "off"
+    }
+}
+
+impl fmt::Display for PlayerStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "ch {}  length {:2}  divisor {:2}  step {:2}  swing {}  slot {}",
+            self.channel, self.length, self.divisor, self.step, self.swing, self.slot,
+        )?;
+        match self.next_slot {
+            Some(slot) => write!(f, ">{}", slot)?,
+            None => write!(f, "  ")?,
+        }
+        write!(
+            f,
+            "  {}  mute {}  hold {}",
+            if self.playing { "playing" } else { "stopped" },
+            on_off(self.muted),
+            on_off(self.held),
+        )
+    }
+}
+
+pub fn write_status<W: Write>(out: &mut W, status: PlayerStatus) -> fmt::Result {
+    write!(out, "{}{}", status, NEWLINE)
+}
+
+// pattern is what Player::export_pattern wrote. Events are step.sub followed by
+// the raw message in hex and the chance when it is not certain.
+pub fn write_pattern<W: Write>(out: &mut W, pattern: &[u8]) -> fmt::Result {
+    if pattern.len() < 4 {
+        return Ok(());
+    }
+    let count = u16::from_le_bytes([pattern[2], pattern[3]]) as usize;
+    write!(
+        out,
+        "length {}  divisor {}  events {}{}",
+        pattern[0], pattern[1], count, NEWLINE
+    )?;
+    for raw in pattern[4..].chunks(EVENT_SIZE).take(count) {
+        if raw.len() < EVENT_SIZE {
+            break;
+        }
+        write!(
+            out,
+            "{:2}.{:03}  {:02x} {:02x} {:02x}",
+            raw[0], raw[1], raw[2], raw[3], raw[4]
+        )?;
+        if raw[5] < ALWAYS {
+            write!(out, "  {}%", raw[5])?;
+        }
+        write!(out, "{}", NEWLINE)?;
+    }
+    let ratchets = pattern.get(4 + count * EVENT_SIZE..).unwrap_or(&[]);
+    for (step, ratchet) in ratchets.iter().enumerate() {
+        if *ratchet > 1 && step < pattern[0] as usize {
+            write!(out, "{:2}  ratchet {}{}", step, ratchet, NEWLINE)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn write_tempo<W: Write>(out: &mut W, bpm: u16, source: Source) -> fmt::Result {
+    match source {
+        Source::Internal => write!(out, "{} bpm{}", bpm, NEWLINE),
+        Source::External => write!(out, "{} bpm, following external clock{}", bpm, NEWLINE),
+    }
+}
+
+pub fn write_bend_range<W: Write>(out: &mut W, semitones: u8) -> fmt::Result {
+    write!(out, "bend range {} semitones{}", semitones, NEWLINE)
+}
+
+pub fn write_priorities<W: Write>(out: &mut W, priorities: [Priority; 4]) -> fmt::Result {
+    for (ch, priority) in priorities.iter().enumerate() {
+        write!(out, "{}  {}", ch, priority.order.name())?;
+        if priority.retrigger {
+            write!(out, "  retrigger")?;
+        }
+        write!(out, "{}", NEWLINE)?;
+    }
+    Ok(())
+}
+
+pub fn write_allocations<W: Write>(out: &mut W, allocations: [Allocation; 4]) -> fmt::Result {
+    for (ch, allocation) in allocations.iter().enumerate() {
+        write!(
+            out,
+            "{}  {}  {}{}",
+            ch,
+            allocation.assign.name(),
+            allocation.steal.name(),
+            NEWLINE
+        )?;
+    }
+    Ok(())
+}
+
+pub fn write_unisons<W: Write>(out: &mut W, unisons: [bool; 4]) -> fmt::Result {
+    for (ch, unison) in unisons.iter().enumerate() {
+        write!(out, "{}  unison {}{}", ch, on_off(*unison), NEWLINE)?;
+    }
+    Ok(())
+}
+
+pub fn write_detunes<W: Write>(out: &mut W, detunes: [i8; 4]) -> fmt::Result {
+    for ((name, _), cents) in PORTS.iter().zip(detunes) {
+        write!(out, "{}  {:+} cents{}", name, cents, NEWLINE)?;
+    }
+    Ok(())
+}
+
+pub fn write_drum_map<W: Write>(out: &mut W, drum_map: DrumMap) -> fmt::Result {
+    write!(out, "{}", drum_map.preset_name().unwrap_or("custom"))?;
+    if drum_map.by_octave {
+        write!(out, ", every octave")?;
+    }
+    write!(out, "{}", NEWLINE)?;
+    for drum in drum_map.notes() {
+        if drum_map.by_octave {
+            write!(out, "{}", ROOTS[drum.note as usize % 12])?;
+        } else {
+            write!(out, "{}", drum.note)?;
+        }
+        write!(out, "  {}{}", drum.gate.name(), NEWLINE)?;
+    }
+    Ok(())
+}
+
+pub fn write_dynamics<W: Write>(out: &mut W, dynamics: Dynamics) -> fmt::Result {
+    match dynamics.accent {
+        Some(vel) => write!(out, "accent from velocity {}{}", vel, NEWLINE)?,
+        None => write!(out, "accent off{}", NEWLINE)?,
+    }
+    match dynamics.velocity {
+        Some(cv) => write!(out, "drum velocity on {}{}", PORTS[cv.index()].0, NEWLINE)?,
+        None => write!(out, "drum velocity off{}", NEWLINE)?,
+    }
+    Ok(())
+}
+
+pub fn write_chokes<W: Write>(out: &mut W, chokes: Chokes) -> fmt::Result {
+    for (group, gates) in chokes.groups.iter().enumerate() {
+        write!(out, "{} ", group)?;
+        if gates.is_empty() {
+            write!(out, " off")?;
+        }
+        for gate in gates.gates() {
+            write!(out, " {}", gate.name())?;
+        }
+        write!(out, "{}", NEWLINE)?;
+    }
+    Ok(())
+}
+
+pub fn write_triggers<W: Write>(out: &mut W, triggers: Triggers) -> fmt::Result {
+    let mut any = false;
+    for (gate, ms) in triggers.lengths() {
+        write!(out, "{}  {} ms{}", gate.name(), ms, NEWLINE)?;
+        any = true;
+    }
+    if !any {
+        write!(out, "every drum gate follows its notes{}", NEWLINE)?;
+    }
+    Ok(())
+}
+
+pub fn write_glides<W: Write>(out: &mut W, portamentos: [Portamento; 4]) -> fmt::Result {
+    for ((name, _), portamento) in PORTS.iter().zip(portamentos) {
+        write!(out, "{}  {}", name, on_off(portamento.enabled))?;
+        match portamento.glide.mode {
+            GlideMode::Time => write!(out, "  {} ms", portamento.glide.ms)?,
+            GlideMode::Rate => write!(out, "  {} ms per octave", portamento.glide.ms)?,
+        }
+        if portamento.legato {
+            write!(out, "  legato")?;
+        }
+        write!(out, "{}", NEWLINE)?;
+    }
+    Ok(())
+}
+
+fn greatest_divisor(a: u32, b: u32) -> u32 {
+    if b == 0 {
+        a
+    } else {
+        greatest_divisor(b, a % b)
+    }
+}
+
+pub fn write_lfos<W: Write>(out: &mut W, lfos: [Option<LfoSettings>; 4]) -> fmt::Result {
+    for ((name, _), lfo) in PORTS.iter().zip(lfos) {
+        match lfo {
+            Some(LfoSettings {
+                shape,
+                rate: Rate::Free(centihertz),
+            }) => write!(
+                out,
+                "{}  {}  {}.{:02} Hz",
+                name,
+                shape.name(),
+                centihertz / 100,
+                centihertz % 100
+            )?,
+            // Back to a note length, 96 ticks to a bar
+            Some(LfoSettings {
+                shape,
+                rate: Rate::Clock(ticks),
+            }) => {
+                let divisor = greatest_divisor(ticks as u32, 96).max(1);
+                write!(
+                    out,
+                    "{}  {}  {}/{}",
+                    name,
+                    shape.name(),
+                    ticks as u32 / divisor,
+                    96 / divisor
+                )?
+            }
+            None => write!(out, "{}  off", name)?,
+        }
+        write!(out, "{}", NEWLINE)?;
+    }
+    Ok(())
+}
+
+pub fn write_envelopes<W: Write>(
+    out: &mut W,
+    envelopes: [Option<EnvelopeSettings>; 4],
+) -> fmt::Result {
+    for ((name, _), envelope) in PORTS.iter().zip(envelopes) {
+        match envelope {
+            Some(EnvelopeSettings { source, adsr }) => write!(
+                out,
+                "{}  gate {}  a {} ms  d {} ms  s {}%  r {} ms",
+                name,
+                PORTS[source.index()].0,
+                adsr.attack,
+                adsr.decay,
+                adsr.sustain,
+                adsr.release
+            )?,
+            None => write!(out, "{}  off", name)?,
+        }
+        write!(out, "{}", NEWLINE)?;
+    }
+    Ok(())
+}
+
+pub fn write_scales<W: Write>(out: &mut W, quantizers: [Quantizer; 4]) -> fmt::Result {
+    for ((name, _), quantizer) in PORTS.iter().zip(quantizers) {
+        write!(out, "{}  {}", name, quantizer.scale.name())?;
+        match quantizer.scale {
+            Scale::User(mask) => {
+                write!(out, " ")?;
+                for i in 0..12 {
+                    write!(out, "{}", (mask >> i) & 1)?;
+                }
+            }
+            _ => {}
+        }
+        write!(
+            out,
+            "  root {}{}",
+            ROOTS[quantizer.root as usize % 12],
+            NEWLINE
+        )?;
+    }
+    Ok(())
+}
+
+// A row per port with the trim at every point in mV
+pub fn write_calibration<W: Write>(out: &mut W, calibrations: &[Calibration; 4]) -> fmt::Result {
+    write!(out, " ")?;
+    for volts in 0..CALIBRATION_POINTS {
+        write!(out, "  {:>3}V", volts)?;
+    }
+    write!(out, "{}", NEWLINE)?;
+    for ((name, _), calibration) in PORTS.iter().zip(calibrations) {
+        write!(out, "{}", name)?;
+        for trim in calibration.trims {
+            write!(out, "  {:>4}", trim)?;
+        }
+        write!(out, "{}", NEWLINE)?;
+    }
+    Ok(())
+}
+
+pub fn write_song<W: Write>(out: &mut W, arranger: &Arranger) -> fmt::Result {
+    let position = arranger.position();
+    write!(
+        out,
+        "song {}  {}  entry {}  repeat {}{}",
+        position.song,
+        if arranger.is_enabled() { "on" } else { "off" },
+        position.entry,
+        position.repeat,
+        NEWLINE
+    )?;
+    for (i, entry) in arranger.entries(position.song).iter().enumerate() {
+        write!(out, "{:2}  repeats {:2}  slots", i, entry.repeats)?;
+        for slot in entry.slots {
+            write!(out, " {}", slot)?;
+        }
+        write!(out, "{}", NEWLINE)?;
+    }
+    Ok(())
+}
+
+pub fn write_overflows<W: Write>(out: &mut W, counters: &[(&str, u32)]) -> fmt::Result {
+    for (name, count) in counters {
+        write!(out, "{:10} {}{}", name, count, NEWLINE)?;
+    }
+    Ok(())
+}
+
+pub fn write_error<W: Write>(out: &mut W, error: ParseError) -> fmt::Result {
+    match error {
+        ParseError::Empty => Ok(()),
+        _ => write!(out, "{}{}", error.describe(), NEWLINE),
+    }
+}
+
+// Collects typed characters until the end of a line. Backspace works, whatever
+// does not fit in LINE_SIZE is dropped.
+pub struct LineReader {
+    buf: Vec<u8, LINE_SIZE>,
+    done: bool,
+}
+
+impl LineReader {
+    pub fn new() -> Self {
+        LineReader {
+            buf: Vec::new(),
+            done: false,
+        }
+    }
+
+    // True when a whole line is available from line()
+    pub fn push(&mut self, byte: u8) -> bool {
+        if self.done {
+            self.buf.clear();
+            self.done = false;
+        }
+        match byte {
+            b'\r' | b'\n' => {
+                self.done = !self.buf.is_empty();
+                self.done
+            }
+            0x08 | 0x7f => {
+                self.buf.pop();
+                false
+            }
+            _ => {
+                self.buf.push(byte).ok();
+                false
+            }
+        }
+    }
+
+    pub fn line(&self) -> &str {
+        core::str::from_utf8(&self.buf).unwrap_or("")
+    }
+}
@@ -0,0 +1,286 @@
+use crate::outs::{Cv, Gate};
+
+/*
+Which gate a note on the drum channel fires. The map is a short table of notes
+and gates, and the first entry for a note wins. Any gate can be in it, so a
+drum note can also open one of the voice gates A to D.
+
+A map that goes by octave only looks at the note within the octave, both when
+it is played and when an entry is set, so that a keyboard plays the same drums
+everywhere. The octave preset is how drums were always mapped, General MIDI
+puts them where drum machines and DAWs send them.
+
+A hit closes every other gate in the choke groups of its gate, the way a
+closed hi-hat cuts an open one short.
+
+A gate with a trigger length opens for that long on every hit, however long
+the note is, and the output handler times it off. The voice gates A to D always
+follow their notes since envelopes follow them too.
+*/
+
+pub const DRUM_MAP_SIZE: usize = 16;
+pub const CHOKE_GROUPS: usize = 4;
+pub const MAX_TRIGGER_MS: u8 = 250;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct DrumNote {
+    pub note: u8,
+    pub gate: Gate,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct DrumMap {
+    pub by_octave: bool,
+    entries: [Option<DrumNote>; DRUM_MAP_SIZE],
+}
+
+impl DrumMap {
+    pub const fn empty(by_octave: bool) -> Self {
+        DrumMap {
+            by_octave,
+            entries: [None; DRUM_MAP_SIZE],
+        }
+    }
+
+    fn with(by_octave: bool, notes: &[(u8, Gate)]) -> Self {
+        let mut map = DrumMap::empty(by_octave);
+        for (note, gate) in notes {
+            map.set(*note, Some(*gate));
+        }
+        return map;
+    }
+
+    pub fn octave() -> Self {
+        DrumMap::with(
+            true,
+            &[
+                (0, Gate::Kick),     // C
+                (2, Gate::Snare),    // D
+                (4, Gate::Clap),     // E
+                (5, Gate::ClosedHH), // F
+                (7, Gate::OpenHH),   // G
+                (9, Gate::FX),       // A
+                (11, Gate::Accent),  // B
+            ],
+        )
+    }
+
+    pub fn general_midi() -> Self {
+        DrumMap::with(
+            false,
+            &[
+                (35, Gate::Kick),     // Acoustic bass drum
+                (36, Gate::Kick),     // Bass drum
+                (38, Gate::Snare),    // Acoustic snare
+                (40, Gate::Snare),    // Electric snare
+                (39, Gate::Clap),     // Hand clap
+                (42, Gate::ClosedHH), // Closed hi-hat
+                (44, Gate::ClosedHH), // Pedal hi-hat
+                (46, Gate::OpenHH),   // Open hi-hat
+                (49, Gate::FX),       // Crash cymbal
+                (37, Gate::Accent),   // Side stick
+            ],
+        )
+    }
+
+    pub fn preset_name(&self) -> Option<&'static str> {
+        if *self == DrumMap::octave() {
+            return Some("octave");
+        }
+        if *self == DrumMap::general_midi() {
+            return Some("gm");
+        }
+        return None;
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "octave" => Some(DrumMap::octave()),
+            "gm" => Some(DrumMap::general_midi()),
+            _ => None,
+        }
+    }
+
+    fn key(&self, note: u8) -> u8 {
+        if self.by_octave {
+            return note % 12;
+        }
+        return note;
+    }
+
+    pub fn gate(&self, note: u8) -> Option<Gate> {
+        let note = self.key(note);
+        return self
+            .entries
+            .iter()
+            .flatten()
+            .find(|entry| entry.note == note)
+            .map(|entry| entry.gate);
+    }
+
+    // None takes the note out, fails when the table has no room left
+    pub fn set(&mut self, note: u8, gate: Option<Gate>) -> Option<()> {
+        let note = self.key(note);
+        for entry in self.entries.iter_mut() {
+            match entry {
+                Some(DrumNote { note: n, .. }) if *n == note => *entry = None,
+                _ => {}
+            }
+        }
+        match gate {
+            Some(gate) => {
+                let free = self.entries.iter_mut().find(|entry| entry.is_none())?;
+                *free = Some(DrumNote { note, gate });
+            }
+            None => {}
+        }
+        return Some(());
+    }
+
+    pub fn notes(&self) -> impl Iterator<Item = DrumNote> + '_ {
+        self.entries.iter().flatten().copied()
+    }
+}
+
+// Hits at or above the accent velocity open the accent gate for as long as they
+// are held, and the velocity of every hit can go out on a CV port
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Dynamics {
+    pub accent: Option<u8>,
+    pub velocity: Option<Cv>,
+}
+
+impl Dynamics {
+    pub const fn off() -> Self {
+        Dynamics {
+            accent: None,
+            velocity: None,
+        }
+    }
+}
+
+pub fn can_trigger(gate: Gate) -> bool {
+    !matches!(gate, Gate::GateA | Gate::GateB | Gate::GateC | Gate::GateD)
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Triggers {
+    lengths: [u8; GATES.len()], // ms by gate, 0 follows the note
+}
+
+impl Triggers {
+    pub const fn off() -> Self {
+        Triggers {
+            lengths: [0; GATES.len()],
+        }
+    }
+
+    pub fn length(&self, gate: Gate) -> Option<u8> {
+        match self.lengths[gate as usize] {
+            0 => None,
+            ms => Some(ms),
+        }
+    }
+
+    pub fn set(&mut self, gate: Gate, ms: Option<u8>) -> Option<()> {
+        match ms {
+            _ if !can_trigger(gate) => return None,
+            Some(ms) if ms == 0 || ms > MAX_TRIGGER_MS => return None,
+            _ => {}
+        }
+        self.lengths[gate as usize] = ms.unwrap_or(0);
+        return Some(());
+    }
+
+    pub fn lengths(&self) -> impl Iterator<Item = (Gate, u8)> + '_ {
+        GATES
+            .into_iter()
+            .filter_map(|gate| self.length(gate).map(|ms| (gate, ms)))
+    }
+}
+
+const GATES: [Gate; 14] = [
+    Gate::Kick,
+    Gate::OpenHH,
+    Gate::Clap,
+    Gate::Snare,
+    Gate::FX,
+    Gate::ClosedHH,
+    Gate::Accent,
+    Gate::Start,
+    Gate::Stop,
+    Gate::Clock,
+    Gate::GateA,
+    Gate::GateB,
+    Gate::GateC,
+    Gate::GateD,
+];
+
+// A bit for every gate
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct GateSet(u16);
+
+impl GateSet {
+    pub const fn empty() -> Self {
+        GateSet(0)
+    }
+
+    pub fn of(gates: &[Gate]) -> Self {
+        let mut set = GateSet::empty();
+        for gate in gates {
+            set.insert(*gate);
+        }
+        return set;
+    }
+
+    pub fn insert(&mut self, gate: Gate) {
+        self.0 |= 1 << gate as u16;
+    }
+
+    pub fn remove(&mut self, gate: Gate) {
+        self.0 &= !(1 << gate as u16);
+    }
+
+    pub fn contains(&self, gate: Gate) -> bool {
+        self.0 & (1 << gate as u16) != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn gates(self) -> impl Iterator<Item = Gate> {
+        GATES.into_iter().filter(move |gate| self.contains(*gate))
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Chokes {
+    pub groups: [GateSet; CHOKE_GROUPS],
+}
+
+impl Chokes {
+    pub const fn none() -> Self {
+        Chokes {
+            groups: [GateSet::empty(); CHOKE_GROUPS],
+        }
+    }
+
+    pub fn hi_hats() -> Self {
+        let mut chokes = Chokes::none();
+        chokes.groups[0] = GateSet::of(&[Gate::ClosedHH, Gate::OpenHH]);
+        return chokes;
+    }
+
+    // Everything that shares a group with the gate
+    pub fn choked_by(&self, gate: Gate) -> GateSet {
+        let mut choked = GateSet::empty();
+        for group in self.groups {
+            if group.contains(gate) {
+                choked.0 |= group.0;
+            }
+        }
+        choked.remove(gate);
+        return choked;
+    }
+}
@@ -0,0 +1,160 @@
+use crate::outs::Cv;
+use crate::utils::midi_utils::cc_to_ms;
+
+/*
+ADSR envelopes for CV ports that no voice uses. An envelope follows the gate of
+the voice port it is paired with: the mapper tells the output handler when that
+gate opens and closes, and the output handler moves the level along in its own
+time in microseconds. Every stage is a straight line and a stage with no time
+is skipped.
+
+Levels go from 0 to 1, like the ones SetVal takes.
+*/
+
+pub const ATTACK_CC: u8 = 73;
+pub const DECAY_CC: u8 = 75;
+pub const SUSTAIN_CC: u8 = 79;
+pub const RELEASE_CC: u8 = 72;
+pub const MAX_STAGE_MS: u16 = 5000;
+pub const MAX_SUSTAIN: u8 = 100;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Adsr {
+    pub attack: u16,  // ms
+    pub decay: u16,   // ms
+    pub sustain: u8,  // Percent of the peak
+    pub release: u16, // ms
+}
+
+impl Adsr {
+    pub const fn new() -> Self {
+        Adsr {
+            attack: 5,
+            decay: 200,
+            sustain: 70,
+            release: 300,
+        }
+    }
+
+    // Same curve as the glide time, fine control at the short end
+    pub fn ms_from_cc(value: u8) -> u16 {
+        return cc_to_ms(value, MAX_STAGE_MS);
+    }
+
+    pub fn sustain_from_cc(value: u8) -> u8 {
+        return (value.min(127) as u16 * MAX_SUSTAIN as u16 / 127) as u8;
+    }
+
+    fn sustain_level(&self) -> f32 {
+        self.sustain.min(MAX_SUSTAIN) as f32 / MAX_SUSTAIN as f32
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct EnvelopeSettings {
+    pub source: Cv, // The voice port whose gate it follows
+    pub adsr: Adsr,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Stage {
+    Idle,
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Envelope {
+    adsr: Adsr,
+    stage: Stage,
+    level: f32,
+    released_at: f32, // Level when the gate closed
+    last: u64,
+}
+
+// How far a stage of ms milliseconds goes in elapsed microseconds
+fn progress(elapsed: u64, ms: u16) -> f32 {
+    if ms == 0 {
+        return 1.0;
+    }
+    return elapsed as f32 / (ms as f32 * 1000.0);
+}
+
+impl Envelope {
+    pub fn new(adsr: Adsr) -> Self {
+        Envelope {
+            adsr,
+            stage: Stage::Idle,
+            level: 0.0,
+            released_at: 0.0,
+            last: 0,
+        }
+    }
+
+    // Keeps going from where it is, so knobs can be turned while it plays
+    pub fn set_adsr(&mut self, adsr: Adsr) {
+        self.adsr = adsr;
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.stage == Stage::Idle
+    }
+
+    pub fn value(&self) -> f32 {
+        self.level
+    }
+
+    // Opening again before the release is done starts the attack from where
+    // the level is
+    pub fn gate(&mut self, open: bool, now: u64) {
+        self.advance(now);
+        if open {
+            self.stage = Stage::Attack;
+        } else if self.stage != Stage::Idle {
+            self.stage = Stage::Release;
+            self.released_at = self.level;
+        }
+        self.advance(now);
+    }
+
+    pub fn advance(&mut self, now: u64) {
+        let elapsed = now.wrapping_sub(self.last);
+        self.last = now;
+        let sustain = self.adsr.sustain_level();
+        match self.stage {
+            Stage::Idle => {}
+            Stage::Sustain => self.level = sustain,
+            Stage::Attack => {
+                self.level += progress(elapsed, self.adsr.attack);
+                if self.level >= 1.0 {
+                    self.level = 1.0;
+                    self.stage = Stage::Decay;
+                }
+            }
+            Stage::Decay => {
+                self.level -= progress(elapsed, self.adsr.decay) * (1.0 - sustain);
+                if self.level <= sustain {
+                    self.level = sustain;
+                    self.stage = Stage::Sustain;
+                }
+            }
+            Stage::Release => {
+                self.level -= progress(elapsed, self.adsr.release) * self.released_at;
+                if self.level <= 0.0 {
+                    self.level = 0.0;
+                    self.stage = Stage::Idle;
+                }
+            }
+        }
+        // Zero length stages hand over right away
+        match self.stage {
+            Stage::Decay if self.adsr.decay == 0 => self.advance(now),
+            Stage::Release if self.released_at <= 0.0 => {
+                self.stage = Stage::Idle;
+            }
+            _ => {}
+        }
+    }
+}
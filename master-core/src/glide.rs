@@ -0,0 +1,99 @@
+/*
+Portamento between notes on a pitch output. The mapper decides whether a note
+glides and the output handler moves the voltage there over time, in whatever
+unit of time it counts in microseconds.
+
+In Time mode every glide takes the same time, in Rate mode the time is per
+octave so that short steps are quick.
+*/
+
+use crate::utils::midi_utils::cc_to_ms;
+
+pub const TIME_CC: u8 = 5;
+pub const SWITCH_CC: u8 = 65;
+pub const MAX_GLIDE_MS: u16 = 2000;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum GlideMode {
+    Time,
+    Rate,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Glide {
+    pub mode: GlideMode,
+    pub ms: u16,
+}
+
+impl Glide {
+    pub fn ms_from_cc(value: u8) -> u16 {
+        return cc_to_ms(value, MAX_GLIDE_MS);
+    }
+
+    fn micros(&self, from: f32, to: f32) -> u64 {
+        match self.mode {
+            GlideMode::Time => self.ms as u64 * 1000,
+            GlideMode::Rate => ((to - from).abs() * self.ms as f32 * 1000.0) as u64,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Portamento {
+    pub enabled: bool,
+    pub legato: bool, // Only glide while the last note is still held
+    pub glide: Glide,
+}
+
+impl Portamento {
+    pub const fn off() -> Self {
+        Portamento {
+            enabled: false,
+            legato: false,
+            glide: Glide {
+                mode: GlideMode::Time,
+                ms: 0,
+            },
+        }
+    }
+
+    pub fn glides(&self, legato: bool) -> bool {
+        self.enabled && self.glide.ms > 0 && (legato || !self.legato)
+    }
+}
+
+// A glide in progress, in volts
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Slew {
+    from: f32,
+    to: f32,
+    start: u64,
+    length: u64,
+}
+
+impl Slew {
+    pub fn new(from: f32, to: f32, now: u64, glide: Glide) -> Self {
+        Slew {
+            from,
+            to,
+            start: now,
+            length: glide.micros(from, to),
+        }
+    }
+
+    pub fn target(&self) -> f32 {
+        self.to
+    }
+
+    pub fn is_done(&self, now: u64) -> bool {
+        now.wrapping_sub(self.start) >= self.length
+    }
+
+    pub fn at(&self, now: u64) -> f32 {
+        if self.is_done(now) {
+            return self.to;
+        }
+        let progress = now.wrapping_sub(self.start) as f32 / self.length as f32;
+        return self.from + (self.to - self.from) * progress;
+    }
+}
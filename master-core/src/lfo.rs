@@ -0,0 +1,171 @@
+use crate::utils::random::Random;
+
+/*
+Low frequency oscillators for CV ports that no voice uses. The output handler
+advances them with its own time in microseconds. A clock synced LFO snaps its
+phase to the MIDI clock on every tick, counted from the last start, and glides
+along in between at the speed the ticks have been coming in.
+
+Values go from 0 to 1, like the ones SetVal takes.
+*/
+
+pub const MIN_CENTIHERTZ: u16 = 1;
+pub const MAX_CENTIHERTZ: u16 = 5000;
+const TICKS_PER_BAR: u32 = 96;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Shape {
+    Sine,
+    Triangle,
+    Saw,
+    Square,
+    Random, // A new level every cycle
+}
+
+impl Shape {
+    pub fn name(self) -> &'static str {
+        match self {
+            Shape::Sine => "sine",
+            Shape::Triangle => "triangle",
+            Shape::Saw => "saw",
+            Shape::Square => "square",
+            Shape::Random => "random",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "sine" => Some(Shape::Sine),
+            "triangle" => Some(Shape::Triangle),
+            "saw" => Some(Shape::Saw),
+            "square" => Some(Shape::Square),
+            "random" => Some(Shape::Random),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Rate {
+    Free(u16),  // Hundredths of a hertz
+    Clock(u16), // MIDI clock ticks per cycle
+}
+
+impl Rate {
+    // A note length like 1/4 or 2/1, as long as it is a whole number of ticks
+    pub fn from_division(beats: u32, per: u32) -> Option<Self> {
+        if beats == 0 || per == 0 || (TICKS_PER_BAR * beats) % per != 0 {
+            return None;
+        }
+        let ticks = TICKS_PER_BAR * beats / per;
+        return u16::try_from(ticks).ok().map(Rate::Clock);
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct LfoSettings {
+    pub shape: Shape,
+    pub rate: Rate,
+}
+
+// sin(pi * t) for t from 0 to 1, Bhaskara's approximation is plenty for a
+// modulation source and needs no libm
+fn half_sine(t: f32) -> f32 {
+    let x = t * (1.0 - t);
+    return 16.0 * x / (5.0 - 4.0 * x);
+}
+
+#[derive(Copy, Clone)]
+pub struct Lfo {
+    settings: LfoSettings,
+    phase: f32,
+    held: f32,
+    random: Random,
+    last: Option<u64>,
+    tick_length: u64, // Microseconds between the last two clock ticks
+    last_tick: Option<u64>,
+    ceiling: f32, // Where the next clock tick will put the phase
+}
+
+impl Lfo {
+    pub fn new(settings: LfoSettings, seed: u32) -> Self {
+        let mut random = Random::new(seed);
+        let held = (random.next_u32() >> 8) as f32 / (1 << 24) as f32;
+        Lfo {
+            settings,
+            phase: 0.0,
+            held,
+            random,
+            last: None,
+            tick_length: 0,
+            last_tick: None,
+            ceiling: 0.0,
+        }
+    }
+
+    pub fn settings(&self) -> LfoSettings {
+        self.settings
+    }
+
+    fn next_cycle(&mut self) {
+        self.held = (self.random.next_u32() >> 8) as f32 / (1 << 24) as f32;
+    }
+
+    pub fn advance(&mut self, now: u64) {
+        let elapsed = match self.last {
+            Some(last) => now.wrapping_sub(last),
+            None => 0,
+        };
+        self.last = Some(now);
+        match self.settings.rate {
+            Rate::Free(centihertz) => {
+                self.phase += elapsed as f32 * centihertz as f32 / 100_000_000.0;
+                if self.phase >= 1.0 {
+                    self.phase -= self.phase as u32 as f32;
+                    self.next_cycle();
+                }
+            }
+            Rate::Clock(ticks) => {
+                if self.tick_length > 0 && ticks > 0 {
+                    let step = elapsed as f32 / (self.tick_length as f32 * ticks as f32);
+                    self.phase = (self.phase + step).min(self.ceiling);
+                }
+            }
+        }
+    }
+
+    // ticks is the number of clock ticks since the last start
+    pub fn clock(&mut self, ticks: u32, now: u64) {
+        match self.last_tick {
+            Some(last) => self.tick_length = now.wrapping_sub(last),
+            None => {}
+        }
+        self.last_tick = Some(now);
+        self.last = Some(now);
+        match self.settings.rate {
+            Rate::Clock(period) if period > 0 => {
+                let position = ticks % period as u32;
+                if position == 0 {
+                    self.next_cycle();
+                }
+                self.phase = position as f32 / period as f32;
+                self.ceiling = (position + 1) as f32 / period as f32;
+            }
+            _ => {}
+        }
+    }
+
+    pub fn value(&self) -> f32 {
+        let phase = self.phase;
+        match self.settings.shape {
+            Shape::Sine if phase < 0.5 => 0.5 + 0.5 * half_sine(phase * 2.0),
+            Shape::Sine => 0.5 - 0.5 * half_sine(phase * 2.0 - 1.0),
+            Shape::Triangle if phase < 0.5 => phase * 2.0,
+            Shape::Triangle => 2.0 - phase * 2.0,
+            Shape::Saw => phase,
+            Shape::Square if phase < 0.5 => 1.0,
+            Shape::Square => 0.0,
+            Shape::Random => self.held,
+        }
+    }
+}
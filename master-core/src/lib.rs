@@ -0,0 +1,35 @@
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
+#![allow(dead_code)]
+#![allow(unused_imports)]
+#![allow(
+    clippy::needless_return,
+    clippy::single_match,
+    clippy::collapsible_match,
+    clippy::match_like_matches_macro,
+    clippy::new_without_default,
+    clippy::neg_multiply,
+    clippy::partialeq_to_none,
+    clippy::manual_is_multiple_of
+)]
+
+pub mod clock;
+pub mod commando_unit;
+pub mod console;
+pub mod drums;
+pub mod envelope;
+pub mod glide;
+pub mod lfo;
+pub mod midi_mapper;
+pub mod outs;
+pub mod player;
+pub mod priority;
+pub mod prorgrammer;
+pub mod quantizer;
+pub mod sink;
+#[cfg(any(test, feature = "std"))]
+pub mod smf;
+pub mod song;
+pub mod storage;
+pub mod sysex;
+pub mod usb_midi;
+pub mod utils;
@@ -0,0 +1,1189 @@
+use midly::live::{LiveEvent, SystemRealtime};
+use midly::num::{u4, u7};
+use midly::{MidiMessage, PitchBend};
+
+use crate::drums::{Chokes, DrumMap, Dynamics, GateSet, Triggers};
+use crate::envelope::{Adsr, EnvelopeSettings, ATTACK_CC, DECAY_CC, RELEASE_CC, SUSTAIN_CC};
+use crate::glide::{Glide, Portamento, SWITCH_CC, TIME_CC};
+use crate::lfo::{LfoSettings, Rate};
+use crate::outs::{Cv, Gate, OutputRequest, MAX_DETUNE_CENTS};
+use crate::priority::{Order, Priority};
+use crate::quantizer::{Quantizer, Scale, ROOT_CC, SCALE_CC};
+use crate::sink::OutputSink;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Port {
+    A,
+    B,
+    C,
+    D,
+}
+
+impl Port {
+    fn index(self) -> usize {
+        match self {
+            Port::A => 0,
+            Port::B => 1,
+            Port::C => 2,
+            Port::D => 3,
+        }
+    }
+
+    fn from_cv(cv: Cv) -> Self {
+        match cv {
+            Cv::A => Port::A,
+            Cv::B => Port::B,
+            Cv::C => Port::C,
+            Cv::D => Port::D,
+        }
+    }
+
+    fn from_gate(gate: Gate) -> Option<Self> {
+        match gate {
+            Gate::GateA => Some(Port::A),
+            Gate::GateB => Some(Port::B),
+            Gate::GateC => Some(Port::C),
+            Gate::GateD => Some(Port::D),
+            _ => None,
+        }
+    }
+
+    fn request_set_note(self, key: u7) -> OutputRequest {
+        return OutputRequest::SetNote(self.to_output_cv(), key.into());
+    }
+
+    fn request_set_val(self, val: f32) -> OutputRequest {
+        return OutputRequest::SetVal(self.to_output_cv(), val);
+    }
+
+    fn request_glide_note(self, key: u7, glide: Glide) -> OutputRequest {
+        return OutputRequest::GlideNote(self.to_output_cv(), key.into(), glide);
+    }
+
+    fn request_set_bend(self, volts: f32) -> OutputRequest {
+        return OutputRequest::SetBend(self.to_output_cv(), volts);
+    }
+
+    fn request_gate_on(self) -> OutputRequest {
+        return OutputRequest::GateOn(self.to_output_gate());
+    }
+
+    fn request_gate_off(self) -> OutputRequest {
+        return OutputRequest::GateOff(self.to_output_gate());
+    }
+
+    fn to_output_cv(self) -> Cv {
+        match self {
+            Port::A => Cv::A,
+            Port::B => Cv::B,
+            Port::C => Cv::C,
+            Port::D => Cv::D,
+        }
+    }
+
+    fn to_output_gate(self) -> Gate {
+        match self {
+            Port::A => Gate::GateA,
+            Port::B => Gate::GateB,
+            Port::C => Gate::GateC,
+            Port::D => Gate::GateD,
+        }
+    }
+}
+
+type PortMapping = [Option<Port>; 4];
+
+enum ChannelType {
+    Drumms,
+    Pitch([Option<Port>; 4]),
+    None,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Config {
+    drum_channel: u4,
+    port_mappings: [Option<PortMapping>; 4], // Maps channels to ports
+    vel_mappings: [Option<Port>; 4],         // Maps pitch ports to vel ports by index
+    aftertouch: Option<Port>,
+    mod_port: Option<Port>,
+}
+
+impl Config {
+    fn get_channel_type(&mut self, ch: u4) -> ChannelType {
+        if ch == self.drum_channel {
+            return ChannelType::Drumms;
+        }
+        match self.port_mappings.get(usize::from(ch.as_int())) {
+            Some(Some(ports)) => ChannelType::Pitch(*ports),
+            _ => ChannelType::None,
+        }
+    }
+
+    // Whether a voice, velocity or modulation goes out on the port
+    fn claims(&self, port: Port) -> bool {
+        let voice = self
+            .port_mappings
+            .iter()
+            .flatten()
+            .any(|ports| ports.contains(&Some(port)));
+        return voice
+            || self.vel_mappings.contains(&Some(port))
+            || self.aftertouch == Some(port)
+            || self.mod_port == Some(port);
+    }
+
+    // The pitched channel playing on the port and every port it has
+    fn owner(&self, port: Port) -> Option<(usize, PortMapping)> {
+        self.port_mappings
+            .iter()
+            .enumerate()
+            .find_map(|(ch, ports)| match ports {
+                Some(ports) if ports.contains(&Some(port)) => Some((ch, *ports)),
+                _ => None,
+            })
+    }
+
+    fn get_vel_mapping(&mut self, port: Port) -> Option<Port> {
+        return self.vel_mappings[port.index()];
+    }
+
+    pub fn four_poly() -> Self {
+        Config {
+            drum_channel: 4.into(),
+            port_mappings: [
+                Some([Some(Port::A), Some(Port::B), Some(Port::C), Some(Port::D)]),
+                None,
+                None,
+                None,
+            ],
+            vel_mappings: [None; 4],
+            aftertouch: None,
+            mod_port: None,
+        }
+    }
+
+    pub fn four_indie() -> Self {
+        Config {
+            drum_channel: 4.into(),
+            port_mappings: [
+                Some([Some(Port::A), None, None, None]),
+                Some([None, Some(Port::B), None, None]),
+                Some([None, None, Some(Port::C), None]),
+                Some([None, None, None, Some(Port::D)]),
+            ],
+            vel_mappings: [None; 4],
+            aftertouch: None,
+            mod_port: None,
+        }
+    }
+
+    pub fn two_poly() -> Self {
+        Config {
+            drum_channel: 4.into(),
+            port_mappings: [
+                Some([Some(Port::A), Some(Port::B), None, None]),
+                Some([None, None, Some(Port::C), Some(Port::D)]),
+                None,
+                None,
+            ],
+            vel_mappings: [None; 4],
+            aftertouch: None,
+            mod_port: None,
+        }
+    }
+
+    pub fn two_mono() -> Self {
+        Config {
+            drum_channel: 4.into(),
+            port_mappings: [
+                Some([Some(Port::A), None, None, None]),
+                Some([None, None, Some(Port::C), None]),
+                None,
+                None,
+            ],
+            vel_mappings: [Some(Port::B), None, Some(Port::D), None],
+            aftertouch: None,
+            mod_port: None,
+        }
+    }
+
+    pub fn two_fancy_mono() -> Self {
+        Config {
+            drum_channel: 4.into(),
+            port_mappings: [
+                Some([Some(Port::A), None, None, None]),
+                Some([None, Some(Port::B), None, None]),
+                None,
+                None,
+            ],
+            vel_mappings: [Some(Port::C), None, None, None],
+            aftertouch: None,
+            mod_port: Some(Port::D),
+        }
+    }
+
+    pub fn one_duo() -> Self {
+        Config {
+            drum_channel: 4.into(),
+            port_mappings: [
+                Some([Some(Port::A), Some(Port::B), None, None]),
+                None,
+                None,
+                None,
+            ],
+            vel_mappings: [Some(Port::C), Some(Port::D), None, None],
+            aftertouch: None,
+            mod_port: None,
+        }
+    }
+    pub fn one_mono() -> Self {
+        Config {
+            drum_channel: 4.into(),
+            port_mappings: [Some([Some(Port::A), None, None, None]), None, None, None],
+            vel_mappings: [Some(Port::B), None, None, None],
+            aftertouch: None,
+            mod_port: Some(Port::D),
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "four_poly" => Some(Self::four_poly()),
+            "four_indie" => Some(Self::four_indie()),
+            "two_poly" => Some(Self::two_poly()),
+            "two_mono" => Some(Self::two_mono()),
+            "two_fancy_mono" => Some(Self::two_fancy_mono()),
+            "one_duo" => Some(Self::one_duo()),
+            "one_mono" => Some(Self::one_mono()),
+            _ => None,
+        }
+    }
+
+    pub fn select_confg(play: bool, step: bool, rec: bool) -> Self {
+        match (play, step, rec) {
+            (false, false, false) => Self::two_fancy_mono(),
+            (false, false, true) => Self::one_mono(),
+            (false, true, false) => Self::four_indie(),
+            (false, true, true) => Self::four_poly(),
+            (true, false, false) => Self::two_mono(),
+            (true, false, true) => Self::two_poly(),
+            _ => Self::two_fancy_mono(),
+        }
+    }
+}
+
+const CAPACITY: usize = 16;
+pub const DEFAULT_BEND_RANGE: u8 = 2;
+pub const MAX_BEND_RANGE: u8 = 24;
+pub const PITCH_CHANNELS: u8 = 4;
+
+// How a pitched channel with more than one port hands out its voices. A free
+// port is picked first, and only when every port is busy is one taken from the
+// note it plays.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Assign {
+    RoundRobin, // The free port that has waited longest
+    ReuseKey,   // The free port that last played the same key, if there is one
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Steal {
+    Oldest,   // The port whose note started first
+    Quietest, // The port whose note came with the lowest velocity
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Allocation {
+    pub assign: Assign,
+    pub steal: Steal,
+}
+
+impl Assign {
+    pub fn name(self) -> &'static str {
+        match self {
+            Assign::RoundRobin => "roundrobin",
+            Assign::ReuseKey => "reuse",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "roundrobin" => Some(Assign::RoundRobin),
+            "reuse" => Some(Assign::ReuseKey),
+            _ => None,
+        }
+    }
+}
+
+impl Steal {
+    pub fn name(self) -> &'static str {
+        match self {
+            Steal::Oldest => "oldest",
+            Steal::Quietest => "quietest",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "oldest" => Some(Steal::Oldest),
+            "quietest" => Some(Steal::Quietest),
+            _ => None,
+        }
+    }
+}
+
+impl Allocation {
+    pub const fn new() -> Self {
+        Allocation {
+            assign: Assign::RoundRobin,
+            steal: Steal::Oldest,
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+struct TrackedMessage {
+    ts: u32, // Order relative to the other messages
+    port: Port,
+    key: u7,
+    vel: u7,
+}
+
+/*
+The keys held down on pitched channels and the ports they went to, modelled in
+voices.vdmsl. A port on a polyphonic channel holds one key at a time, a mono
+port holds every key that is down so that it can go back to them.
+*/
+struct TrackedSet {
+    active_messages: [Option<TrackedMessage>; CAPACITY],
+    last_keys: [Option<u7>; 4], // What each port played last, by port
+    assigned: [u32; 4],         // When each port last got a key, by port
+    insertions: u32,
+}
+
+impl TrackedSet {
+    fn new() -> Self {
+        Self {
+            active_messages: [None; CAPACITY],
+            last_keys: [None; 4],
+            assigned: [0; 4],
+            insertions: 0,
+        }
+    }
+
+    // A key that is already held on the port counts as pressed again
+    fn add(&mut self, key: u7, vel: u7, port: Port) -> Option<()> {
+        self.insertions += 1;
+        let tm = TrackedMessage {
+            ts: self.insertions,
+            port,
+            key,
+            vel,
+        };
+        self.last_keys[port.index()] = Some(key);
+        self.assigned[port.index()] = tm.ts;
+
+        let mut empty = None;
+        for i in 0..CAPACITY {
+            match self.active_messages[i] {
+                Some(held) if held.port == port && held.key == key => {
+                    self.active_messages[i] = Some(tm);
+                    return Some(());
+                }
+                Some(_) => {}
+                None if empty.is_none() => empty = Some(i),
+                None => {}
+            }
+        }
+        self.active_messages[empty?] = Some(tm);
+        return Some(());
+    }
+
+    fn count(&self) -> usize {
+        return self.active_messages.iter().flatten().count();
+    }
+
+    fn is_free(&self, port: Port) -> bool {
+        return !self
+            .active_messages
+            .iter()
+            .flatten()
+            .any(|tm| tm.port == port);
+    }
+
+    fn remove_oldest(&mut self) -> Option<TrackedMessage> {
+        let mut oldest: Option<(usize, u32)> = None;
+        for i in 0..CAPACITY {
+            match (self.active_messages[i], oldest) {
+                (Some(tm), Some((_, ts))) if ts <= tm.ts => {}
+                (Some(tm), _) => oldest = Some((i, tm.ts)),
+                (None, _) => {}
+            }
+        }
+        let (i, _) = oldest?;
+        return self.active_messages[i].take();
+    }
+
+    // Lets go of everything the port holds, for when its voice is stolen
+    fn clear_port(&mut self, port: Port) {
+        for tm in self.active_messages.iter_mut() {
+            match tm {
+                Some(TrackedMessage { port: p, .. }) if *p == port => *tm = None,
+                _ => {}
+            }
+        }
+    }
+
+    fn find_port(
+        &self,
+        assigned_ports: PortMapping,
+        key: u7,
+        allocation: Allocation,
+    ) -> Option<Port> {
+        let ports = assigned_ports.iter().flatten().copied();
+
+        let mut free: Option<Port> = None;
+        for port in ports.clone().filter(|port| self.is_free(*port)) {
+            if allocation.assign == Assign::ReuseKey && self.last_keys[port.index()] == Some(key) {
+                return Some(port);
+            }
+            match free {
+                Some(p) if self.assigned[p.index()] <= self.assigned[port.index()] => {}
+                _ => free = Some(port),
+            }
+        }
+        if free.is_some() {
+            return free;
+        }
+
+        // Every port is busy, compare the notes they play
+        let mut stolen: Option<(Port, TrackedMessage)> = None;
+        for port in ports {
+            let tm = match self.find_newest_by_port(port) {
+                Some(tm) => tm,
+                None => continue,
+            };
+            let better = match stolen {
+                None => true,
+                Some((_, other)) => match allocation.steal {
+                    Steal::Oldest => tm.ts < other.ts,
+                    Steal::Quietest => (tm.vel, tm.ts) < (other.vel, other.ts),
+                },
+            };
+            if better {
+                stolen = Some((port, tm));
+            }
+        }
+        return stolen.map(|(port, _)| port);
+    }
+
+    fn find_newest_by_port(&self, port: Port) -> Option<TrackedMessage> {
+        return self.find_by_priority(port, Order::Last);
+    }
+
+    // The key that should sound on the port out of the ones held there
+    fn find_by_priority(&self, port: Port, order: Order) -> Option<TrackedMessage> {
+        let mut winner: Option<TrackedMessage> = None;
+        for tm in self.active_messages.iter().flatten() {
+            if tm.port != port {
+                continue;
+            }
+            match winner {
+                Some(w) if !order.prefers(tm.key.as_int(), tm.ts, w.key.as_int(), w.ts) => {}
+                _ => winner = Some(*tm),
+            }
+        }
+        return winner;
+    }
+
+    // Takes out one message for the key at a time, the same key can be held
+    // on more than one port
+    fn remove(&mut self, lifted_key: u7, ports: PortMapping) -> Option<TrackedMessage> {
+        for i in 0..CAPACITY {
+            match self.active_messages[i] {
+                Some(tm) if tm.key == lifted_key && ports.contains(&Some(tm.port)) => {
+                    return self.active_messages[i].take();
+                }
+                _ => {}
+            }
+        }
+        return None;
+    }
+}
+
+pub struct MidiMapper<O: OutputSink> {
+    tracked_messages: TrackedSet,
+    config: Config,
+    quantizers: [Quantizer; 4],   // By port, kept when the config changes
+    portamentos: [Portamento; 4], // By port, like the quantizers
+    priorities: [Priority; 4],    // By channel
+    allocations: [Allocation; 4], // By channel
+    unisons: [bool; 4],           // By channel
+    detunes: [i8; 4],             // Cents, by port
+    drum_map: DrumMap,
+    dynamics: Dynamics,
+    accented: u128, // Drum notes holding the accent open, a bit each
+    chokes: Chokes,
+    open_drums: GateSet, // Gates the drums have opened, so chokes know what to close
+    triggers: Triggers,
+    bend_range: u8, // Semitones either way
+    lfos: [Option<LfoSettings>; 4],
+    envelopes: [Option<EnvelopeSettings>; 4], // A port has an LFO or an envelope
+    lfo_ticks: u32,                           // Since the last start
+    io_sender: O,
+    clock: u32,
+    ppq: u32,
+    divisor: u32, //At what interval do we emit a clock pulse
+}
+
+pub fn make_all_notes_off(channel: u8) -> LiveEvent<'static> {
+    LiveEvent::Midi {
+        channel: channel.into(),
+        message: MidiMessage::Controller {
+            controller: 123.into(),
+            value: 0.into(),
+        },
+    }
+}
+
+impl<O: OutputSink> MidiMapper<O> {
+    pub fn new(config: Config, io_sender: O) -> Self {
+        Self {
+            tracked_messages: TrackedSet::new(),
+            config,
+            quantizers: [Quantizer::off(); 4],
+            portamentos: [Portamento::off(); 4],
+            priorities: [Priority::last(); 4],
+            allocations: [Allocation::new(); 4],
+            unisons: [false; 4],
+            detunes: [0; 4],
+            drum_map: DrumMap::octave(),
+            dynamics: Dynamics::off(),
+            accented: 0,
+            chokes: Chokes::hi_hats(),
+            open_drums: GateSet::empty(),
+            triggers: Triggers::off(),
+            bend_range: DEFAULT_BEND_RANGE,
+            lfos: [None; 4],
+            envelopes: [None; 4],
+            lfo_ticks: 0,
+            io_sender,
+            clock: 0,
+            ppq: 24,
+            divisor: 16,
+        }
+    }
+
+    // Lets go of everything first since the ports might mean something else after
+    pub fn set_config(&mut self, config: Config) {
+        self.all_notes_off();
+        self.config = config;
+        for cv in [Cv::A, Cv::B, Cv::C, Cv::D] {
+            let port = Port::from_cv(cv);
+            if self.lfos[port.index()].is_some() && config.claims(port) {
+                self.lfos[port.index()] = None;
+                self.io_sender
+                    .try_send(OutputRequest::SetLfo(cv, None))
+                    .ok();
+            }
+            if self.envelopes[port.index()].is_some() && config.claims(port) {
+                self.envelopes[port.index()] = None;
+                self.io_sender
+                    .try_send(OutputRequest::SetEnvelope(cv, None))
+                    .ok();
+            }
+            if self.dynamics.velocity == Some(cv) && config.claims(port) {
+                self.dynamics.velocity = None;
+            }
+        }
+    }
+
+    pub fn lfo(&self, cv: Cv) -> Option<LfoSettings> {
+        return self.lfos[Port::from_cv(cv).index()];
+    }
+
+    // Only ports that the config leaves alone can take an LFO
+    pub fn set_lfo(&mut self, cv: Cv, lfo: Option<LfoSettings>) -> Option<()> {
+        let port = Port::from_cv(cv);
+        if self.config.claims(port) {
+            return None;
+        }
+        if lfo.is_some() {
+            self.envelopes[port.index()] = None;
+            if self.dynamics.velocity == Some(cv) {
+                self.dynamics.velocity = None;
+            }
+        }
+        self.lfos[port.index()] = lfo;
+        self.io_sender.try_send(OutputRequest::SetLfo(cv, lfo)).ok();
+        return Some(());
+    }
+
+    pub fn envelope(&self, cv: Cv) -> Option<EnvelopeSettings> {
+        return self.envelopes[Port::from_cv(cv).index()];
+    }
+
+    // Like LFOs only on ports the config leaves alone, and never on their own
+    // source
+    pub fn set_envelope(&mut self, cv: Cv, envelope: Option<EnvelopeSettings>) -> Option<()> {
+        let port = Port::from_cv(cv);
+        match envelope {
+            Some(settings) if settings.source == cv => return None,
+            Some(_) => self.lfos[port.index()] = None,
+            None => {}
+        }
+        if self.config.claims(port) {
+            return None;
+        }
+        if envelope.is_some() && self.dynamics.velocity == Some(cv) {
+            self.dynamics.velocity = None;
+        }
+        self.envelopes[port.index()] = envelope;
+        self.io_sender
+            .try_send(OutputRequest::SetEnvelope(cv, envelope))
+            .ok();
+        return Some(());
+    }
+
+    pub fn quantizer(&self, cv: Cv) -> Quantizer {
+        return self.quantizers[Port::from_cv(cv).index()];
+    }
+
+    // Applies from the next note on the port
+    pub fn set_quantizer(&mut self, cv: Cv, quantizer: Quantizer) {
+        self.quantizers[Port::from_cv(cv).index()] = quantizer;
+    }
+
+    pub fn portamento(&self, cv: Cv) -> Portamento {
+        return self.portamentos[Port::from_cv(cv).index()];
+    }
+
+    pub fn set_portamento(&mut self, cv: Cv, portamento: Portamento) {
+        self.portamentos[Port::from_cv(cv).index()] = portamento;
+    }
+
+    pub fn priorities(&self) -> [Priority; 4] {
+        return self.priorities;
+    }
+
+    // Only the channels that can carry pitch have one
+    pub fn set_priority(&mut self, ch: u8, priority: Priority) -> Option<()> {
+        *self.priorities.get_mut(ch as usize)? = priority;
+        return Some(());
+    }
+
+    pub fn allocations(&self) -> [Allocation; 4] {
+        return self.allocations;
+    }
+
+    pub fn set_allocation(&mut self, ch: u8, allocation: Allocation) -> Option<()> {
+        *self.allocations.get_mut(ch as usize)? = allocation;
+        return Some(());
+    }
+
+    pub fn unisons(&self) -> [bool; 4] {
+        return self.unisons;
+    }
+
+    // Every note of the channel goes to all of its ports. Held notes are let
+    // go first since they were given out the other way.
+    pub fn set_unison(&mut self, ch: u8, unison: bool) -> Option<()> {
+        if ch >= PITCH_CHANNELS {
+            return None;
+        }
+        self.all_notes_off();
+        self.unisons[ch as usize] = unison;
+        return Some(());
+    }
+
+    pub fn detune(&self, cv: Cv) -> i8 {
+        return self.detunes[Port::from_cv(cv).index()];
+    }
+
+    pub fn set_detune(&mut self, cv: Cv, cents: i8) {
+        let cents = cents.clamp(-MAX_DETUNE_CENTS, MAX_DETUNE_CENTS);
+        self.detunes[Port::from_cv(cv).index()] = cents;
+        self.io_sender
+            .try_send(OutputRequest::SetDetune(cv, cents))
+            .ok();
+    }
+
+    pub fn drum_map(&self) -> DrumMap {
+        return self.drum_map;
+    }
+
+    // Lets go of the gates of the old map, nothing would close them after
+    pub fn set_drum_map(&mut self, drum_map: DrumMap) {
+        self.release_drums();
+        self.drum_map = drum_map;
+    }
+
+    pub fn dynamics(&self) -> Dynamics {
+        return self.dynamics;
+    }
+
+    // Like LFOs the velocity only goes to a port the config leaves alone, and
+    // it takes the port over from an LFO or envelope
+    pub fn set_dynamics(&mut self, dynamics: Dynamics) -> Option<()> {
+        match dynamics.velocity {
+            Some(cv) => {
+                let port = Port::from_cv(cv);
+                if self.config.claims(port) {
+                    return None;
+                }
+                if self.lfos[port.index()].is_some() {
+                    self.set_lfo(cv, None);
+                }
+                if self.envelopes[port.index()].is_some() {
+                    self.set_envelope(cv, None);
+                }
+            }
+            None => {}
+        }
+        self.release_drums();
+        self.dynamics = dynamics;
+        return Some(());
+    }
+
+    pub fn chokes(&self) -> Chokes {
+        return self.chokes;
+    }
+
+    pub fn set_chokes(&mut self, chokes: Chokes) {
+        self.chokes = chokes;
+    }
+
+    pub fn triggers(&self) -> Triggers {
+        return self.triggers;
+    }
+
+    pub fn set_triggers(&mut self, triggers: Triggers) {
+        self.triggers = triggers;
+    }
+
+    pub fn bend_range(&self) -> u8 {
+        return self.bend_range;
+    }
+
+    pub fn set_bend_range(&mut self, semitones: u8) {
+        self.bend_range = semitones.min(MAX_BEND_RANGE);
+    }
+
+    pub fn handle_message(&mut self, msg: LiveEvent<'static>) {
+        match msg {
+            LiveEvent::Midi { channel, message } => match self.config.get_channel_type(channel) {
+                ChannelType::Drumms => match message {
+                    MidiMessage::NoteOff { key, vel } => {
+                        self.on_drumm(key.into(), vel.into(), false)
+                    }
+                    MidiMessage::NoteOn { key, vel } => self.on_drumm(key.into(), vel.into(), true),
+                    _ => {}
+                },
+                ChannelType::Pitch(ports) => {
+                    self.handle_pitched_channel(message, usize::from(channel.as_int()), ports)
+                }
+                ChannelType::None => {}
+            },
+            LiveEvent::Common(_) => {}
+            LiveEvent::Realtime(msg) => match msg {
+                SystemRealtime::TimingClock => self.tick(),
+                SystemRealtime::Start => {
+                    self.lfo_ticks = 0;
+                    self.flash_gate(Gate::Start);
+                }
+                SystemRealtime::Continue => self.flash_gate(Gate::Start),
+                SystemRealtime::Stop => {
+                    self.flash_gate(Gate::Stop);
+                    self.all_notes_off();
+                }
+                SystemRealtime::Reset => {
+                    self.all_notes_off();
+                    self.flash_gate(Gate::Stop);
+                    self.flash_gate(Gate::Start);
+                }
+                _ => {}
+            },
+        }
+    }
+
+    fn flash_gate(&mut self, gate: Gate) {
+        self.io_sender.try_send(OutputRequest::Flash(gate)).ok();
+    }
+
+    fn tick(&mut self) {
+        if (self.clock % ((self.ppq * 4) / self.divisor)) == 0 {
+            self.flash_gate(Gate::Clock);
+        }
+        self.clock += 1;
+        let synced = self.lfos.iter().flatten().any(|lfo| match lfo.rate {
+            Rate::Clock(_) => true,
+            Rate::Free(_) => false,
+        });
+        if synced {
+            self.io_sender
+                .try_send(OutputRequest::LfoClock(self.lfo_ticks))
+                .ok();
+        }
+        self.lfo_ticks = self.lfo_ticks.wrapping_add(1);
+    }
+
+    fn safe_key(&self, key: u7) -> u7 {
+        if key < 12 {
+            return 12.into();
+        }
+        return key;
+    }
+
+    fn handle_pitched_channel(&mut self, msg: MidiMessage, ch: usize, ports: PortMapping) {
+        match msg {
+            MidiMessage::NoteOn { key, vel } => self.on_note_on(self.safe_key(key), vel, ch, ports),
+            MidiMessage::NoteOff { key, vel } => {
+                self.on_note_off(self.safe_key(key), vel, ch, ports)
+            }
+            MidiMessage::Controller { controller, value } => match controller.as_int() {
+                1 => self.on_modwheel(value),
+                SCALE_CC => self.on_scale(Scale::from_cc(value.as_int()), ports),
+                ROOT_CC => self.on_root(value.as_int(), ports),
+                TIME_CC => self.on_glide_time(Glide::ms_from_cc(value.as_int()), ports),
+                SWITCH_CC => self.on_glide_switch(value >= 64, ports),
+                ATTACK_CC | DECAY_CC | SUSTAIN_CC | RELEASE_CC => {
+                    self.on_envelope_cc(controller.as_int(), value.as_int(), ports)
+                }
+                121 => self.on_pitch_bend(PitchBend::mid_raw_value(), ports),
+                123 => self.all_notes_off(),
+                _ => {}
+            },
+            MidiMessage::ChannelAftertouch { vel } => self.on_aftertouch(vel),
+            MidiMessage::PitchBend { bend } => self.on_pitch_bend(bend, ports),
+            _ => {}
+        }
+    }
+
+    fn on_aftertouch(&mut self, value: u7) {
+        match self.config.aftertouch {
+            Some(port) => {
+                self.io_sender
+                    .try_send(port.request_set_val(value.as_int() as f32 / 127.0))
+                    .ok();
+            }
+            None => {}
+        }
+    }
+
+    fn on_modwheel(&mut self, value: u7) {
+        match self.config.mod_port {
+            Some(port) => {
+                self.io_sender
+                    .try_send(port.request_set_val(value.as_int() as f32 / 127.0))
+                    .ok();
+            }
+            None => {}
+        }
+    }
+
+    // Every port of the channel follows, so notes that start mid bend are bent too
+    fn on_pitch_bend(&mut self, bend: PitchBend, ports: PortMapping) {
+        let volts = bend.as_f32() * self.bend_range as f32 / 12.0;
+        for port in ports.iter().flatten() {
+            self.io_sender.try_send(port.request_set_bend(volts)).ok();
+        }
+    }
+
+    fn on_glide_time(&mut self, ms: u16, ports: PortMapping) {
+        for port in ports.iter().flatten() {
+            self.portamentos[port.index()].glide.ms = ms;
+        }
+    }
+
+    fn on_glide_switch(&mut self, enabled: bool, ports: PortMapping) {
+        for port in ports.iter().flatten() {
+            self.portamentos[port.index()].enabled = enabled;
+        }
+    }
+
+    fn on_scale(&mut self, scale: Scale, ports: PortMapping) {
+        for port in ports.iter().flatten() {
+            self.quantizers[port.index()].scale = scale;
+        }
+    }
+
+    fn on_root(&mut self, root: u8, ports: PortMapping) {
+        for port in ports.iter().flatten() {
+            self.quantizers[port.index()].root = root % 12;
+        }
+    }
+
+    // Envelopes that follow the port go along with its gate
+    fn set_gate(&mut self, port: Port, open: bool) {
+        let request = if open {
+            port.request_gate_on()
+        } else {
+            port.request_gate_off()
+        };
+        self.io_sender.try_send(request).ok();
+        let source = port.to_output_cv();
+        for cv in [Cv::A, Cv::B, Cv::C, Cv::D] {
+            match self.envelopes[Port::from_cv(cv).index()] {
+                Some(envelope) if envelope.source == source => {
+                    self.io_sender
+                        .try_send(OutputRequest::TriggerEnvelope(cv, open))
+                        .ok();
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // CCs on a channel reach the envelopes that follow its ports
+    fn on_envelope_cc(&mut self, controller: u8, value: u8, ports: PortMapping) {
+        for cv in [Cv::A, Cv::B, Cv::C, Cv::D] {
+            let mut envelope = match self.envelopes[Port::from_cv(cv).index()] {
+                Some(envelope) if ports.contains(&Some(Port::from_cv(envelope.source))) => envelope,
+                _ => continue,
+            };
+            match controller {
+                ATTACK_CC => envelope.adsr.attack = Adsr::ms_from_cc(value),
+                DECAY_CC => envelope.adsr.decay = Adsr::ms_from_cc(value),
+                SUSTAIN_CC => envelope.adsr.sustain = Adsr::sustain_from_cc(value),
+                RELEASE_CC => envelope.adsr.release = Adsr::ms_from_cc(value),
+                _ => {}
+            }
+            self.envelopes[Port::from_cv(cv).index()] = Some(envelope);
+            self.io_sender
+                .try_send(OutputRequest::SetEnvelope(cv, Some(envelope)))
+                .ok();
+        }
+    }
+
+    // Legato is when the port still holds another note
+    fn set_note(&mut self, port: Port, key: u7, legato: bool) {
+        let mut note = self.quantizers[port.index()].quantize(key.as_int());
+        // Snapping down must not go below what safe_key lets through
+        if note < 12 {
+            note += 12;
+        }
+        let portamento = self.portamentos[port.index()];
+        let request = if portamento.glides(legato) {
+            port.request_glide_note(note.into(), portamento.glide)
+        } else {
+            port.request_set_note(note.into())
+        };
+        self.io_sender.try_send(request).ok();
+    }
+
+    // In unison the first port of the channel holds the keys for all of them
+    fn allocated_ports(&self, ch: usize, ports: PortMapping) -> PortMapping {
+        if !self.unisons[ch] {
+            return ports;
+        }
+        let mut lead = [None; 4];
+        lead[0] = ports.iter().flatten().next().copied();
+        return lead;
+    }
+
+    // The ports that play what the port was given
+    fn stacked_ports(&self, ch: usize, ports: PortMapping, port: Port) -> PortMapping {
+        if self.unisons[ch] {
+            return ports;
+        }
+        return [Some(port), None, None, None];
+    }
+
+    fn on_note_on(&mut self, key: u7, vel: u7, ch: usize, ports: PortMapping) {
+        if self.tracked_messages.count() >= CAPACITY {
+            // Nothing else will close the gates if that was all the port held.
+            // The oldest note may be on another channel, with its own unison.
+            match self.tracked_messages.remove_oldest() {
+                Some(tm) if self.tracked_messages.is_free(tm.port) => {
+                    match self.config.owner(tm.port) {
+                        Some((owner, owner_ports)) => {
+                            for port in self
+                                .stacked_ports(owner, owner_ports, tm.port)
+                                .into_iter()
+                                .flatten()
+                            {
+                                self.set_gate(port, false);
+                            }
+                        }
+                        None => self.set_gate(tm.port, false),
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let allocated = self.allocated_ports(ch, ports);
+        let port = match self
+            .tracked_messages
+            .find_port(allocated, key, self.allocations[ch])
+        {
+            Some(port) => port,
+            None => return,
+        };
+        // A stolen voice forgets its key, only a mono port goes back to them
+        let poly = allocated.iter().flatten().count() > 1;
+        if poly {
+            self.tracked_messages.clear_port(port);
+        }
+        let legato = self.tracked_messages.find_newest_by_port(port).is_some();
+        self.tracked_messages.add(key, vel, port);
+        // A held key that wins keeps sounding, the new one waits for it
+        match self
+            .tracked_messages
+            .find_by_priority(port, self.priorities[ch].order)
+        {
+            Some(tm) if tm.key != key => return,
+            _ => {}
+        }
+        for port in self.stacked_ports(ch, ports, port).into_iter().flatten() {
+            self.set_note(port, key, legato);
+            self.set_gate(port, true);
+            match self.config.get_vel_mapping(port) {
+                Some(port) => self
+                    .io_sender
+                    .try_send(port.request_set_val(vel.as_int() as f32 / 127.0))
+                    .ok(),
+                None => None,
+            };
+        }
+    }
+
+    fn on_note_off(&mut self, key: u7, _vel: u7, ch: usize, ports: PortMapping) {
+        let priority = self.priorities[ch];
+        while let Some(lifted) = self.tracked_messages.remove(key, ports) {
+            let next = self
+                .tracked_messages
+                .find_by_priority(lifted.port, priority.order);
+            for port in self
+                .stacked_ports(ch, ports, lifted.port)
+                .into_iter()
+                .flatten()
+            {
+                match next {
+                    // Lifting a key that was not sounding changes nothing
+                    Some(tm)
+                        if priority.order.prefers(
+                            tm.key.as_int(),
+                            tm.ts,
+                            key.as_int(),
+                            lifted.ts,
+                        ) => {}
+                    Some(tm) if priority.retrigger => {
+                        self.set_gate(port, false);
+                        self.set_note(port, tm.key, true);
+                        self.set_gate(port, true);
+                    }
+                    Some(tm) => self.set_note(port, tm.key, true),
+                    None => self.set_gate(port, false),
+                }
+            }
+        }
+    }
+
+    /*UNSURE*/
+    pub fn all_notes_off(&mut self) {
+        for tm in self.tracked_messages.active_messages {
+            match tm {
+                Some(TrackedMessage { port, .. }) => self.set_gate(port, false),
+                None => {}
+            }
+        }
+        // Only the first port of a unison channel is tracked
+        for ch in 0..4 {
+            match self.config.port_mappings[ch] {
+                Some(ports) if self.unisons[ch] => {
+                    for port in ports.into_iter().flatten().skip(1) {
+                        self.set_gate(port, false);
+                    }
+                }
+                _ => {}
+            }
+        }
+        self.release_drums();
+        self.tracked_messages = TrackedSet::new()
+    }
+
+    fn release_drums(&mut self) {
+        let drum_map = self.drum_map;
+        for drum in drum_map.notes() {
+            self.set_drum_gate(drum.gate, false);
+        }
+        if self.accented != 0 {
+            self.accented = 0;
+            self.set_drum_gate(Gate::Accent, false);
+        }
+    }
+
+    // Voice gates take their envelopes along, like they do for notes
+    fn set_drum_gate(&mut self, gate: Gate, state: bool) {
+        if state {
+            self.open_drums.insert(gate);
+        } else {
+            self.open_drums.remove(gate);
+        }
+        match (Port::from_gate(gate), self.triggers.length(gate)) {
+            (Some(port), _) => self.set_gate(port, state),
+            (None, Some(ms)) if state => {
+                self.io_sender
+                    .try_send(OutputRequest::Trigger(gate, ms))
+                    .ok();
+            }
+            (None, _) => {
+                match state {
+                    true => self.io_sender.try_send(OutputRequest::GateOn(gate)).ok(),
+                    false => self.io_sender.try_send(OutputRequest::GateOff(gate)).ok(),
+                };
+            }
+        }
+    }
+
+    // Triggered gates close on their own, but stay open as far as chokes know
+    fn release_drum_gate(&mut self, gate: Gate) {
+        match self.triggers.length(gate) {
+            Some(_) => {}
+            None => self.set_drum_gate(gate, false),
+        }
+    }
+
+    pub fn on_drumm(&mut self, note: u8, vel: u8, state: bool) {
+        let gate = match self.drum_map.gate(note) {
+            Some(gate) => gate,
+            None => return,
+        };
+        // A note on without velocity is how some gear says note off
+        let state = state && vel > 0;
+        let bit = 1u128 << (note & 0x7f);
+        if state {
+            match self.dynamics.velocity {
+                Some(cv) => {
+                    self.io_sender
+                        .try_send(OutputRequest::SetVal(cv, vel as f32 / 127.0))
+                        .ok();
+                }
+                None => {}
+            }
+            match self.dynamics.accent {
+                Some(threshold) if vel >= threshold => {
+                    self.accented |= bit;
+                    self.set_drum_gate(Gate::Accent, true);
+                }
+                _ => {}
+            }
+            let choked = self.chokes.choked_by(gate);
+            for open in self.open_drums.gates() {
+                if choked.contains(open) {
+                    self.set_drum_gate(open, false);
+                }
+            }
+            self.set_drum_gate(gate, true);
+        } else {
+            self.release_drum_gate(gate);
+            if self.accented & bit != 0 {
+                self.accented &= !bit;
+                if self.accented == 0 {
+                    self.release_drum_gate(Gate::Accent);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use crate::envelope::EnvelopeSettings;
+use crate::glide::Glide;
+use crate::lfo::LfoSettings;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Gate {
+    Kick,
+    OpenHH,
+    Clap,
+    Snare,
+    FX,
+    ClosedHH,
+    Accent,
+    Start,
+    Stop,
+    Clock,
+    GateA,
+    GateB,
+    GateC,
+    GateD,
+}
+
+impl Gate {
+    pub fn name(self) -> &'static str {
+        match self {
+            Gate::Kick => "kick",
+            Gate::OpenHH => "ohh",
+            Gate::Clap => "clap",
+            Gate::Snare => "snare",
+            Gate::FX => "fx",
+            Gate::ClosedHH => "chh",
+            Gate::Accent => "accent",
+            Gate::Start => "start",
+            Gate::Stop => "stop",
+            Gate::Clock => "clock",
+            Gate::GateA => "a",
+            Gate::GateB => "b",
+            Gate::GateC => "c",
+            Gate::GateD => "d",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "kick" => Some(Gate::Kick),
+            "ohh" => Some(Gate::OpenHH),
+            "clap" => Some(Gate::Clap),
+            "snare" => Some(Gate::Snare),
+            "fx" => Some(Gate::FX),
+            "chh" => Some(Gate::ClosedHH),
+            "accent" => Some(Gate::Accent),
+            "start" => Some(Gate::Start),
+            "stop" => Some(Gate::Stop),
+            "clock" => Some(Gate::Clock),
+            "a" => Some(Gate::GateA),
+            "b" => Some(Gate::GateB),
+            "c" => Some(Gate::GateC),
+            "d" => Some(Gate::GateD),
+            _ => None,
+        }
+    }
+}
+
+/**
+- 1V = C1(1)= MIDI note 24 = 32.703 Hz
+- 3V = C3 = MIDI note 48 = 130.81 Hz
+ */
+pub fn note_to_voltage(key: u8) -> f32 {
+    return (key - 12) as f32 / 12.0;
+}
+
+pub fn val_to_voltage(val: f32) -> f32 {
+    return val * 5.0;
+}
+
+pub const MAX_DETUNE_CENTS: i8 = 100;
+
+pub fn cents_to_voltage(cents: i8) -> f32 {
+    return cents as f32 / 1200.0;
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Cv {
+    A,
+    B,
+    C,
+    D,
+}
+
+impl Cv {
+    pub fn index(self) -> usize {
+        match self {
+            Cv::A => 0,
+            Cv::B => 1,
+            Cv::C => 2,
+            Cv::D => 3,
+        }
+    }
+}
+
+// One for every whole volt from 0 to 5
+pub const CALIBRATION_POINTS: usize = 6;
+pub const MAX_TRIM: i16 = 500;
+pub const CALIBRATION_SIZE: usize = CALIBRATION_POINTS * 2;
+
+/*
+What the output stage gets wrong, measured at every whole volt. A trim is how
+many millivolts to add to get the voltage asked for, in between two points the
+trims are blended. All zeros is a perfect output.
+ */
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Calibration {
+    pub trims: [i16; CALIBRATION_POINTS],
+}
+
+impl Calibration {
+    pub const fn linear() -> Self {
+        Calibration {
+            trims: [0; CALIBRATION_POINTS],
+        }
+    }
+
+    pub fn apply(&self, voltage: f32) -> f32 {
+        let last = (CALIBRATION_POINTS - 1) as f32;
+        let clamped = voltage.clamp(0.0, last);
+        let below = (clamped as usize).min(CALIBRATION_POINTS - 2);
+        let between = clamped - below as f32;
+        let trim =
+            self.trims[below] as f32 * (1.0 - between) + self.trims[below + 1] as f32 * between;
+        return voltage + trim / 1000.0;
+    }
+
+    pub fn encode(&self, out: &mut [u8; CALIBRATION_SIZE]) {
+        for (i, trim) in self.trims.iter().enumerate() {
+            out[i * 2..i * 2 + 2].copy_from_slice(&trim.to_le_bytes());
+        }
+    }
+
+    pub fn decode(raw: &[u8]) -> Option<Self> {
+        if raw.len() != CALIBRATION_SIZE {
+            return None;
+        }
+        let mut trims = [0; CALIBRATION_POINTS];
+        for (i, trim) in trims.iter_mut().enumerate() {
+            *trim = i16::from_le_bytes([raw[i * 2], raw[i * 2 + 1]]);
+            if !(-MAX_TRIM..=MAX_TRIM).contains(trim) {
+                return None;
+            }
+        }
+        return Some(Calibration { trims });
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum OutputRequest {
+    GateOn(Gate),
+    GateOff(Gate),
+    SetNote(Cv, u8),
+    GlideNote(Cv, u8, Glide), // Like SetNote but gets there over time
+    SetVal(Cv, f32),
+    SetBend(Cv, f32),  // In volts, added to the note until the next bend
+    SetDetune(Cv, i8), // In cents, added to every note on the port
+    Flash(Gate),
+    Trigger(Gate, u8), // Like Flash but open for the given ms
+    SetLfo(Cv, Option<LfoSettings>),
+    LfoClock(u32), // Ticks since the last start
+    SetEnvelope(Cv, Option<EnvelopeSettings>),
+    TriggerEnvelope(Cv, bool), // The gate it follows opened or closed
+    Calibrate(Cv, Calibration),
+    // Holds the port at a calibration point until None, notes are ignored meanwhile
+    Reference(Cv, Option<u8>),
+}
@@ -0,0 +1,782 @@
+use core::cmp::Ordering;
+use core::ops::Not;
+
+use heapless::spsc::Queue;
+use heapless::Vec;
+use midly::live::LiveEvent;
+use midly::MidiMessage;
+
+use crate::midi_mapper::make_all_notes_off;
+use crate::outs::{Gate, OutputRequest};
+use crate::sink::{MidiSink, OutputSink};
+use crate::utils::midi_utils::{decode_message, encode_message};
+use crate::utils::random::Random;
+
+type Ticks = u32;
+
+pub const SUBS_PER_STEP: u32 = 128;
+pub const STEP_CAP: usize = 8;
+pub const MAX_LENGTH: usize = 32;
+pub const INITIAL_LENGTH: u8 = 16;
+// Swing is how many percent of a pair of steps goes to the first one
+pub const STRAIGHT: u8 = 50;
+pub const MAX_SWING: u8 = 75;
+// Step, sub, the three message bytes and the chance
+pub(crate) const EVENT_SIZE: usize = 6;
+// Percent, anything from here up always plays
+pub const ALWAYS: u8 = 100;
+pub const MAX_RATCHET: u8 = 8;
+pub const PATTERN_SLOTS: usize = 4;
+// Length, divisor, event count, every event and then the ratchet of every step
+pub const PATTERN_SIZE: usize = 4 + MAX_LENGTH * STEP_CAP * EVENT_SIZE + MAX_LENGTH;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct TimeStamp {
+    pub step: u32,
+    pub sub: u32,
+}
+
+impl PartialOrd for TimeStamp {
+    // This does not handle last step....
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+        Some(if self.step < other.step {
+            Ordering::Less
+        } else if self.step > other.step {
+            Ordering::Greater
+        } else {
+            if self.sub < other.sub {
+                Ordering::Less
+            } else if self.sub > other.sub {
+                Ordering::Greater
+            } else {
+                Ordering::Equal
+            }
+        })
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Event {
+    midi_event: LiveEvent<'static>,
+    ts: TimeStamp,
+    chance: u8,
+}
+
+impl Event {
+    fn replaces(self, other: Event) -> bool {
+        if self.ts.step == other.ts.step {
+            match (self.midi_event, other.midi_event) {
+                (
+                    LiveEvent::Midi {
+                        channel: c1,
+                        message: m1,
+                    },
+                    LiveEvent::Midi {
+                        channel: c2,
+                        message: m2,
+                    },
+                ) if c1 == c2 => match (m1, m2) {
+                    (
+                        MidiMessage::NoteOff { key: k1, .. },
+                        MidiMessage::NoteOff { key: k2, .. },
+                    ) => k1 == k2,
+                    (MidiMessage::NoteOn { key: k1, .. }, MidiMessage::NoteOn { key: k2, .. }) => {
+                        k1 == k2
+                    }
+                    _ => false,
+                },
+                _ => false,
+            }
+        } else {
+            false
+        }
+    }
+}
+
+type Step = [Option<Event>; STEP_CAP];
+struct Sequence {
+    steps: [Step; MAX_LENGTH],
+    // How many times each step plays within itself
+    ratchets: [u8; MAX_LENGTH],
+    channel: u8,
+    overflow: Queue<LiveEvent<'static>, 8>,
+    random: Random,
+    // Keys whose note on lost the roll, their note off goes too
+    skipped: u128,
+}
+
+impl Sequence {
+    fn new(channel: u8) -> Self {
+        Sequence {
+            steps: [[None; STEP_CAP]; MAX_LENGTH],
+            ratchets: [1; MAX_LENGTH],
+            channel,
+            overflow: Queue::new(),
+            random: Random::new(channel as u32 + 1),
+            skipped: 0,
+        }
+    }
+
+    fn clear_queue(&mut self) {
+        self.overflow = Queue::new();
+        self.skipped = 0;
+    }
+
+    // Only note ons roll, a note off plays whenever its note on did
+    fn rolls(&mut self, event: Event) -> bool {
+        match event.midi_event {
+            LiveEvent::Midi {
+                message: MidiMessage::NoteOn { key, .. },
+                ..
+            } => {
+                let bit = 1u128 << key.as_int();
+                if self.random.chance(event.chance) {
+                    self.skipped &= !bit;
+                    true
+                } else {
+                    self.skipped |= bit;
+                    false
+                }
+            }
+            LiveEvent::Midi {
+                message: MidiMessage::NoteOff { key, .. },
+                ..
+            } => {
+                let bit = 1u128 << key.as_int();
+                let skip = self.skipped & bit != 0;
+                self.skipped &= !bit;
+                !skip
+            }
+            _ => self.random.chance(event.chance),
+        }
+    }
+
+    fn set_chance(&mut self, step: usize, key: u8, chance: u8) {
+        for event in self.steps[step].iter_mut().flatten() {
+            match event.midi_event {
+                LiveEvent::Midi {
+                    message: MidiMessage::NoteOn { key: k, .. },
+                    ..
+                } if k == key => event.chance = chance,
+                _ => {}
+            }
+        }
+    }
+
+    fn clear_step(&mut self, step: usize) {
+        self.steps[step] = [None; STEP_CAP];
+        self.ratchets[step] = 1;
+    }
+
+    fn count(&self) -> u32 {
+        let mut count = 0;
+        for step in self.steps {
+            for me in step {
+                match me {
+                    Some(_) => count += 1,
+                    None => {}
+                }
+            }
+        }
+        return count;
+    }
+
+    fn insert(&mut self, event: Event) {
+        for i in 0..STEP_CAP {
+            match self.steps[event.ts.step as usize][i] {
+                Some(e) if event.replaces(e) => {
+                    self.steps[event.ts.step as usize][i] = Some(event);
+                    return;
+                }
+                None => {
+                    self.steps[event.ts.step as usize][i] = Some(event);
+                    return;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Only channel messages are stored, which is all that gets inserted anyway
+    fn encode(&self, buf: &mut [u8]) -> usize {
+        let mut count: u16 = 0;
+        let mut at = 2;
+        for step in self.steps {
+            for maybe_event in step {
+                match maybe_event {
+                    Some(Event {
+                        midi_event: LiveEvent::Midi { channel, message },
+                        ts,
+                        chance,
+                    }) => {
+                        let raw = encode_message(channel, message);
+                        buf[at..at + EVENT_SIZE].copy_from_slice(&[
+                            ts.step as u8,
+                            ts.sub as u8,
+                            raw[0],
+                            raw[1],
+                            raw[2],
+                            chance,
+                        ]);
+                        at += EVENT_SIZE;
+                        count += 1;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        buf[0..2].copy_from_slice(&count.to_le_bytes());
+        buf[at..at + MAX_LENGTH].copy_from_slice(&self.ratchets);
+        return at + MAX_LENGTH;
+    }
+
+    fn decode(buf: &[u8], channel: u8) -> Option<Self> {
+        if buf.len() < 2 {
+            return None;
+        }
+        let count = u16::from_le_bytes([buf[0], buf[1]]) as usize;
+        if buf.len() < 2 + count * EVENT_SIZE {
+            return None;
+        }
+        let mut sequence = Sequence::new(channel);
+        // Patterns from before ratchets end with the events
+        match buf.get(2 + count * EVENT_SIZE..2 + count * EVENT_SIZE + MAX_LENGTH) {
+            Some(ratchets) => {
+                if ratchets.iter().any(|r| *r == 0 || *r > MAX_RATCHET) {
+                    return None;
+                }
+                sequence.ratchets.copy_from_slice(ratchets);
+            }
+            None => {}
+        }
+        for raw in buf[2..2 + count * EVENT_SIZE].chunks(EVENT_SIZE) {
+            if raw[0] as usize >= MAX_LENGTH || raw[1] as u32 > SUBS_PER_STEP || raw[5] > ALWAYS {
+                return None;
+            }
+            let (channel, message) = decode_message([raw[2], raw[3], raw[4]])?;
+            sequence.insert(Event {
+                midi_event: LiveEvent::Midi { channel, message },
+                ts: TimeStamp {
+                    step: raw[0] as u32,
+                    sub: raw[1] as u32,
+                },
+                chance: raw[5],
+            });
+        }
+        return Some(sequence);
+    }
+
+    // Everything from ts1 up to but not including ts2, so that an event right on
+    // a tick does not come out twice
+    fn emit<M: MidiSink>(&mut self, ts1: TimeStamp, ts2: TimeStamp, sender: &mut M) {
+        let mut emitted_ts: Option<TimeStamp> = None;
+
+        self.emit_step(
+            ts1.step,
+            |ts| ts >= ts1 && (ts < ts2 || ts1 > ts2),
+            &mut emitted_ts,
+            sender,
+        );
+        if ts1.step != ts2.step {
+            self.emit_step(ts2.step, |ts| ts < ts2, &mut emitted_ts, sender);
+        }
+
+        if emitted_ts == None {
+            match self.overflow.dequeue() {
+                Some(event) => {
+                    sender.try_send(event).ok();
+                }
+                None => {}
+            }
+        }
+    }
+
+    // A ratcheted step is squeezed into the first part of itself and played
+    // that many times over
+    fn emit_step<M: MidiSink, F: Fn(TimeStamp) -> bool>(
+        &mut self,
+        step: u32,
+        within: F,
+        emitted_ts: &mut Option<TimeStamp>,
+        sender: &mut M,
+    ) {
+        let ratchet = self.ratchets[step as usize] as u32;
+        for event in self.steps[step as usize].into_iter().flatten() {
+            for repeat in 0..ratchet {
+                let ts = TimeStamp {
+                    step,
+                    sub: (event.ts.sub + repeat * SUBS_PER_STEP) / ratchet,
+                };
+                if within(ts) && self.rolls(event) {
+                    if *emitted_ts == None || Some(ts) == *emitted_ts {
+                        sender.try_send(event.midi_event).ok();
+                        *emitted_ts = Some(ts)
+                    } else {
+                        self.overflow.enqueue(event.midi_event).ok();
+                    }
+                }
+            }
+        }
+    }
+}
+
+enum State {
+    Playing,
+    Stopped,
+}
+
+const PPQ: Ticks = 24;
+
+// A step has to be a whole number of ticks, anything else ends up with no
+// ticks per step or with a divisor that does not read back the same
+pub fn is_divisor(divisor: u8) -> bool {
+    divisor > 0 && (PPQ * 4) % divisor as Ticks == 0
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum PlayerAction {
+    Play,
+    Continue,
+    Tick,
+    Stop,
+    Locate(u32), // Ticks from the start, only while stopped
+    SetDivisor(u8),
+    SetLength(u8),
+    SetSwing(Option<u8>), // None follows the default
+    SetDefaultSwing(u8),
+    Insert(LiveEvent<'static>, u32, f32),
+    SetChance(u32, u8, u8), // Step, key and percent for the note on there
+    SetRatchet(u32, u8),    // Step and how many times it plays
+    ClearStep(u32),
+    ClearPattern,
+    ToggleMute,
+    ToggleHold,  // Local clock will not update
+    SoftRestart, // Local clock sat to 0
+    Snap,        // Local clock syncronizes with global one
+    Save,        // Handled by whoever owns the storage
+    Load,
+    SelectSlot(u8),    // Takes over when the current loop is done
+    SongPosition(u16), // Handled by the arranger
+    SongSelect(u8),
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum PlayerMessage {
+    Broadcast(PlayerAction),
+    Action(u8, PlayerAction),
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Modal {
+    True,
+    WillBeFalse,
+    WillBeTrue,
+    False,
+}
+
+impl Not for Modal {
+    type Output = Self;
+    fn not(self) -> Self::Output {
+        match self {
+            Modal::True | Modal::WillBeTrue => Modal::WillBeFalse,
+            _ => Modal::WillBeTrue,
+        }
+    }
+}
+
+impl Modal {
+    fn change(self) -> Self {
+        match self {
+            Modal::WillBeFalse => Modal::False,
+            Modal::WillBeTrue => Modal::True,
+            _ => self,
+        }
+    }
+}
+
+pub struct Player<M: MidiSink, O: OutputSink> {
+    channel: u8,
+    length: u8,
+    clock: Ticks,
+    global_clock: Ticks,
+    state: State,
+    sequences: [Sequence; PATTERN_SLOTS],
+    slot: usize,
+    next_slot: usize,
+    switching: Modal,
+    pps: Ticks,
+    midi_sender: M,
+    output_sender: O,
+    mute: bool,
+    hold: Modal,
+    snap: bool,
+    should_restart: bool,
+    swing: Option<u8>,
+    default_swing: u8,
+}
+
+impl<M: MidiSink, O: OutputSink> Player<M, O> {
+    pub fn new(channel: u8, divisor: u32, midi_sender: M, output_sender: O) -> Self {
+        Player {
+            length: INITIAL_LENGTH,
+            clock: 0,
+            global_clock: 0,
+            state: State::Stopped,
+            sequences: core::array::from_fn(|_| Sequence::new(channel)),
+            slot: 0,
+            next_slot: 0,
+            switching: Modal::False,
+            pps: (PPQ * 4) / divisor,
+            midi_sender,
+            output_sender,
+            channel,
+            mute: false,
+            hold: Modal::False,
+            snap: false,
+            should_restart: false,
+            swing: None,
+            default_swing: STRAIGHT,
+        }
+    }
+
+    pub fn channel(&self) -> u8 {
+        self.channel
+    }
+
+    pub fn divisor(&self) -> u8 {
+        ((PPQ * 4) / self.pps) as u8
+    }
+
+    pub fn length(&self) -> u8 {
+        self.length
+    }
+
+    // How many ticks it takes for the pattern to come around
+    pub fn loop_ticks(&self) -> u32 {
+        self.pps * self.length as u32
+    }
+
+    pub fn step(&self) -> u32 {
+        self.get_step(self.clock)
+    }
+
+    pub fn is_playing(&self) -> bool {
+        match self.state {
+            State::Playing => true,
+            State::Stopped => false,
+        }
+    }
+
+    pub fn swing(&self) -> u8 {
+        self.swing.unwrap_or(self.default_swing)
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.mute
+    }
+
+    pub fn slot(&self) -> usize {
+        self.slot
+    }
+
+    // The slot that takes over at the end of the loop, if any
+    pub fn next_slot(&self) -> Option<usize> {
+        match self.switching {
+            Modal::WillBeTrue => Some(self.next_slot),
+            _ => None,
+        }
+    }
+
+    // The local clock stands still in every state but False
+    pub fn is_held(&self) -> bool {
+        self.hold != Modal::False
+    }
+
+    // In storage order, which is by step but not by sub
+    pub fn events(&self) -> impl Iterator<Item = (TimeStamp, LiveEvent<'static>)> + '_ {
+        self.events_in(self.slot)
+    }
+
+    // slot has to be below PATTERN_SLOTS
+    pub fn events_in(
+        &self,
+        slot: usize,
+    ) -> impl Iterator<Item = (TimeStamp, LiveEvent<'static>)> + '_ {
+        self.sequences[slot]
+            .steps
+            .iter()
+            .flatten()
+            .flatten()
+            .map(|e| (e.ts, e.midi_event))
+    }
+
+    pub fn ratchet(&self, step: u32) -> u8 {
+        self.sequences[self.slot].ratchets[step as usize % MAX_LENGTH]
+    }
+
+    // Rolls come out the same every time for the same seed
+    pub fn seed(&mut self, seed: u32) {
+        for sequence in self.sequences.iter_mut() {
+            sequence.random = Random::new(seed);
+        }
+    }
+
+    // buf needs room for PATTERN_SIZE bytes, returns how many were used
+    pub fn export_pattern(&self, buf: &mut [u8]) -> usize {
+        self.export_slot(self.slot, buf)
+    }
+
+    pub fn import_pattern(&mut self, buf: &[u8]) -> Option<()> {
+        self.import_slot(self.slot, buf)
+    }
+
+    // Length and divisor belong to the player, so every slot carries them but
+    // only importing the selected slot sets them. slot has to be below
+    // PATTERN_SLOTS.
+    pub fn export_slot(&self, slot: usize, buf: &mut [u8]) -> usize {
+        buf[0] = self.length;
+        buf[1] = self.divisor();
+        return 2 + self.sequences[slot].encode(&mut buf[2..]);
+    }
+
+    pub fn import_slot(&mut self, slot: usize, buf: &[u8]) -> Option<()> {
+        if slot >= PATTERN_SLOTS
+            || buf.len() < 2
+            || buf[0] == 0
+            || buf[0] as usize > MAX_LENGTH
+            || !is_divisor(buf[1])
+        {
+            return None;
+        }
+        let mut sequence = Sequence::decode(&buf[2..], self.channel)?;
+        sequence.random = self.sequences[slot].random;
+        self.sequences[slot] = sequence;
+        if slot == self.slot {
+            self.length = buf[0];
+            self.pps = (PPQ * 4) / (buf[1] as u32);
+            self.midi_sender
+                .try_send(make_all_notes_off(self.channel))
+                .ok();
+        }
+        return Some(());
+    }
+
+    pub fn handle_message(&mut self, msg: PlayerMessage) {
+        match msg {
+            PlayerMessage::Action(ch, _) if ch != self.channel => {}
+            PlayerMessage::Broadcast(action) | PlayerMessage::Action(_, action) => match action {
+                PlayerAction::Play | PlayerAction::Continue => self.play(),
+                PlayerAction::Locate(ticks) => match self.state {
+                    State::Stopped => {
+                        self.clock = ticks;
+                        self.global_clock = ticks;
+                    }
+                    State::Playing => {}
+                },
+                PlayerAction::Tick => self.tick(),
+                PlayerAction::Stop => {
+                    self.midi_sender
+                        .try_send(make_all_notes_off(self.channel))
+                        .ok();
+                    self.stop()
+                }
+                PlayerAction::Insert(e, s, o) => self.insert(e, s, o),
+                PlayerAction::SetDivisor(d) => {
+                    if is_divisor(d) {
+                        self.pps = (PPQ * 4) / (d as u32)
+                    }
+                }
+                PlayerAction::SetLength(length) => {
+                    if length > 0 && length <= 32 {
+                        self.length = length
+                    }
+                }
+                PlayerAction::SetSwing(swing) => match swing {
+                    Some(swing) if !(STRAIGHT..=MAX_SWING).contains(&swing) => {}
+                    _ => self.swing = swing,
+                },
+                PlayerAction::SetDefaultSwing(swing) => {
+                    if (STRAIGHT..=MAX_SWING).contains(&swing) {
+                        self.default_swing = swing
+                    }
+                }
+                PlayerAction::SetChance(step, key, chance) => {
+                    if step < MAX_LENGTH as u32 && chance <= ALWAYS {
+                        self.sequences[self.slot].set_chance(step as usize, key, chance)
+                    }
+                }
+                PlayerAction::SetRatchet(step, ratchet) => {
+                    if step < MAX_LENGTH as u32 && ratchet > 0 && ratchet <= MAX_RATCHET {
+                        self.sequences[self.slot].ratchets[step as usize] = ratchet
+                    }
+                }
+                PlayerAction::ClearStep(step) => {
+                    self.sequences[self.slot].clear_step(step as usize)
+                }
+                PlayerAction::ClearPattern => {
+                    let random = self.sequences[self.slot].random;
+                    self.sequences[self.slot] = Sequence::new(self.channel);
+                    self.sequences[self.slot].random = random;
+                }
+                PlayerAction::ToggleMute => {
+                    self.mute = !self.mute;
+                    if self.mute {
+                        self.midi_sender
+                            .try_send(make_all_notes_off(self.channel))
+                            .ok();
+                    }
+                }
+                PlayerAction::ToggleHold => self.hold = !self.hold,
+                PlayerAction::SoftRestart => self.should_restart = true,
+                PlayerAction::Snap => {
+                    self.hold = Modal::WillBeFalse;
+                    self.snap = true;
+                }
+                PlayerAction::Save | PlayerAction::Load => {}
+                PlayerAction::SelectSlot(slot) => self.select_slot(slot as usize),
+                PlayerAction::SongPosition(_) | PlayerAction::SongSelect(_) => {}
+            },
+        }
+    }
+
+    fn update_modals(&mut self, change: bool) {
+        if change {
+            self.hold = self.hold.change();
+            if self.hold == Modal::False && self.snap {
+                self.clock = self.global_clock;
+                self.snap = false;
+            }
+            if self.should_restart {
+                self.clock = 0;
+                self.should_restart = false;
+            }
+        }
+    }
+
+    fn tick(&mut self) {
+        match self.state {
+            State::Playing => {
+                let did_change =
+                    self.get_step(self.global_clock) != self.get_step(self.global_clock + 1);
+                self.global_clock += 1;
+                let old_ts = self.get_ts();
+                self.update_modals(did_change);
+                self.clock += if self.hold == Modal::False { 1 } else { 0 };
+                if !self.mute && self.hold == Modal::False {
+                    self.sequences[self.slot].emit(old_ts, self.get_ts(), &mut self.midi_sender)
+                };
+                // Everything up to the end of the loop came from the old slot
+                if self.hold == Modal::False && self.clock % self.loop_ticks() == 0 {
+                    self.switching = self.switching.change();
+                    if self.switching == Modal::True {
+                        self.switch_slot();
+                    }
+                }
+            }
+            State::Stopped => {}
+        }
+    }
+
+    fn select_slot(&mut self, slot: usize) {
+        if slot >= PATTERN_SLOTS {
+            return;
+        }
+        match self.state {
+            State::Stopped => {
+                self.next_slot = slot;
+                self.switch_slot();
+            }
+            State::Playing if slot == self.slot => self.switching = Modal::False,
+            State::Playing => {
+                self.next_slot = slot;
+                self.switching = Modal::WillBeTrue;
+            }
+        }
+    }
+
+    // Notes tied over the end of the loop have nothing to end them in the new slot
+    fn switch_slot(&mut self) {
+        self.sequences[self.slot].clear_queue();
+        self.midi_sender
+            .try_send(make_all_notes_off(self.channel))
+            .ok();
+        self.slot = self.next_slot;
+        self.switching = Modal::False;
+    }
+
+    fn play(&mut self) {
+        match self.state {
+            State::Playing => {}
+            State::Stopped => self.state = State::Playing,
+        }
+    }
+
+    fn stop(&mut self) {
+        match self.state {
+            State::Playing => {
+                /*Issue all notes off*/
+                self.clock = 0;
+                self.global_clock = 0; // Assming that all channels receives this
+                self.state = State::Stopped;
+                self.hold = Modal::False;
+                self.mute = false;
+                self.sequences[self.slot].clear_queue();
+                // Nothing left to wait for
+                if self.switching == Modal::WillBeTrue {
+                    self.switch_slot();
+                }
+            }
+            State::Stopped => {}
+        }
+    }
+
+    fn get_step(&self, tick: Ticks) -> u32 {
+        (tick / self.pps) % self.length as u32
+    }
+
+    /*
+    Where in the pattern the local clock is. With swing the first step of every
+    pair is stretched and the second one squeezed, so the second one starts
+    late while the pair as a whole keeps time. Everything in the pattern moves
+    with it, note offs included.
+    */
+    fn get_ts(&self) -> TimeStamp {
+        let step = self.get_step(self.clock);
+        let first = step - step % 2;
+        // A lone last step has nothing to swing against
+        if self.swing() == STRAIGHT || first + 1 >= self.length as u32 {
+            return TimeStamp {
+                step,
+                sub: (self.clock % self.pps) * SUBS_PER_STEP / self.pps,
+            };
+        }
+        let pair = 2 * self.pps * 100;
+        let at = ((self.clock % self.loop_ticks()) - first * self.pps) * 100;
+        let stretched = 2 * self.pps * self.swing() as u32;
+        if at < stretched {
+            return TimeStamp {
+                step: first,
+                sub: at * SUBS_PER_STEP / stretched,
+            };
+        }
+        return TimeStamp {
+            step: first + 1,
+            sub: (at - stretched) * SUBS_PER_STEP / (pair - stretched),
+        };
+    }
+
+    pub fn insert(&mut self, event: LiveEvent, step: u32, _offset: f32) {
+        if step >= self.length as u32 {
+            panic!();
+        }
+        let offset = if _offset > 1.0 { 1.0 } else { _offset };
+        self.sequences[self.slot].insert(Event {
+            midi_event: event.to_static(),
+            ts: TimeStamp {
+                step,
+                sub: (offset * SUBS_PER_STEP as f32) as u32,
+            },
+            chance: ALWAYS,
+        });
+    }
+}
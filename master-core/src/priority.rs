@@ -0,0 +1,59 @@
+/*
+Which of the keys held on a port gets to sound. A mono channel holds more than
+one key on its port whenever keys overlap, and the priority picks the winner
+both when a key goes down and when one is lifted. Keys that lose are kept and
+come back when the winner is lifted.
+
+With retrigger the gate closes and opens again when a lifted key hands over to
+one that is still held, otherwise only the pitch moves.
+*/
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Order {
+    Last,
+    Lowest,
+    Highest,
+}
+
+impl Order {
+    pub fn name(self) -> &'static str {
+        match self {
+            Order::Last => "last",
+            Order::Lowest => "lowest",
+            Order::Highest => "highest",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "last" => Some(Order::Last),
+            "lowest" => Some(Order::Lowest),
+            "highest" => Some(Order::Highest),
+            _ => None,
+        }
+    }
+
+    // Keys come with the order they went down in, later is larger
+    pub fn prefers(self, key: u8, ts: u32, than_key: u8, than_ts: u32) -> bool {
+        match self {
+            Order::Last => ts > than_ts,
+            Order::Lowest => key < than_key,
+            Order::Highest => key > than_key,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Priority {
+    pub order: Order,
+    pub retrigger: bool,
+}
+
+impl Priority {
+    pub const fn last() -> Self {
+        Priority {
+            order: Order::Last,
+            retrigger: false,
+        }
+    }
+}
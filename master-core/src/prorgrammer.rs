@@ -2,9 +2,9 @@ use midly::live::LiveEvent;
 use midly::MidiMessage;
 
 use crate::commando_unit::Operation;
-use crate::midi_master::MessageSender;
 use crate::outs::{Gate, OutputRequest};
 use crate::player::{PlayerAction, PlayerMessage, INITIAL_LENGTH, MAX_LENGTH};
+use crate::sink::{OutputSink, PlayerSink};
 use crate::utils::key_names::{is_white, key_to_note, to_deg, Note};
 
 enum Mode {
@@ -16,6 +16,7 @@ enum Modifier {
     Gate,
     Vel,
     Timing,
+    Ratchet,
 }
 
 #[derive(Copy, Clone)]
@@ -24,6 +25,7 @@ struct EventProps {
     gate: Option<f32>,
     vel: f32,
     shift: f32,
+    ratchet: u8, // For the whole step
 }
 
 impl EventProps {
@@ -33,6 +35,7 @@ impl EventProps {
             gate: Some(0.5),
             vel: 0.50,
             shift: 0.0,
+            ratchet: 1,
         }
     }
 
@@ -41,7 +44,7 @@ impl EventProps {
     }
 }
 
-pub struct Programmer {
+pub struct Programmer<P: PlayerSink, O: OutputSink> {
     channel: u8,
     step: u8,
     length: u8,
@@ -49,15 +52,12 @@ pub struct Programmer {
     modifier: Modifier,
     props: Option<EventProps>,
     lengths: [u8; 5],
-    player_sender: MessageSender<PlayerMessage>,
-    output_sender: MessageSender<OutputRequest>,
+    player_sender: P,
+    output_sender: O,
 }
 
-impl Programmer {
-    pub fn new(
-        player_sender: MessageSender<PlayerMessage>,
-        output_sender: MessageSender<OutputRequest>,
-    ) -> Self {
+impl<P: PlayerSink, O: OutputSink> Programmer<P, O> {
+    pub fn new(player_sender: P, output_sender: O) -> Self {
         Programmer {
             channel: 0,
             step: 0,
@@ -121,6 +121,8 @@ impl Programmer {
                 }
                 Operation::ClearStep => self.send_action(PlayerAction::ClearStep(self.step as u32)),
                 Operation::ClearPattern => self.send_action(PlayerAction::ClearPattern),
+                Operation::Save => self.send_action(PlayerAction::Save),
+                Operation::Load => self.send_action(PlayerAction::Load),
                 _ => {}
             },
         }
@@ -165,6 +167,7 @@ impl Programmer {
                 gate,
                 vel,
                 shift,
+                ratchet,
             }) => {
                 self.send_action(PlayerAction::Insert(
                     LiveEvent::Midi {
@@ -193,6 +196,9 @@ impl Programmer {
                     }
                     None => {}
                 }
+                if ratchet > 1 {
+                    self.send_action(PlayerAction::SetRatchet(self.step.into(), ratchet));
+                }
             }
             None => panic!(),
         }
@@ -258,6 +264,7 @@ impl Programmer {
                         Modifier::Gate => p.gate = Some(value),
                         Modifier::Vel => p.vel = value,
                         Modifier::Timing => p.shift = value,
+                        Modifier::Ratchet => p.ratchet = [2, 3, 4, 6, 8][diff as usize - 1],
                     }
                 } else {
                     match diff {
@@ -276,7 +283,8 @@ impl Programmer {
         match self.modifier {
             Modifier::Gate => self.modifier = Modifier::Vel,
             Modifier::Vel => self.modifier = Modifier::Timing,
-            Modifier::Timing => self.modifier = Modifier::Gate,
+            Modifier::Timing => self.modifier = Modifier::Ratchet,
+            Modifier::Ratchet => self.modifier = Modifier::Gate,
         }
     }
 }
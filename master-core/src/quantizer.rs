@@ -0,0 +1,128 @@
+/*
+Snaps notes to a scale before they reach the pitch outputs. A scale is a mask of
+twelve bits where bit n means n semitones above the root is in. Notes outside
+the scale go to the closest one that is in, the lower one when it is a tie.
+
+The named scales can be picked with a knob, the user scale only from the
+console.
+*/
+
+pub const SCALE_CC: u8 = 20;
+pub const ROOT_CC: u8 = 21;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Scale {
+    Chromatic,
+    Major,
+    Minor,
+    Dorian,
+    Phrygian,
+    Lydian,
+    Mixolydian,
+    Locrian,
+    MajorPentatonic,
+    MinorPentatonic,
+    User(u16),
+}
+
+// In the order the scale knob goes through them
+const NAMED: [Scale; 10] = [
+    Scale::Chromatic,
+    Scale::Major,
+    Scale::Minor,
+    Scale::Dorian,
+    Scale::Phrygian,
+    Scale::Lydian,
+    Scale::Mixolydian,
+    Scale::Locrian,
+    Scale::MajorPentatonic,
+    Scale::MinorPentatonic,
+];
+
+impl Scale {
+    pub fn mask(self) -> u16 {
+        match self {
+            Scale::Chromatic => 0b1111_1111_1111,
+            Scale::Major => 0b1010_1011_0101,
+            Scale::Minor => 0b0101_1010_1101,
+            Scale::Dorian => 0b0110_1010_1101,
+            Scale::Phrygian => 0b0101_1010_1011,
+            Scale::Lydian => 0b1010_1101_0101,
+            Scale::Mixolydian => 0b0110_1011_0101,
+            Scale::Locrian => 0b0101_0110_1011,
+            Scale::MajorPentatonic => 0b0010_1001_0101,
+            Scale::MinorPentatonic => 0b0100_1010_1001,
+            Scale::User(mask) => mask & 0xfff,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Scale::Chromatic => "off",
+            Scale::Major => "major",
+            Scale::Minor => "minor",
+            Scale::Dorian => "dorian",
+            Scale::Phrygian => "phrygian",
+            Scale::Lydian => "lydian",
+            Scale::Mixolydian => "mixolydian",
+            Scale::Locrian => "locrian",
+            Scale::MajorPentatonic => "majpent",
+            Scale::MinorPentatonic => "minpent",
+            Scale::User(_) => "user",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        NAMED.iter().find(|scale| scale.name() == name).copied()
+    }
+
+    // Spreads the named scales over the whole range of a controller
+    pub fn from_cc(value: u8) -> Self {
+        NAMED[(value as usize * NAMED.len() / 128).min(NAMED.len() - 1)]
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Quantizer {
+    pub scale: Scale,
+    pub root: u8, // 0 is C
+}
+
+impl Quantizer {
+    pub const fn off() -> Self {
+        Quantizer {
+            scale: Scale::Chromatic,
+            root: 0,
+        }
+    }
+
+    pub fn new(scale: Scale, root: u8) -> Self {
+        Quantizer {
+            scale,
+            root: root % 12,
+        }
+    }
+
+    fn contains(&self, key: u8) -> bool {
+        let degree = (key as u16 + 12 - self.root as u16) % 12;
+        return self.scale.mask() & (1 << degree) != 0;
+    }
+
+    pub fn quantize(&self, key: u8) -> u8 {
+        // Nothing to snap to
+        if self.scale.mask() == 0 {
+            return key;
+        }
+        for distance in 0..12 {
+            match key.checked_sub(distance) {
+                Some(below) if self.contains(below) => return below,
+                _ => {}
+            }
+            match key.checked_add(distance) {
+                Some(above) if above < 128 && self.contains(above) => return above,
+                _ => {}
+            }
+        }
+        return key;
+    }
+}
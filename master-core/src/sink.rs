@@ -0,0 +1,57 @@
+use midly::live::LiveEvent;
+
+use crate::outs::OutputRequest;
+use crate::player::PlayerMessage;
+
+// Anything messages can be pushed into without blocking. On the module this is
+// an rtic channel, on the host it is usually just a Vec.
+pub trait Sink<T> {
+    fn try_send(&mut self, item: T) -> Result<(), T>;
+}
+
+pub trait MidiSink: Sink<LiveEvent<'static>> {}
+impl<S: Sink<LiveEvent<'static>>> MidiSink for S {}
+
+pub trait OutputSink: Sink<OutputRequest> {}
+impl<S: Sink<OutputRequest>> OutputSink for S {}
+
+pub trait PlayerSink: Sink<PlayerMessage> {}
+impl<S: Sink<PlayerMessage>> PlayerSink for S {}
+
+impl<T, S: Sink<T> + ?Sized> Sink<T> for &mut S {
+    fn try_send(&mut self, item: T) -> Result<(), T> {
+        (**self).try_send(item)
+    }
+}
+
+// Sends everything to both. Only the first one decides if it went through, the
+// second one just gets a copy when it has room.
+pub struct Tee<A, B>(pub A, pub B);
+
+impl<T: Copy, A: Sink<T>, B: Sink<T>> Sink<T> for Tee<A, B> {
+    fn try_send(&mut self, item: T) -> Result<(), T> {
+        self.1.try_send(item).ok();
+        self.0.try_send(item)
+    }
+}
+
+impl<T, const N: usize> Sink<T> for heapless::Deque<T, N> {
+    fn try_send(&mut self, item: T) -> Result<(), T> {
+        self.push_back(item)
+    }
+}
+
+#[cfg(any(test, feature = "std"))]
+impl<T> Sink<T> for std::vec::Vec<T> {
+    fn try_send(&mut self, item: T) -> Result<(), T> {
+        self.push(item);
+        Ok(())
+    }
+}
+
+#[cfg(any(test, feature = "std"))]
+impl<T, S: Sink<T>> Sink<T> for std::rc::Rc<core::cell::RefCell<S>> {
+    fn try_send(&mut self, item: T) -> Result<(), T> {
+        self.borrow_mut().try_send(item)
+    }
+}
@@ -0,0 +1,256 @@
+use std::vec::Vec;
+
+use midly::live::LiveEvent;
+use midly::num::{u15, u28};
+use midly::{
+    Format, Header, MetaMessage, MidiMessage, Smf, Timing, Track, TrackEvent, TrackEventKind,
+};
+
+use crate::player::{
+    is_divisor, Player, TimeStamp, ALWAYS, MAX_LENGTH, PATTERN_SIZE, PATTERN_SLOTS, STEP_CAP,
+    SUBS_PER_STEP,
+};
+use crate::sink::{MidiSink, OutputSink};
+use crate::utils::midi_utils::encode_message;
+
+// A step is a 1/divisor note. At this resolution every sub of a sixteenth note
+// step is one tick, and longer power of two steps get whole ticks as well.
+pub const TICKS_PER_BEAT: u32 = SUBS_PER_STEP * 4;
+
+fn to_ticks(ts: TimeStamp, divisor: u8) -> u32 {
+    let subs = ts.step * SUBS_PER_STEP + ts.sub;
+    return subs * TICKS_PER_BEAT * 4 / (SUBS_PER_STEP * divisor as u32);
+}
+
+// Rounds to the nearest sub
+fn to_subs(tick: u64, ticks_per_beat: u64, divisor: u8) -> u64 {
+    let den = ticks_per_beat * 4;
+    return (tick * SUBS_PER_STEP as u64 * divisor as u64 + den / 2) / den;
+}
+
+fn is_note_off(message: MidiMessage) -> bool {
+    match message {
+        MidiMessage::NoteOff { .. } => true,
+        MidiMessage::NoteOn { vel, .. } => vel == 0,
+        _ => false,
+    }
+}
+
+// Whether the event starts or ends a note and for which key
+fn note_of(raw: &[u8; 3]) -> Option<(bool, u8)> {
+    match raw[0] & 0xf0 {
+        0x90 if raw[2] > 0 => Some((true, raw[1])),
+        0x80 | 0x90 => Some((false, raw[1])),
+        _ => None,
+    }
+}
+
+// Which of the events, in time order, fit into steps of STEP_CAP. A note on goes
+// in or out together with the note off that ends it, so that a note cannot be
+// left hanging.
+fn fit(events: &[(TimeStamp, [u8; 3])], length: u8) -> Vec<bool> {
+    let mut kept = vec![false; events.len()];
+    let mut paired = vec![false; events.len()];
+    let mut per_step = [0; MAX_LENGTH];
+    for i in 0..events.len() {
+        if paired[i] {
+            continue;
+        }
+        let off = match note_of(&events[i].1) {
+            Some((true, key)) => (i + 1..events.len())
+                .find(|j| !paired[*j] && note_of(&events[*j].1) == Some((false, key))),
+            _ => None,
+        };
+        let group = [Some(i), off];
+        let fits = group.iter().flatten().all(|j| {
+            let step = events[*j].0.step;
+            let needed = group
+                .iter()
+                .flatten()
+                .filter(|k| events[**k].0.step == step)
+                .count();
+            step < length as u32 && per_step[step as usize] + needed <= STEP_CAP
+        });
+        for j in group.iter().flatten() {
+            paired[*j] = true;
+            if fits {
+                kept[*j] = true;
+                per_step[events[*j].0.step as usize] += 1;
+            }
+        }
+    }
+    return kept;
+}
+
+// One track with the whole pattern in a slot, ending where the pattern loops.
+// slot has to be below PATTERN_SLOTS.
+pub fn to_track<M: MidiSink, O: OutputSink>(player: &Player<M, O>, slot: usize) -> Track<'static> {
+    let divisor = player.divisor();
+    let mut events: Vec<(u32, TrackEventKind<'static>)> = Vec::new();
+    for (ts, event) in player.events_in(slot) {
+        match event {
+            LiveEvent::Midi { channel, message } => events.push((
+                to_ticks(ts, divisor),
+                TrackEventKind::Midi { channel, message },
+            )),
+            _ => {}
+        }
+    }
+    events.sort_by_key(|(tick, _)| *tick);
+    let end = to_ticks(
+        TimeStamp {
+            step: player.length() as u32,
+            sub: 0,
+        },
+        divisor,
+    );
+    events.push((end, TrackEventKind::Meta(MetaMessage::EndOfTrack)));
+
+    let mut track = Vec::new();
+    let mut last = 0;
+    for (tick, kind) in events {
+        track.push(TrackEvent {
+            delta: u28::new(tick - last),
+            kind,
+        });
+        last = tick;
+    }
+    return track;
+}
+
+// Track names say which pattern slot a track belongs to
+fn slot_name(slot: usize) -> &'static [u8] {
+    const NAMES: [&[u8]; PATTERN_SLOTS] = [b"slot 0", b"slot 1", b"slot 2", b"slot 3"];
+    NAMES[slot]
+}
+
+fn named_slot(track: &Track) -> Option<usize> {
+    track.iter().find_map(|event| match event.kind {
+        TrackEventKind::Meta(MetaMessage::TrackName(name)) => {
+            (0..PATTERN_SLOTS).find(|slot| slot_name(*slot) == name)
+        }
+        _ => None,
+    })
+}
+
+// A type 1 file with a named track for every pattern slot of each player that
+// has something in it, and always one for the first slot
+pub fn export<M: MidiSink, O: OutputSink>(players: &[Player<M, O>]) -> Smf<'static> {
+    let mut smf = Smf::new(Header::new(
+        Format::Parallel,
+        Timing::Metrical(u15::new(TICKS_PER_BEAT as u16)),
+    ));
+    for player in players {
+        for slot in 0..PATTERN_SLOTS {
+            if slot > 0 && player.events_in(slot).next().is_none() {
+                continue;
+            }
+            let mut track = to_track(player, slot);
+            track.insert(
+                0,
+                TrackEvent {
+                    delta: u28::new(0),
+                    kind: TrackEventKind::Meta(MetaMessage::TrackName(slot_name(slot))),
+                },
+            );
+            smf.tracks.push(track);
+        }
+    }
+    return smf;
+}
+
+/*
+Builds patterns out of everything on the player's channel, in any track. A
+track named after a pattern slot, the way export names them, goes to that slot
+and any other track to the selected one. Each step becomes a 1/divisor note.
+The patterns are as long as the longest track holding such events, up to
+MAX_LENGTH steps, and anything after that is dropped. So is anything past the
+first STEP_CAP events of a step, and a note is kept or dropped along with its
+note off. Note offs that land right on a step boundary are kept at the end of
+the step before, which is where the programmer would have put them. Slots
+without events in the file keep what they had.
+
+Returns how many events were dropped.
+*/
+pub fn import<M: MidiSink, O: OutputSink>(
+    smf: &Smf,
+    player: &mut Player<M, O>,
+    divisor: u8,
+) -> Option<usize> {
+    let ticks_per_beat = match smf.header.timing {
+        Timing::Metrical(tpb) => tpb.as_int() as u64,
+        Timing::Timecode(..) => return None,
+    };
+    if !is_divisor(divisor) {
+        return None;
+    }
+
+    let mut end = 0;
+    let mut found: Vec<(usize, TimeStamp, [u8; 3])> = Vec::new();
+    for track in smf.tracks.iter() {
+        let slot = named_slot(track).unwrap_or(player.slot());
+        let mut tick = 0u64;
+        let mut mine = false;
+        for event in track.iter() {
+            tick += event.delta.as_int() as u64;
+            match event.kind {
+                TrackEventKind::Midi { channel, message }
+                    if channel.as_int() == player.channel() =>
+                {
+                    mine = true;
+                    let subs = to_subs(tick, ticks_per_beat, divisor) as u32;
+                    let mut ts = TimeStamp {
+                        step: subs / SUBS_PER_STEP,
+                        sub: subs % SUBS_PER_STEP,
+                    };
+                    if is_note_off(message) && ts.sub == 0 && ts.step > 0 {
+                        ts = TimeStamp {
+                            step: ts.step - 1,
+                            sub: SUBS_PER_STEP,
+                        };
+                    }
+                    found.push((slot, ts, encode_message(channel, message)));
+                }
+                _ => {}
+            }
+        }
+        if mine {
+            end = end.max(to_subs(tick, ticks_per_beat, divisor));
+        }
+    }
+    if found.is_empty() {
+        return None;
+    }
+
+    let length = end
+        .div_ceil(SUBS_PER_STEP as u64)
+        .clamp(1, MAX_LENGTH as u64) as u8;
+    let mut dropped = 0;
+    for slot in 0..PATTERN_SLOTS {
+        if !found.iter().any(|(s, _, _)| *s == slot) {
+            continue;
+        }
+        let mut buf = Vec::with_capacity(PATTERN_SIZE);
+        buf.extend_from_slice(&[length, divisor, 0, 0]);
+        let mut count: u16 = 0;
+        let mut events: Vec<(TimeStamp, [u8; 3])> = found
+            .iter()
+            .filter(|(s, _, _)| *s == slot)
+            .map(|(_, ts, raw)| (*ts, *raw))
+            .collect();
+        // Tracks come one after the other, notes are paired up in time order
+        events.sort_by_key(|(ts, _)| (ts.step, ts.sub));
+        let kept = fit(&events, length);
+        for ((ts, raw), keep) in events.iter().zip(kept) {
+            if !keep {
+                dropped += 1;
+                continue;
+            }
+            buf.extend_from_slice(&[ts.step as u8, ts.sub as u8, raw[0], raw[1], raw[2], ALWAYS]);
+            count += 1;
+        }
+        buf[2..4].copy_from_slice(&count.to_le_bytes());
+        player.import_slot(slot, &buf)?;
+    }
+    return Some(dropped);
+}
@@ -0,0 +1,244 @@
+use heapless::Vec;
+
+use crate::player::{PlayerAction, PlayerMessage, PATTERN_SLOTS};
+use crate::sink::PlayerSink;
+
+/*
+A song is a chain of entries, each naming the pattern slot of every player and
+how many times it repeats. An entry is over once the longest loop among the
+players has come around that many times, so shorter loops fill it up with
+however many fit. The arranger follows the clock and asks every player for its
+slot of the next entry when its last loop of the entry starts, so loops that
+add up to the entry switch right on the border. Others switch whenever they
+come around.
+
+Song position pointers count sixteenths from the start of the song. Positions
+past the end wrap around, just like playing does.
+*/
+
+pub const PLAYERS: usize = 5;
+pub const SONGS: usize = 4;
+pub const MAX_ENTRIES: usize = 16;
+pub const MAX_REPEATS: u8 = 64;
+const TICKS_PER_BAR: u32 = 96;
+const TICKS_PER_SIXTEENTH: u32 = 6;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Entry {
+    pub slots: [u8; PLAYERS], // By channel
+    pub repeats: u8,
+}
+
+impl Entry {
+    pub fn new(slots: [u8; PLAYERS], repeats: u8) -> Option<Self> {
+        if repeats == 0
+            || repeats > MAX_REPEATS
+            || slots.iter().any(|s| *s as usize >= PATTERN_SLOTS)
+        {
+            return None;
+        }
+        return Some(Entry { slots, repeats });
+    }
+
+    fn ticks(&self, loops: &[u32; PLAYERS]) -> u32 {
+        self.repeats as u32 * longest(loops)
+    }
+}
+
+fn longest(loops: &[u32; PLAYERS]) -> u32 {
+    loops.iter().copied().max().unwrap_or(0).max(1)
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Position {
+    pub song: usize,
+    pub entry: usize,
+    pub repeat: u32,
+}
+
+pub struct Arranger {
+    songs: [Vec<Entry, MAX_ENTRIES>; SONGS],
+    enabled: bool,
+    playing: bool,
+    song: usize,
+    entry: usize,
+    tick: u32, // Since the start of the entry
+    queued: [bool; PLAYERS],
+    // How many ticks each player's loop takes
+    loops: [u32; PLAYERS],
+}
+
+impl Arranger {
+    pub fn new() -> Self {
+        Arranger {
+            songs: Default::default(),
+            enabled: false,
+            playing: false,
+            song: 0,
+            entry: 0,
+            tick: 0,
+            queued: [false; PLAYERS],
+            loops: [TICKS_PER_BAR; PLAYERS],
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn song(&self) -> usize {
+        self.song
+    }
+
+    pub fn entries(&self, song: usize) -> &[Entry] {
+        match self.songs.get(song) {
+            Some(entries) => entries,
+            None => &[],
+        }
+    }
+
+    pub fn position(&self) -> Position {
+        Position {
+            song: self.song,
+            entry: self.entry,
+            repeat: self.tick / longest(&self.loops),
+        }
+    }
+
+    pub fn append(&mut self, song: usize, entry: Entry) -> Option<()> {
+        self.songs.get_mut(song)?.push(entry).ok()
+    }
+
+    pub fn clear(&mut self, song: usize) {
+        match self.songs.get_mut(song) {
+            Some(entries) => entries.clear(),
+            None => {}
+        }
+        if song == self.song {
+            self.locate(0);
+        }
+    }
+
+    // Looks at everything on its way to the players and sends what the song
+    // wants on top of it. Has to come first so that slots are picked before a
+    // start reaches the players. loops are how many ticks the loop of each
+    // player takes, by channel.
+    pub fn handle_message<P: PlayerSink>(
+        &mut self,
+        msg: PlayerMessage,
+        loops: [u32; PLAYERS],
+        out: &mut P,
+    ) {
+        self.loops = loops;
+        let action = match msg {
+            PlayerMessage::Broadcast(action) => action,
+            PlayerMessage::Action(..) => return,
+        };
+        // Keeps up with starts and stops while off so that it can be turned on
+        // in the middle of playing
+        match action {
+            PlayerAction::Play => {
+                if self.enabled && !self.playing {
+                    self.locate(0);
+                    self.select(out);
+                }
+                self.playing = true;
+            }
+            PlayerAction::Continue => {
+                if self.enabled && !self.playing {
+                    self.select(out);
+                    out.try_send(PlayerMessage::Broadcast(PlayerAction::Locate(self.tick)))
+                        .ok();
+                }
+                self.playing = true;
+            }
+            PlayerAction::Stop => self.playing = false,
+            PlayerAction::Tick if self.enabled && self.playing => self.tick(out),
+            PlayerAction::SongPosition(sixteenths) if self.enabled && !self.playing => {
+                self.locate(sixteenths as u32 * TICKS_PER_SIXTEENTH);
+                self.select(out);
+            }
+            PlayerAction::SongSelect(song)
+                if self.enabled && !self.playing && (song as usize) < SONGS =>
+            {
+                self.song = song as usize;
+                self.locate(0);
+                self.select(out);
+            }
+            _ => {}
+        }
+    }
+
+    fn current(&self) -> Option<&Entry> {
+        self.songs[self.song].get(self.entry)
+    }
+
+    fn next(&self) -> usize {
+        (self.entry + 1) % self.songs[self.song].len().max(1)
+    }
+
+    fn tick<P: PlayerSink>(&mut self, out: &mut P) {
+        let ticks = match self.current() {
+            Some(entry) => entry.ticks(&self.loops),
+            None => return,
+        };
+        let next = self.songs[self.song][self.next()];
+        for channel in 0..PLAYERS {
+            if !self.queued[channel] && self.tick + self.loops[channel] >= ticks {
+                out.try_send(PlayerMessage::Action(
+                    channel as u8,
+                    PlayerAction::SelectSlot(next.slots[channel]),
+                ))
+                .ok();
+                self.queued[channel] = true;
+            }
+        }
+        self.tick += 1;
+        if self.tick >= ticks {
+            self.entry = self.next();
+            self.tick = 0;
+            self.queued = [false; PLAYERS];
+        }
+    }
+
+    fn locate(&mut self, ticks: u32) {
+        let entries = &self.songs[self.song];
+        let total: u32 = entries.iter().map(|e| e.ticks(&self.loops)).sum();
+        self.entry = 0;
+        self.tick = 0;
+        self.queued = [false; PLAYERS];
+        if total == 0 {
+            return;
+        }
+        let mut ticks = ticks % total;
+        for (i, entry) in entries.iter().enumerate() {
+            if ticks < entry.ticks(&self.loops) {
+                self.entry = i;
+                self.tick = ticks;
+                return;
+            }
+            ticks -= entry.ticks(&self.loops);
+        }
+    }
+
+    fn select<P: PlayerSink>(&self, out: &mut P) {
+        match self.current() {
+            Some(entry) => self.send_slots(*entry, out),
+            None => {}
+        }
+    }
+
+    fn send_slots<P: PlayerSink>(&self, entry: Entry, out: &mut P) {
+        for (channel, slot) in entry.slots.iter().enumerate() {
+            out.try_send(PlayerMessage::Action(
+                channel as u8,
+                PlayerAction::SelectSlot(*slot),
+            ))
+            .ok();
+        }
+    }
+}
@@ -0,0 +1,373 @@
+use crate::outs::{Calibration, CALIBRATION_SIZE};
+use crate::player::{
+    Player, PlayerAction, PlayerMessage, ALWAYS, EVENT_SIZE, PATTERN_SIZE, PATTERN_SLOTS,
+};
+use crate::sink::{MidiSink, OutputSink};
+use crate::song::PLAYERS;
+
+pub const SLOT_SIZE: usize = 4096; // One flash sector
+pub const SLOTS: usize = 16;
+// Two full patterns fit in a slot, so a player keeps pattern slots 0 and 1 in
+// the slot of its channel and 2 and 3 in the slot PLAYERS above it. The top
+// slot holds the calibration.
+const PATTERNS_PER_SLOT: usize = 2;
+pub const CALIBRATION_SLOT: usize = SLOTS - 1;
+
+const MAGIC: [u8; 4] = *b"YAMM";
+pub const HEADER_SIZE: usize = 16;
+
+/*
+Every slot starts with a header:
+  0..4    magic
+  4       kind of record
+  5       version of that kind
+  6..8    reserved
+  8..12   body length, little endian
+  12..16  crc32 of the body, little endian
+
+Version 3 pattern bodies hold the pattern slots kept in that storage slot:
+  0       how many
+followed by each of them
+  0       pattern slot
+  1..3    pattern length, little endian
+  3..     the pattern as Player::export_slot writes it
+Older bodies are a single pattern, which goes to pattern slot 0.
+*/
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Kind {
+    Pattern = 1,
+    Calibration = 2,
+}
+
+// Version 1 events had no chance and always played, version 2 held one
+// pattern slot
+const PATTERN_VERSION: u8 = 3;
+const PATTERN_VERSION_2: u8 = 2;
+const PATTERN_VERSION_1: u8 = 1;
+const PATTERN_RECORD_SIZE: usize = 1 + PATTERNS_PER_SLOT * (3 + PATTERN_SIZE);
+const _: () = assert!(PATTERN_RECORD_SIZE <= SLOT_SIZE - HEADER_SIZE);
+const _: () = assert!(PLAYERS * PATTERN_SLOTS / PATTERNS_PER_SLOT <= CALIBRATION_SLOT);
+const EVENT_SIZE_1: usize = 5;
+const CALIBRATION_VERSION: u8 = 1;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum SlotError {
+    Empty,
+    UnknownVersion(u8),
+    Corrupt,
+    Io,
+}
+
+// Whole slots are read and written at once since that is how flash wants it
+pub trait Storage {
+    fn read(&mut self, slot: usize, buf: &mut [u8; SLOT_SIZE]) -> Option<()>;
+    fn write(&mut self, slot: usize, buf: &[u8; SLOT_SIZE]) -> Option<()>;
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffff;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    return !crc;
+}
+
+pub fn write_slot<S: Storage>(
+    storage: &mut S,
+    slot: usize,
+    kind: Kind,
+    version: u8,
+    body: &[u8],
+) -> Result<(), SlotError> {
+    if slot >= SLOTS || body.len() > SLOT_SIZE - HEADER_SIZE {
+        return Err(SlotError::Io);
+    }
+    let mut buf = [0xff; SLOT_SIZE];
+    buf[0..4].copy_from_slice(&MAGIC);
+    buf[4] = kind as u8;
+    buf[5] = version;
+    buf[6..8].copy_from_slice(&[0, 0]);
+    buf[8..12].copy_from_slice(&(body.len() as u32).to_le_bytes());
+    buf[12..16].copy_from_slice(&crc32(body).to_le_bytes());
+    buf[HEADER_SIZE..HEADER_SIZE + body.len()].copy_from_slice(body);
+    storage.write(slot, &buf).ok_or(SlotError::Io)
+}
+
+// Returns the body of the slot if it holds a healthy record of the right kind
+pub fn read_slot<'a, S: Storage>(
+    storage: &mut S,
+    slot: usize,
+    kind: Kind,
+    version: u8,
+    buf: &'a mut [u8; SLOT_SIZE],
+) -> Result<&'a [u8], SlotError> {
+    if slot >= SLOTS {
+        return Err(SlotError::Io);
+    }
+    storage.read(slot, buf).ok_or(SlotError::Io)?;
+    if buf[0..4] != MAGIC || buf[4] != kind as u8 {
+        return Err(SlotError::Empty);
+    }
+    if buf[5] != version {
+        return Err(SlotError::UnknownVersion(buf[5]));
+    }
+    let length = u32::from_le_bytes([buf[8], buf[9], buf[10], buf[11]]) as usize;
+    if length > SLOT_SIZE - HEADER_SIZE {
+        return Err(SlotError::Corrupt);
+    }
+    let crc = u32::from_le_bytes([buf[12], buf[13], buf[14], buf[15]]);
+    let body = &buf[HEADER_SIZE..HEADER_SIZE + length];
+    if crc32(body) != crc {
+        return Err(SlotError::Corrupt);
+    }
+    return Ok(body);
+}
+
+fn pattern_slots(channel: u8) -> Option<[usize; PATTERN_SLOTS / PATTERNS_PER_SLOT]> {
+    if channel as usize >= PLAYERS {
+        return None;
+    }
+    return Some(core::array::from_fn(|i| channel as usize + i * PLAYERS));
+}
+
+// Every pattern slot of the player, whether it has anything in it or not
+pub fn save_patterns<S: Storage, M: MidiSink, O: OutputSink>(
+    storage: &mut S,
+    player: &Player<M, O>,
+) -> Result<(), SlotError> {
+    let slots = pattern_slots(player.channel()).ok_or(SlotError::Io)?;
+    for (i, slot) in slots.iter().enumerate() {
+        let mut body = [0; PATTERN_RECORD_SIZE];
+        body[0] = PATTERNS_PER_SLOT as u8;
+        let mut at = 1;
+        for pattern_slot in i * PATTERNS_PER_SLOT..(i + 1) * PATTERNS_PER_SLOT {
+            let length = player.export_slot(pattern_slot, &mut body[at + 3..]);
+            body[at] = pattern_slot as u8;
+            body[at + 1..at + 3].copy_from_slice(&(length as u16).to_le_bytes());
+            at += 3 + length;
+        }
+        write_slot(storage, *slot, Kind::Pattern, PATTERN_VERSION, &body[..at])?;
+    }
+    return Ok(());
+}
+
+// Gives every event of a version 1 body a chance byte
+fn upgrade_pattern_1(body: &[u8], out: &mut [u8; PATTERN_SIZE]) -> Option<usize> {
+    let header = body.get(..4)?;
+    out[..4].copy_from_slice(header);
+    let mut at = 4;
+    for raw in body[4..].as_chunks::<EVENT_SIZE_1>().0 {
+        out.get_mut(at..at + EVENT_SIZE)?[..EVENT_SIZE_1].copy_from_slice(raw);
+        out[at + EVENT_SIZE_1] = ALWAYS;
+        at += EVENT_SIZE;
+    }
+    return Some(at);
+}
+
+// Stops at the first pattern the player rejects, the ones before it stay
+fn import_patterns<M: MidiSink, O: OutputSink>(
+    player: &mut Player<M, O>,
+    body: &[u8],
+) -> Option<()> {
+    let (count, mut rest) = body.split_first()?;
+    for _ in 0..*count {
+        let slot = *rest.first()? as usize;
+        let length = u16::from_le_bytes([*rest.get(1)?, *rest.get(2)?]) as usize;
+        player.import_slot(slot, rest.get(3..3 + length)?)?;
+        rest = &rest[3 + length..];
+    }
+    return Some(());
+}
+
+// Reads one storage slot worth of patterns into the player
+fn load_slot<S: Storage, M: MidiSink, O: OutputSink>(
+    storage: &mut S,
+    player: &mut Player<M, O>,
+    slot: usize,
+) -> Result<(), SlotError> {
+    let mut buf = [0; SLOT_SIZE];
+    match read_slot(storage, slot, Kind::Pattern, PATTERN_VERSION, &mut buf) {
+        Ok(body) => import_patterns(player, body).ok_or(SlotError::Corrupt),
+        Err(SlotError::UnknownVersion(PATTERN_VERSION_2)) => {
+            let body = read_slot(storage, slot, Kind::Pattern, PATTERN_VERSION_2, &mut buf)?;
+            player.import_slot(0, body).ok_or(SlotError::Corrupt)
+        }
+        Err(SlotError::UnknownVersion(PATTERN_VERSION_1)) => {
+            let body = read_slot(storage, slot, Kind::Pattern, PATTERN_VERSION_1, &mut buf)?;
+            let mut upgraded = [0; PATTERN_SIZE];
+            let length = upgrade_pattern_1(body, &mut upgraded).ok_or(SlotError::Corrupt)?;
+            player
+                .import_slot(0, &upgraded[..length])
+                .ok_or(SlotError::Corrupt)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+// A slot that fails the checks is left alone and the pattern slots it holds
+// keep what they had. The second slot of a player is empty when the patterns
+// were saved by a version before 3, that is not an error.
+pub fn load_patterns<S: Storage, M: MidiSink, O: OutputSink>(
+    storage: &mut S,
+    player: &mut Player<M, O>,
+) -> Result<(), SlotError> {
+    let slots = pattern_slots(player.channel()).ok_or(SlotError::Io)?;
+    load_slot(storage, player, slots[0])?;
+    for slot in slots[1..].iter() {
+        match load_slot(storage, player, *slot) {
+            Err(SlotError::Empty) => {}
+            result => result?,
+        }
+    }
+    return Ok(());
+}
+
+// Takes care of the Save and Load actions that the player itself ignores
+pub fn handle_message<S: Storage, M: MidiSink, O: OutputSink>(
+    storage: &mut S,
+    player: &mut Player<M, O>,
+    msg: PlayerMessage,
+) -> Option<Result<(), SlotError>> {
+    match msg {
+        PlayerMessage::Action(ch, _) if ch != player.channel() => None,
+        PlayerMessage::Broadcast(action) | PlayerMessage::Action(_, action) => match action {
+            PlayerAction::Save => Some(save_patterns(storage, player)),
+            PlayerAction::Load => Some(load_patterns(storage, player)),
+            _ => None,
+        },
+    }
+}
+
+// A port after port, A first
+pub fn save_calibration<S: Storage>(
+    storage: &mut S,
+    calibrations: &[Calibration; 4],
+) -> Result<(), SlotError> {
+    let mut body = [0; CALIBRATION_SIZE * 4];
+    for (calibration, raw) in calibrations
+        .iter()
+        .zip(body.as_chunks_mut::<CALIBRATION_SIZE>().0)
+    {
+        calibration.encode(raw);
+    }
+    write_slot(
+        storage,
+        CALIBRATION_SLOT,
+        Kind::Calibration,
+        CALIBRATION_VERSION,
+        &body,
+    )
+}
+
+pub fn load_calibration<S: Storage>(storage: &mut S) -> Result<[Calibration; 4], SlotError> {
+    let mut buf = [0; SLOT_SIZE];
+    let body = read_slot(
+        storage,
+        CALIBRATION_SLOT,
+        Kind::Calibration,
+        CALIBRATION_VERSION,
+        &mut buf,
+    )?;
+    if body.len() != CALIBRATION_SIZE * 4 {
+        return Err(SlotError::Corrupt);
+    }
+    let mut calibrations = [Calibration::linear(); 4];
+    for (calibration, raw) in calibrations.iter_mut().zip(body.chunks(CALIBRATION_SIZE)) {
+        *calibration = Calibration::decode(raw).ok_or(SlotError::Corrupt)?;
+    }
+    return Ok(calibrations);
+}
+
+pub fn is_save(msg: PlayerMessage) -> bool {
+    match msg {
+        PlayerMessage::Broadcast(PlayerAction::Save) => true,
+        PlayerMessage::Action(_, PlayerAction::Save) => true,
+        _ => false,
+    }
+}
+
+#[cfg(any(test, feature = "std"))]
+pub struct MemoryStorage {
+    slots: std::vec::Vec<[u8; SLOT_SIZE]>,
+}
+
+#[cfg(any(test, feature = "std"))]
+impl MemoryStorage {
+    pub fn new() -> Self {
+        MemoryStorage {
+            slots: std::vec![[0xff; SLOT_SIZE]; SLOTS],
+        }
+    }
+
+    pub fn slot_mut(&mut self, slot: usize) -> &mut [u8; SLOT_SIZE] {
+        &mut self.slots[slot]
+    }
+}
+
+#[cfg(any(test, feature = "std"))]
+impl Storage for MemoryStorage {
+    fn read(&mut self, slot: usize, buf: &mut [u8; SLOT_SIZE]) -> Option<()> {
+        buf.copy_from_slice(self.slots.get(slot)?);
+        Some(())
+    }
+
+    fn write(&mut self, slot: usize, buf: &[u8; SLOT_SIZE]) -> Option<()> {
+        self.slots.get_mut(slot)?.copy_from_slice(buf);
+        Some(())
+    }
+}
+
+// Same layout as the flash region, so an image can be written straight to it
+#[cfg(any(test, feature = "std"))]
+pub struct FileStorage {
+    file: std::fs::File,
+}
+
+#[cfg(any(test, feature = "std"))]
+impl FileStorage {
+    pub fn open<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<Self> {
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        Ok(FileStorage { file })
+    }
+}
+
+#[cfg(any(test, feature = "std"))]
+impl Storage for FileStorage {
+    fn read(&mut self, slot: usize, buf: &mut [u8; SLOT_SIZE]) -> Option<()> {
+        use std::io::{Read, Seek, SeekFrom};
+        // Whatever is past the end of the file counts as erased flash
+        buf.fill(0xff);
+        self.file
+            .seek(SeekFrom::Start((slot * SLOT_SIZE) as u64))
+            .ok()?;
+        let mut read = 0;
+        while read < SLOT_SIZE {
+            match self.file.read(&mut buf[read..]).ok()? {
+                0 => break,
+                n => read += n,
+            }
+        }
+        Some(())
+    }
+
+    fn write(&mut self, slot: usize, buf: &[u8; SLOT_SIZE]) -> Option<()> {
+        use std::io::{Seek, SeekFrom, Write};
+        self.file
+            .seek(SeekFrom::Start((slot * SLOT_SIZE) as u64))
+            .ok()?;
+        self.file.write_all(buf).ok()
+    }
+}
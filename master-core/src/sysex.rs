@@ -0,0 +1,250 @@
+use heapless::Vec;
+
+use crate::player::{Player, PATTERN_SIZE, PATTERN_SLOTS};
+use crate::sink::{MidiSink, OutputSink};
+use crate::storage::crc32;
+
+/*
+All messages look like
+  F0 7D <command> <channel> <data...> F7
+7D is the manufacturer id set aside for non-commercial use.
+
+  01  Dump request, no data, answered with a slot dump for every pattern slot
+  02  Dump, data is the exported pattern followed by its crc32, packed into 7 bit.
+      Dumps from before pattern slots look like this and go to slot 0.
+  03  Restored, the reply to a dump that was loaded, no data
+  04  Not restored, the reply to a dump with a bad crc, a pattern the player
+      rejects or a channel without a player, no data
+  05  Slot dump, data is the pattern slot followed by what a dump holds
+*/
+
+const SYSEX_START: u8 = 0xf0;
+const SYSEX_END: u8 = 0xf7;
+const MANUFACTURER_ID: u8 = 0x7d;
+const DUMP_REQUEST: u8 = 0x01;
+const DUMP: u8 = 0x02;
+const ACK: u8 = 0x03;
+const NAK: u8 = 0x04;
+const SLOT_DUMP: u8 = 0x05;
+const HEADER_SIZE: usize = 4;
+
+const fn packed_size(n: usize) -> usize {
+    n + n.div_ceil(7)
+}
+
+pub const MAX_SYSEX_SIZE: usize = HEADER_SIZE + 1 + packed_size(PATTERN_SIZE + 4) + 1;
+// Room for the answer to a dump request
+pub const MAX_DUMPS_SIZE: usize = PATTERN_SLOTS * MAX_SYSEX_SIZE;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Request<'a> {
+    Dump(u8),
+    Restore(u8, u8, &'a [u8]), // Channel, pattern slot and data
+}
+
+// Every group of up to seven bytes is preceded by a byte holding their top bits
+pub fn pack(data: &[u8], out: &mut [u8]) -> usize {
+    let mut at = 0;
+    for chunk in data.chunks(7) {
+        let mut msbs = 0;
+        for (i, byte) in chunk.iter().enumerate() {
+            msbs |= (byte >> 7) << i;
+            out[at + 1 + i] = byte & 0x7f;
+        }
+        out[at] = msbs;
+        at += 1 + chunk.len();
+    }
+    return at;
+}
+
+pub fn unpack(data: &[u8], out: &mut [u8]) -> Option<usize> {
+    let mut at = 0;
+    for chunk in data.chunks(8) {
+        let msbs = chunk[0];
+        for (i, byte) in chunk[1..].iter().enumerate() {
+            *out.get_mut(at)? = byte | (((msbs >> i) & 1) << 7);
+            at += 1;
+        }
+    }
+    return Some(at);
+}
+
+// msg is the whole thing, from F0 to F7
+pub fn parse(msg: &[u8]) -> Option<Request<'_>> {
+    if msg.len() < HEADER_SIZE + 1
+        || msg[0] != SYSEX_START
+        || msg[1] != MANUFACTURER_ID
+        || msg[msg.len() - 1] != SYSEX_END
+    {
+        return None;
+    }
+    let channel = msg[3];
+    let data = &msg[HEADER_SIZE..msg.len() - 1];
+    match msg[2] {
+        DUMP_REQUEST => Some(Request::Dump(channel)),
+        DUMP => Some(Request::Restore(channel, 0, data)),
+        SLOT_DUMP => {
+            let (slot, data) = data.split_first()?;
+            Some(Request::Restore(channel, *slot, data))
+        }
+        _ => None,
+    }
+}
+
+fn write_empty(command: u8, channel: u8, out: &mut [u8]) -> usize {
+    out[..HEADER_SIZE + 1].copy_from_slice(&[
+        SYSEX_START,
+        MANUFACTURER_ID,
+        command,
+        channel,
+        SYSEX_END,
+    ]);
+    return HEADER_SIZE + 1;
+}
+
+pub fn write_request(channel: u8, out: &mut [u8]) -> usize {
+    write_empty(DUMP_REQUEST, channel, out)
+}
+
+// Tells the host whether a dump for the channel was restored
+pub fn write_reply(channel: u8, restored: bool, out: &mut [u8]) -> usize {
+    match restored {
+        true => write_empty(ACK, channel, out),
+        false => write_empty(NAK, channel, out),
+    }
+}
+
+// out needs room for MAX_SYSEX_SIZE bytes, slot has to be below PATTERN_SLOTS
+pub fn write_dump<M: MidiSink, O: OutputSink>(
+    player: &Player<M, O>,
+    slot: usize,
+    out: &mut [u8],
+) -> usize {
+    let mut body = [0; PATTERN_SIZE + 4];
+    let length = player.export_slot(slot, &mut body);
+    let crc = crc32(&body[..length]);
+    body[length..length + 4].copy_from_slice(&crc.to_le_bytes());
+
+    out[..HEADER_SIZE + 1].copy_from_slice(&[
+        SYSEX_START,
+        MANUFACTURER_ID,
+        SLOT_DUMP,
+        player.channel(),
+        slot as u8,
+    ]);
+    let at = HEADER_SIZE + 1 + pack(&body[..length + 4], &mut out[HEADER_SIZE + 1..]);
+    out[at] = SYSEX_END;
+    return at + 1;
+}
+
+// A slot dump for every pattern slot, out needs room for MAX_DUMPS_SIZE bytes
+pub fn write_dumps<M: MidiSink, O: OutputSink>(player: &Player<M, O>, out: &mut [u8]) -> usize {
+    let mut at = 0;
+    for slot in 0..PATTERN_SLOTS {
+        at += write_dump(player, slot, &mut out[at..]);
+    }
+    return at;
+}
+
+pub fn restore<M: MidiSink, O: OutputSink>(
+    player: &mut Player<M, O>,
+    slot: u8,
+    data: &[u8],
+) -> Option<()> {
+    let mut body = [0; PATTERN_SIZE + 4];
+    let length = unpack(data, &mut body)?;
+    if length < 4 {
+        return None;
+    }
+    let (pattern, crc) = body[..length].split_at(length - 4);
+    if crc32(pattern).to_le_bytes() != crc {
+        return None;
+    }
+    player.import_slot(slot as usize, pattern)
+}
+
+// Returns the length of the reply written to out, if there is one. out needs
+// room for MAX_DUMPS_SIZE bytes.
+pub fn handle<M: MidiSink, O: OutputSink>(
+    players: &mut [Player<M, O>],
+    msg: &[u8],
+    out: &mut [u8],
+) -> Option<usize> {
+    match parse(msg)? {
+        Request::Dump(channel) => {
+            let player = players.iter().find(|p| p.channel() == channel)?;
+            Some(write_dumps(player, out))
+        }
+        Request::Restore(channel, slot, data) => {
+            let restored = players
+                .iter_mut()
+                .find(|p| p.channel() == channel)
+                .and_then(|player| restore(player, slot, data));
+            Some(write_reply(channel, restored.is_some(), out))
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Reading {
+    NotMine, // Byte is not part of a SysEx message, parse it as usual
+    Busy,
+    Done, // A whole message is available from message()
+}
+
+// Picks SysEx messages out of a raw byte stream that may arrive in pieces.
+// Realtime bytes are allowed in the middle of a message and are passed on.
+pub struct SysExReader {
+    buf: Vec<u8, MAX_SYSEX_SIZE>,
+    active: bool,
+    overflowed: bool,
+}
+
+impl SysExReader {
+    pub fn new() -> Self {
+        SysExReader {
+            buf: Vec::new(),
+            active: false,
+            overflowed: false,
+        }
+    }
+
+    pub fn push(&mut self, byte: u8) -> Reading {
+        match byte {
+            0xf8..=0xff => Reading::NotMine,
+            SYSEX_START => {
+                self.buf.clear();
+                self.buf.push(byte).ok();
+                self.active = true;
+                self.overflowed = false;
+                Reading::Busy
+            }
+            SYSEX_END if self.active => {
+                self.active = false;
+                if self.buf.push(byte).is_err() || self.overflowed {
+                    self.buf.clear();
+                    Reading::Busy
+                } else {
+                    Reading::Done
+                }
+            }
+            0x80..=0xff => {
+                // Any other status byte ends the message without completing it
+                self.active = false;
+                self.buf.clear();
+                Reading::NotMine
+            }
+            _ if self.active => {
+                if self.buf.push(byte).is_err() {
+                    self.overflowed = true;
+                }
+                Reading::Busy
+            }
+            _ => Reading::NotMine,
+        }
+    }
+
+    pub fn message(&self) -> &[u8] {
+        &self.buf
+    }
+}
@@ -0,0 +1,170 @@
+use midly::live::LiveEvent;
+
+/*
+USB MIDI 1.0 moves everything in four byte event packets:
+  0      cable number << 4 | code index number (CIN)
+  1..4   the MIDI bytes, padded with zeros
+The CIN says how many of the MIDI bytes are used. For channel messages it is
+just the high nibble of the status byte.
+*/
+
+pub type Packet = [u8; 4];
+pub const PACKET_SIZE: usize = 4;
+
+const CIN_COMMON_2: u8 = 0x2;
+const CIN_COMMON_3: u8 = 0x3;
+const CIN_SYSEX: u8 = 0x4; // SysEx starts or goes on
+const CIN_SYSEX_END_1: u8 = 0x5; // Also used for one byte system common messages
+const CIN_SYSEX_END_2: u8 = 0x6;
+const CIN_SYSEX_END_3: u8 = 0x7;
+const CIN_SINGLE_BYTE: u8 = 0xf;
+
+const SYSEX_START: u8 = 0xf0;
+const SYSEX_END: u8 = 0xf7;
+
+pub fn packet_length(header: u8) -> usize {
+    match header & 0x0f {
+        CIN_SYSEX_END_1 | CIN_SINGLE_BYTE => 1,
+        CIN_COMMON_2 | CIN_SYSEX_END_2 | 0xc | 0xd => 2,
+        CIN_COMMON_3 | CIN_SYSEX | CIN_SYSEX_END_3 | 0x8..=0xb | 0xe => 3,
+        _ => 0, // Reserved
+    }
+}
+
+pub fn cable(packet: &Packet) -> u8 {
+    packet[0] >> 4
+}
+
+// The MIDI bytes the packet carries
+pub fn packet_bytes(packet: &Packet) -> &[u8] {
+    &packet[1..1 + packet_length(packet[0])]
+}
+
+// Puts the MIDI bytes of every whole packet in buf after each other in out,
+// which needs room for three bytes per packet. Returns how many were written.
+pub fn unpack_packets(buf: &[u8], out: &mut [u8]) -> usize {
+    let mut at = 0;
+    for packet in buf.as_chunks::<PACKET_SIZE>().0 {
+        let bytes = packet_bytes(packet);
+        out[at..at + bytes.len()].copy_from_slice(bytes);
+        at += bytes.len();
+    }
+    return at;
+}
+
+// Only for what fits in one packet, which is everything but SysEx
+pub fn event_packet(cable: u8, event: LiveEvent) -> Option<Packet> {
+    let mut buf = [0u8; 3];
+    let mut out = &mut buf[..];
+    event.write(&mut out).ok()?;
+    let length = 3 - out.len();
+    return Packer::new(cable).packets(&buf[..length]).next();
+}
+
+// Cuts a stream of whole MIDI messages into packets. SysEx may be of any
+// length and realtime bytes may show up in the middle of it, in which case they
+// go out first while the SysEx bytes around them wait for a full packet. The
+// SysEx may also be handed over in pieces, what is left of one piece waits for
+// the next. Bytes that do not belong to any message are skipped.
+pub struct Packer {
+    cable: u8,
+    in_sysex: bool,
+    held: [u8; 3],
+    held_length: usize,
+}
+
+impl Packer {
+    pub fn new(cable: u8) -> Self {
+        Packer {
+            cable,
+            in_sysex: false,
+            held: [0; 3],
+            held_length: 0,
+        }
+    }
+
+    pub fn packets<'a>(&'a mut self, bytes: &'a [u8]) -> Packets<'a> {
+        Packets {
+            packer: self,
+            bytes,
+        }
+    }
+}
+
+pub struct Packets<'a> {
+    packer: &'a mut Packer,
+    bytes: &'a [u8],
+}
+
+impl Packets<'_> {
+    fn take(&mut self, length: usize, cin: u8) -> Packet {
+        let mut packet = [self.packer.cable << 4 | cin, 0, 0, 0];
+        packet[1..1 + length].copy_from_slice(&self.bytes[..length]);
+        self.bytes = &self.bytes[length..];
+        return packet;
+    }
+
+    // Holds on to the SysEx byte, giving back a packet once there are three or
+    // the SysEx ends
+    fn hold(&mut self, byte: u8) -> Option<Packet> {
+        let packer = &mut *self.packer;
+        self.bytes = &self.bytes[1..];
+        packer.held[packer.held_length] = byte;
+        packer.held_length += 1;
+        let cin = match byte {
+            SYSEX_END => {
+                packer.in_sysex = false;
+                CIN_SYSEX_END_1 + packer.held_length as u8 - 1
+            }
+            _ if packer.held_length == 3 => CIN_SYSEX,
+            _ => return None,
+        };
+        let mut packet = [packer.cable << 4 | cin, 0, 0, 0];
+        packet[1..1 + packer.held_length].copy_from_slice(&packer.held[..packer.held_length]);
+        packer.held_length = 0;
+        return Some(packet);
+    }
+}
+
+impl Iterator for Packets<'_> {
+    type Item = Packet;
+
+    fn next(&mut self) -> Option<Packet> {
+        loop {
+            let status = *self.bytes.first()?;
+            if status >= 0xf8 {
+                return Some(self.take(1, CIN_SINGLE_BYTE));
+            }
+            if status == SYSEX_START {
+                self.packer.in_sysex = true;
+                self.packer.held_length = 0;
+            } else if status >= 0x80 && status != SYSEX_END {
+                // Whatever SysEx was going on got cut short
+                self.packer.in_sysex = false;
+                self.packer.held_length = 0;
+            }
+            if self.packer.in_sysex {
+                match self.hold(status) {
+                    Some(packet) => return Some(packet),
+                    None => continue,
+                }
+            }
+            let (length, cin) = match status {
+                0x80..=0xbf | 0xe0..=0xef => (3, status >> 4),
+                0xc0..=0xdf => (2, status >> 4),
+                0xf1 | 0xf3 => (2, CIN_COMMON_2),
+                0xf2 => (3, CIN_COMMON_3),
+                0xf6 => (1, CIN_SYSEX_END_1),
+                _ => {
+                    self.bytes = &self.bytes[1..];
+                    continue;
+                }
+            };
+            if self.bytes.len() < length {
+                self.bytes = &[];
+                return None;
+            }
+            return Some(self.take(length, cin));
+        }
+    }
+}
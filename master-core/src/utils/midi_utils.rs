@@ -1,4 +1,5 @@
 use midly::live::LiveEvent;
+use midly::num::u4;
 use midly::MidiMessage;
 
 // Not exhaustive
@@ -10,6 +11,12 @@ pub fn equivalent(m1: MidiMessage, m2: MidiMessage) -> bool {
     }
 }
 
+// Knobs for times are slow at the bottom, where small times matter
+pub fn cc_to_ms(value: u8, max: u16) -> u16 {
+    let value = value.min(127) as u32;
+    return (value * value * max as u32 / (127 * 127)) as u16;
+}
+
 pub fn event_length(event: LiveEvent) -> usize {
     1 + match event {
         LiveEvent::Midi { message, .. } => match message {
@@ -32,3 +39,29 @@ pub fn event_length(event: LiveEvent) -> usize {
         LiveEvent::Realtime(_) => 0,
     }
 }
+
+// Raw bytes of a channel message, padded with a zero if it only has one data byte
+pub fn encode_message(channel: u4, message: MidiMessage) -> [u8; 3] {
+    let ch = channel.as_int();
+    match message {
+        MidiMessage::NoteOff { key, vel } => [0x80 | ch, key.as_int(), vel.as_int()],
+        MidiMessage::NoteOn { key, vel } => [0x90 | ch, key.as_int(), vel.as_int()],
+        MidiMessage::Aftertouch { key, vel } => [0xa0 | ch, key.as_int(), vel.as_int()],
+        MidiMessage::Controller { controller, value } => {
+            [0xb0 | ch, controller.as_int(), value.as_int()]
+        }
+        MidiMessage::ProgramChange { program } => [0xc0 | ch, program.as_int(), 0],
+        MidiMessage::ChannelAftertouch { vel } => [0xd0 | ch, vel.as_int(), 0],
+        MidiMessage::PitchBend { bend } => {
+            let raw = bend.0.as_int();
+            [0xe0 | ch, (raw & 0x7f) as u8, (raw >> 7) as u8]
+        }
+    }
+}
+
+pub fn decode_message(raw: [u8; 3]) -> Option<(u4, MidiMessage)> {
+    match LiveEvent::parse(&raw) {
+        Ok(LiveEvent::Midi { channel, message }) => Some((channel, message)),
+        _ => None,
+    }
+}
@@ -1,2 +1,3 @@
 pub mod key_names;
 pub mod midi_utils;
+pub mod random;
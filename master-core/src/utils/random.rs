@@ -0,0 +1,25 @@
+// xorshift32, plenty for deciding whether a note plays and the same seed
+// always gives the same rolls
+#[derive(Copy, Clone)]
+pub struct Random(u32);
+
+impl Random {
+    pub fn new(seed: u32) -> Self {
+        // Zero would stay zero forever
+        Random(if seed == 0 { 0x9e37_79b9 } else { seed })
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.0 = x;
+        return x;
+    }
+
+    // True percent times out of a hundred
+    pub fn chance(&mut self, percent: u8) -> bool {
+        percent >= 100 || self.next_u32() % 100 < percent as u32
+    }
+}
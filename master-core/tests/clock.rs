@@ -0,0 +1,107 @@
+use master_core::clock::{Source, TempoClock, EXTERNAL_TIMEOUT};
+use midly::live::SystemRealtime;
+
+// Every tick the clock gives between from and to, polling once a millisecond
+fn run(clock: &mut TempoClock, from: u64, to: u64) -> Vec<u64> {
+    let mut ticks = Vec::new();
+    for now in (from..to).step_by(1_000) {
+        while let Some(msg) = clock.poll(now) {
+            if msg == SystemRealtime::TimingClock {
+                ticks.push(now);
+            }
+        }
+    }
+    ticks
+}
+
+#[test]
+fn ticks_24_times_per_beat() {
+    let mut clock = TempoClock::new();
+    assert!(clock.start(0));
+    assert!(!clock.start(0));
+    assert_eq!(clock.poll(0), Some(SystemRealtime::Start));
+    // 120 bpm, a beat every half second
+    let ticks = run(&mut clock, 0, 10_000_000);
+    assert_eq!(ticks.len(), 20 * 24);
+    assert_eq!(ticks[24], 500_000);
+    assert!(clock.stop());
+    assert!(!clock.stop());
+    assert_eq!(clock.poll(10_000_000), Some(SystemRealtime::Stop));
+    assert!(run(&mut clock, 10_000_000, 11_000_000).is_empty());
+}
+
+#[test]
+fn changes_tempo_without_skipping() {
+    let mut clock = TempoClock::new();
+    assert_eq!(clock.set_bpm(10, 0), None);
+    assert_eq!(clock.set_bpm(301, 0), None);
+    clock.start(0);
+    run(&mut clock, 0, 1_000_000);
+    clock.set_bpm(60, 1_000_000).unwrap();
+    assert_eq!(clock.bpm(), 60);
+    let ticks = run(&mut clock, 1_000_000, 3_000_000);
+    assert_eq!(ticks.len(), 2 * 24);
+    assert!(ticks.windows(2).all(|w| w[1] - w[0] >= 41_000));
+}
+
+#[test]
+fn gives_way_to_external_clock() {
+    let mut clock = TempoClock::new();
+    clock.start(0);
+    run(&mut clock, 0, 100_000);
+    clock.stop();
+    clock.poll(100_000);
+    assert_eq!(clock.external(SystemRealtime::TimingClock, 100_000), None);
+    assert_eq!(clock.source(100_000), Source::External);
+    assert!(!clock.is_running());
+    assert!(run(&mut clock, 100_000, 200_000).is_empty());
+    assert!(!clock.start(200_000));
+
+    let quiet = 100_000 + EXTERNAL_TIMEOUT;
+    assert_eq!(clock.source(quiet), Source::Internal);
+    assert_eq!(clock.poll(quiet), None);
+    assert!(clock.start(quiet));
+}
+
+#[test]
+fn hands_over_to_external_clock_and_back() {
+    let mut clock = TempoClock::new();
+    clock.start(0);
+    run(&mut clock, 0, 100_000);
+    assert_eq!(
+        clock.external(SystemRealtime::TimingClock, 100_000),
+        Some(SystemRealtime::Stop)
+    );
+    assert!(!clock.is_running());
+    assert_eq!(clock.external(SystemRealtime::TimingClock, 120_000), None);
+    assert!(run(&mut clock, 120_000, 120_000 + EXTERNAL_TIMEOUT).is_empty());
+
+    let quiet = 120_000 + EXTERNAL_TIMEOUT;
+    assert_eq!(clock.poll(quiet), Some(SystemRealtime::Start));
+    assert!(clock.is_running());
+    assert_eq!(run(&mut clock, quiet, quiet + 500_000).len(), 24);
+}
+
+#[test]
+fn stays_stopped_when_external_clock_stops() {
+    let mut clock = TempoClock::new();
+    clock.start(0);
+    run(&mut clock, 0, 100_000);
+    clock.external(SystemRealtime::TimingClock, 100_000);
+    assert_eq!(clock.external(SystemRealtime::Stop, 120_000), None);
+    let quiet = 120_000 + EXTERNAL_TIMEOUT;
+    assert!(run(&mut clock, quiet, quiet + 500_000).is_empty());
+    assert!(!clock.is_running());
+}
+
+#[test]
+fn does_not_burst_after_a_stall() {
+    let mut clock = TempoClock::new();
+    clock.start(0);
+    clock.poll(0);
+    let mut ticks = 0;
+    while clock.poll(1_000_000).is_some() {
+        ticks += 1;
+    }
+    assert!(ticks <= 2);
+}
@@ -0,0 +1,60 @@
+// Every test binary only uses some of these
+#![allow(dead_code)]
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use master_core::midi_mapper::{Config, MidiMapper};
+use master_core::outs::OutputRequest;
+use midly::live::LiveEvent;
+use midly::MidiMessage;
+
+pub type Shared<T> = Rc<RefCell<Vec<T>>>;
+
+pub fn close(a: f32, b: f32) -> bool {
+    (a - b).abs() < 0.001
+}
+
+pub fn message(channel: u8, message: MidiMessage) -> LiveEvent<'static> {
+    LiveEvent::Midi {
+        channel: channel.into(),
+        message,
+    }
+}
+
+pub fn note_on(channel: u8, key: u8, vel: u8) -> LiveEvent<'static> {
+    message(
+        channel,
+        MidiMessage::NoteOn {
+            key: key.into(),
+            vel: vel.into(),
+        },
+    )
+}
+
+pub fn note_off(channel: u8, key: u8) -> LiveEvent<'static> {
+    message(
+        channel,
+        MidiMessage::NoteOff {
+            key: key.into(),
+            vel: 0.into(),
+        },
+    )
+}
+
+pub fn cc(channel: u8, controller: u8, value: u8) -> LiveEvent<'static> {
+    message(
+        channel,
+        MidiMessage::Controller {
+            controller: controller.into(),
+            value: value.into(),
+        },
+    )
+}
+
+// A mapper with the requests it sends kept for looking at
+pub fn mapper_with(config: Config) -> (MidiMapper<Shared<OutputRequest>>, Shared<OutputRequest>) {
+    let outs: Shared<OutputRequest> = Rc::default();
+    let mapper = MidiMapper::new(config, outs.clone());
+    (mapper, outs)
+}
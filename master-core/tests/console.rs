@@ -0,0 +1,475 @@
+use master_core::console::{
+    self, CalibrateCommand, Command, DrumCommand, DynamicsCommand, LineReader, ParseError,
+    PlayerStatus, SongCommand,
+};
+use master_core::drums::{DrumMap, GateSet};
+use master_core::envelope::{Adsr, EnvelopeSettings};
+use master_core::glide::{Glide, GlideMode, Portamento};
+use master_core::lfo::{LfoSettings, Rate, Shape};
+use master_core::midi_mapper::{Allocation, Assign, Config, Steal};
+use master_core::outs::{Cv, Gate, OutputRequest};
+use master_core::player::{Player, PlayerAction, PlayerMessage, PATTERN_SIZE};
+use master_core::priority::{Order, Priority};
+use master_core::quantizer::{Quantizer, Scale};
+use master_core::song::{Arranger, Entry};
+use midly::live::LiveEvent;
+use midly::MidiMessage;
+
+type TestPlayer = Player<Vec<LiveEvent<'static>>, Vec<OutputRequest>>;
+
+#[test]
+fn parses_commands() {
+    assert_eq!(console::parse("help"), Ok(Command::Help));
+    assert_eq!(console::parse("  players  "), Ok(Command::Players));
+    assert_eq!(console::parse("dump 3"), Ok(Command::Dump(3)));
+    assert_eq!(
+        console::parse("config one_mono"),
+        Ok(Command::Config(Config::one_mono()))
+    );
+    assert_eq!(
+        console::parse("play"),
+        Ok(Command::Player(PlayerMessage::Broadcast(
+            PlayerAction::Play
+        )))
+    );
+    assert_eq!(
+        console::parse("mute 2"),
+        Ok(Command::Player(PlayerMessage::Action(
+            2,
+            PlayerAction::ToggleMute
+        )))
+    );
+    assert_eq!(
+        console::parse("save"),
+        Ok(Command::Player(PlayerMessage::Broadcast(
+            PlayerAction::Save
+        )))
+    );
+    assert_eq!(
+        console::parse("length 1 12"),
+        Ok(Command::Player(PlayerMessage::Action(
+            1,
+            PlayerAction::SetLength(12)
+        )))
+    );
+    assert_eq!(
+        console::parse("divisor 0 4"),
+        Ok(Command::Player(PlayerMessage::Action(
+            0,
+            PlayerAction::SetDivisor(4)
+        )))
+    );
+    assert_eq!(
+        console::parse("swing 3 60"),
+        Ok(Command::Player(PlayerMessage::Action(
+            3,
+            PlayerAction::SetSwing(Some(60))
+        )))
+    );
+    assert_eq!(
+        console::parse("swing 3 default"),
+        Ok(Command::Player(PlayerMessage::Action(
+            3,
+            PlayerAction::SetSwing(None)
+        )))
+    );
+    assert_eq!(
+        console::parse("swing 66"),
+        Ok(Command::Player(PlayerMessage::Broadcast(
+            PlayerAction::SetDefaultSwing(66)
+        )))
+    );
+    assert_eq!(
+        console::parse("chance 2 15 60 25"),
+        Ok(Command::Player(PlayerMessage::Action(
+            2,
+            PlayerAction::SetChance(15, 60, 25)
+        )))
+    );
+    assert_eq!(
+        console::parse("ratchet 0 3 4"),
+        Ok(Command::Player(PlayerMessage::Action(
+            0,
+            PlayerAction::SetRatchet(3, 4)
+        )))
+    );
+    assert_eq!(
+        console::parse("slot 2"),
+        Ok(Command::Player(PlayerMessage::Broadcast(
+            PlayerAction::SelectSlot(2)
+        )))
+    );
+    assert_eq!(
+        console::parse("song add 4 0 1 0 2 3"),
+        Ok(Command::Song(SongCommand::Append(
+            Entry::new([0, 1, 0, 2, 3], 4).unwrap()
+        )))
+    );
+    assert_eq!(
+        console::parse("song select 2"),
+        Ok(Command::Player(PlayerMessage::Broadcast(
+            PlayerAction::SongSelect(2)
+        )))
+    );
+    assert_eq!(
+        console::parse("song on"),
+        Ok(Command::Song(SongCommand::Enable(true)))
+    );
+    assert_eq!(console::parse("tempo"), Ok(Command::Tempo(None)));
+    assert_eq!(console::parse("tempo 96"), Ok(Command::Tempo(Some(96))));
+    assert_eq!(console::parse("scale"), Ok(Command::Scale(None)));
+    assert_eq!(
+        console::parse("scale b dorian d"),
+        Ok(Command::Scale(Some((
+            Cv::B,
+            Quantizer::new(Scale::Dorian, 2)
+        ))))
+    );
+    assert_eq!(
+        console::parse("scale a 100101010010"),
+        Ok(Command::Scale(Some((
+            Cv::A,
+            Quantizer::new(Scale::User(0b0100_1010_1001), 0)
+        ))))
+    );
+    assert_eq!(
+        console::parse("calibrate c 4 -25"),
+        Ok(Command::Calibrate(CalibrateCommand::Trim(Cv::C, 4, -25)))
+    );
+    assert_eq!(
+        console::parse("calibrate a 0"),
+        Ok(Command::Calibrate(CalibrateCommand::Reference(Cv::A, 0)))
+    );
+    assert_eq!(
+        console::parse("calibrate save"),
+        Ok(Command::Calibrate(CalibrateCommand::Save))
+    );
+    assert_eq!(
+        console::parse("glide d rate 250 legato"),
+        Ok(Command::Glide(Some((
+            Cv::D,
+            Portamento {
+                enabled: true,
+                legato: true,
+                glide: Glide {
+                    mode: GlideMode::Rate,
+                    ms: 250
+                }
+            }
+        ))))
+    );
+    assert_eq!(
+        console::parse("glide a off"),
+        Ok(Command::Glide(Some((Cv::A, Portamento::off()))))
+    );
+    assert_eq!(
+        console::parse("lfo c triangle 3/8"),
+        Ok(Command::Lfo(Some((
+            Cv::C,
+            Some(LfoSettings {
+                shape: Shape::Triangle,
+                rate: Rate::Clock(36)
+            })
+        ))))
+    );
+    assert_eq!(
+        console::parse("lfo c random 0.25"),
+        Ok(Command::Lfo(Some((
+            Cv::C,
+            Some(LfoSettings {
+                shape: Shape::Random,
+                rate: Rate::Free(25)
+            })
+        ))))
+    );
+    assert_eq!(
+        console::parse("env c a 10 250 60 400"),
+        Ok(Command::Envelope(Some((
+            Cv::C,
+            Some(EnvelopeSettings {
+                source: Cv::A,
+                adsr: Adsr {
+                    attack: 10,
+                    decay: 250,
+                    sustain: 60,
+                    release: 400
+                }
+            })
+        ))))
+    );
+    assert_eq!(console::parse("bend"), Ok(Command::Bend(None)));
+    assert_eq!(console::parse("bend 12"), Ok(Command::Bend(Some(12))));
+    assert_eq!(console::parse("priority"), Ok(Command::Priority(None)));
+    assert_eq!(
+        console::parse("drums gm"),
+        Ok(Command::Drums(DrumCommand::Preset(DrumMap::general_midi())))
+    );
+    assert_eq!(
+        console::parse("drums 38 a"),
+        Ok(Command::Drums(DrumCommand::Map(38, Some(Gate::GateA))))
+    );
+    assert_eq!(
+        console::parse("drums 38 off"),
+        Ok(Command::Drums(DrumCommand::Map(38, None)))
+    );
+    assert_eq!(
+        console::parse("accent 100"),
+        Ok(Command::Dynamics(DynamicsCommand::Accent(Some(100))))
+    );
+    assert_eq!(
+        console::parse("drumvel d"),
+        Ok(Command::Dynamics(DynamicsCommand::Velocity(Some(Cv::D))))
+    );
+    assert_eq!(console::parse("choke"), Ok(Command::Choke(None)));
+    assert_eq!(
+        console::parse("choke 1 chh ohh"),
+        Ok(Command::Choke(Some((
+            1,
+            GateSet::of(&[Gate::ClosedHH, Gate::OpenHH])
+        ))))
+    );
+    assert_eq!(
+        console::parse("choke 3 off"),
+        Ok(Command::Choke(Some((3, GateSet::empty()))))
+    );
+    assert_eq!(console::parse("trigger"), Ok(Command::Trigger(None)));
+    assert_eq!(
+        console::parse("trigger kick 5"),
+        Ok(Command::Trigger(Some((Gate::Kick, Some(5)))))
+    );
+    assert_eq!(
+        console::parse("trigger ohh off"),
+        Ok(Command::Trigger(Some((Gate::OpenHH, None))))
+    );
+    assert_eq!(
+        console::parse("unison 0 on"),
+        Ok(Command::Unison(Some((0, true))))
+    );
+    assert_eq!(
+        console::parse("detune c -7"),
+        Ok(Command::Detune(Some((Cv::C, -7))))
+    );
+    assert_eq!(
+        console::parse("voices 0 reuse quietest"),
+        Ok(Command::Voices(Some((
+            0,
+            Allocation {
+                assign: Assign::ReuseKey,
+                steal: Steal::Quietest
+            }
+        ))))
+    );
+    assert_eq!(
+        console::parse("priority 1 lowest retrigger"),
+        Ok(Command::Priority(Some((
+            1,
+            Priority {
+                order: Order::Lowest,
+                retrigger: true
+            }
+        ))))
+    );
+    assert_eq!(console::parse("overflows"), Ok(Command::Overflows));
+}
+
+#[test]
+fn rejects_bad_lines() {
+    assert_eq!(console::parse(""), Err(ParseError::Empty));
+    assert_eq!(console::parse("   "), Err(ParseError::Empty));
+    assert_eq!(console::parse("jump"), Err(ParseError::UnknownCommand));
+    assert_eq!(console::parse("dump"), Err(ParseError::MissingArgument));
+    assert_eq!(console::parse("tempo 5"), Err(ParseError::BadArgument));
+    assert_eq!(console::parse("swing 80"), Err(ParseError::BadArgument));
+    assert_eq!(
+        console::parse("song add 4 0 1 0"),
+        Err(ParseError::MissingArgument)
+    );
+    assert_eq!(
+        console::parse("song add 0 0 1 0 2 3"),
+        Err(ParseError::BadArgument)
+    );
+    assert_eq!(console::parse("slot 1 4"), Err(ParseError::BadArgument));
+    assert_eq!(
+        console::parse("ratchet 0 3 9"),
+        Err(ParseError::BadArgument)
+    );
+    assert_eq!(
+        console::parse("chance 2 15 60 101"),
+        Err(ParseError::BadArgument)
+    );
+    assert_eq!(
+        console::parse("chance 2 15"),
+        Err(ParseError::MissingArgument)
+    );
+    assert_eq!(console::parse("swing"), Err(ParseError::MissingArgument));
+    assert_eq!(console::parse("dump x"), Err(ParseError::BadArgument));
+    assert_eq!(console::parse("dump 16"), Err(ParseError::BadArgument));
+    assert_eq!(
+        console::parse("config eight_poly"),
+        Err(ParseError::BadArgument)
+    );
+    assert_eq!(console::parse("length 1"), Err(ParseError::MissingArgument));
+    assert_eq!(console::parse("length 1 33"), Err(ParseError::BadArgument));
+    assert_eq!(console::parse("divisor 1 0"), Err(ParseError::BadArgument));
+    assert_eq!(
+        console::parse("divisor 0 200"),
+        Err(ParseError::BadArgument)
+    );
+    assert_eq!(console::parse("divisor 0 5"), Err(ParseError::BadArgument));
+    assert_eq!(console::parse("play 1"), Err(ParseError::BadArgument));
+    assert_eq!(console::parse("scale a"), Err(ParseError::MissingArgument));
+    assert_eq!(
+        console::parse("scale e major"),
+        Err(ParseError::BadArgument)
+    );
+    assert_eq!(console::parse("scale a 1010"), Err(ParseError::BadArgument));
+    assert_eq!(
+        console::parse("calibrate a 6"),
+        Err(ParseError::BadArgument)
+    );
+    assert_eq!(
+        console::parse("calibrate a 1 600"),
+        Err(ParseError::BadArgument)
+    );
+    assert_eq!(
+        console::parse("calibrate a 1 -32768"),
+        Err(ParseError::BadArgument)
+    );
+    assert_eq!(
+        console::parse("scale a major h"),
+        Err(ParseError::BadArgument)
+    );
+    assert_eq!(
+        console::parse("priority 4 last"),
+        Err(ParseError::BadArgument)
+    );
+    assert_eq!(
+        console::parse("priority 0 first"),
+        Err(ParseError::BadArgument)
+    );
+    assert_eq!(console::parse("drums tr909"), Err(ParseError::BadArgument));
+    assert_eq!(console::parse("accent 0"), Err(ParseError::BadArgument));
+    assert_eq!(console::parse("drumvel e"), Err(ParseError::BadArgument));
+    assert_eq!(console::parse("choke 4 chh"), Err(ParseError::BadArgument));
+    assert_eq!(console::parse("choke 0 hat"), Err(ParseError::BadArgument));
+    assert_eq!(console::parse("choke 0"), Err(ParseError::MissingArgument));
+    assert_eq!(console::parse("trigger a 10"), Err(ParseError::BadArgument));
+    assert_eq!(
+        console::parse("trigger kick 0"),
+        Err(ParseError::BadArgument)
+    );
+    assert_eq!(
+        console::parse("trigger kick 251"),
+        Err(ParseError::BadArgument)
+    );
+    assert_eq!(
+        console::parse("trigger kick"),
+        Err(ParseError::MissingArgument)
+    );
+    assert_eq!(
+        console::parse("drums 128 kick"),
+        Err(ParseError::BadArgument)
+    );
+    assert_eq!(
+        console::parse("drums 36 cowbell"),
+        Err(ParseError::BadArgument)
+    );
+    assert_eq!(
+        console::parse("unison 0 maybe"),
+        Err(ParseError::BadArgument)
+    );
+    assert_eq!(console::parse("detune a 101"), Err(ParseError::BadArgument));
+    assert_eq!(
+        console::parse("detune a -128"),
+        Err(ParseError::BadArgument)
+    );
+    assert_eq!(
+        console::parse("voices 1 reuse"),
+        Err(ParseError::MissingArgument)
+    );
+    assert_eq!(
+        console::parse("voices 1 newest oldest"),
+        Err(ParseError::BadArgument)
+    );
+}
+
+#[test]
+fn reads_lines() {
+    let mut reader = LineReader::new();
+    let mut lines = Vec::new();
+    for byte in b"\r\nplx\x7fay\r\ndump 1\n".iter() {
+        if reader.push(*byte) {
+            lines.push(reader.line().to_string());
+        }
+    }
+    assert_eq!(lines, vec!["play", "dump 1"]);
+
+    let mut long = LineReader::new();
+    for _ in 0..100 {
+        long.push(b'a');
+    }
+    assert!(long.push(b'\r'));
+    assert_eq!(long.line().len(), console::LINE_SIZE);
+}
+
+#[test]
+fn describes_players_and_patterns() {
+    let mut p: TestPlayer = Player::new(1, 8, Vec::new(), Vec::new());
+    p.handle_message(PlayerMessage::Broadcast(PlayerAction::ToggleMute));
+    p.insert(
+        LiveEvent::Midi {
+            channel: 1.into(),
+            message: MidiMessage::NoteOn {
+                key: 60.into(),
+                vel: 100.into(),
+            },
+        },
+        2,
+        0.5,
+    );
+
+    let status = PlayerStatus::of(&p);
+    assert_eq!(status.length, 16);
+    assert_eq!(status.divisor, 8);
+    assert!(status.muted);
+    assert!(!status.held);
+    let mut out = String::new();
+    console::write_status(&mut out, status).unwrap();
+    assert_eq!(
+        out,
+        "ch 1  length 16  divisor  8  step  0  swing 50  slot 0    stopped  mute on  hold off\r\n"
+    );
+
+    let mut pattern = [0; PATTERN_SIZE];
+    let length = p.export_pattern(&mut pattern);
+    let mut out = String::new();
+    console::write_pattern(&mut out, &pattern[..length]).unwrap();
+    assert_eq!(
+        out,
+        "length 16  divisor 8  events 1\r\n 2.064  91 3c 64\r\n"
+    );
+
+    p.handle_message(PlayerMessage::Action(1, PlayerAction::SetChance(2, 60, 40)));
+    let length = p.export_pattern(&mut pattern);
+    let mut out = String::new();
+    console::write_pattern(&mut out, &pattern[..length]).unwrap();
+    assert!(out.ends_with(" 2.064  91 3c 64  40%\r\n"));
+
+    p.handle_message(PlayerMessage::Action(1, PlayerAction::SetRatchet(2, 4)));
+    let length = p.export_pattern(&mut pattern);
+    let mut out = String::new();
+    console::write_pattern(&mut out, &pattern[..length]).unwrap();
+    assert!(out.ends_with("40%\r\n 2  ratchet 4\r\n"));
+}
+
+#[test]
+fn describes_songs() {
+    let mut arranger = Arranger::new();
+    arranger.append(0, Entry::new([0, 1, 0, 2, 3], 4).unwrap());
+    let mut out = String::new();
+    console::write_song(&mut out, &arranger).unwrap();
+    assert_eq!(
+        out,
+        "song 0  off  entry 0  repeat 0\r\n 0  repeats  4  slots 0 1 0 2 3\r\n"
+    );
+}
@@ -0,0 +1,199 @@
+use master_core::drums::{
+    Chokes, DrumMap, Dynamics, GateSet, Triggers, DRUM_MAP_SIZE, MAX_TRIGGER_MS,
+};
+use master_core::envelope::{Adsr, EnvelopeSettings};
+use master_core::midi_mapper::Config;
+use master_core::outs::{Cv, Gate, OutputRequest};
+
+mod common;
+
+use common::{mapper_with, note_off, note_on};
+
+// The drum channel of the configs
+const DRUMS: u8 = 4;
+
+#[test]
+fn drum_maps_look_up_notes() {
+    let octave = DrumMap::octave();
+    assert_eq!(octave.gate(36), Some(Gate::Kick));
+    assert_eq!(octave.gate(67), Some(Gate::OpenHH));
+    assert_eq!(octave.gate(37), None);
+
+    let mut gm = DrumMap::general_midi();
+    assert_eq!(gm.gate(42), Some(Gate::ClosedHH));
+    assert_eq!(gm.gate(54), None);
+    assert_eq!(gm.preset_name(), Some("gm"));
+
+    // Setting a note again moves it, off takes it out
+    gm.set(42, Some(Gate::GateB)).unwrap();
+    gm.set(36, None).unwrap();
+    assert_eq!(gm.gate(42), Some(Gate::GateB));
+    assert_eq!(gm.gate(36), None);
+    assert_eq!(gm.preset_name(), None);
+
+    let mut full = DrumMap::empty(false);
+    for note in 0..DRUM_MAP_SIZE as u8 {
+        full.set(note, Some(Gate::FX)).unwrap();
+    }
+    assert_eq!(full.set(100, Some(Gate::FX)), None);
+    assert_eq!(full.set(0, Some(Gate::Kick)), Some(()));
+}
+
+#[test]
+fn mapper_fires_the_gates_in_its_drum_map() {
+    let (mut mapper, outs) = mapper_with(Config::one_mono());
+    mapper
+        .set_envelope(
+            Cv::C,
+            Some(EnvelopeSettings {
+                source: Cv::D,
+                adsr: Adsr::new(),
+            }),
+        )
+        .unwrap();
+    let mut drum_map = DrumMap::general_midi();
+    drum_map.set(60, Some(Gate::GateD)).unwrap();
+    mapper.set_drum_map(drum_map);
+    outs.borrow_mut().clear();
+
+    mapper.handle_message(note_on(DRUMS, 36, 100));
+    mapper.handle_message(note_on(DRUMS, 48, 100));
+    // Envelopes follow a voice gate the drums open
+    mapper.handle_message(note_on(DRUMS, 60, 100));
+    assert_eq!(
+        outs.borrow_mut().drain(..).collect::<Vec<_>>(),
+        vec![
+            OutputRequest::GateOn(Gate::Kick),
+            OutputRequest::GateOn(Gate::GateD),
+            OutputRequest::TriggerEnvelope(Cv::C, true)
+        ]
+    );
+}
+
+#[test]
+fn loud_hits_accent_and_send_their_velocity() {
+    let (mut mapper, outs) = mapper_with(Config::one_mono());
+    mapper.set_drum_map(DrumMap::general_midi());
+    let dynamics = Dynamics {
+        accent: Some(100),
+        velocity: Some(Cv::C),
+    };
+    assert_eq!(
+        mapper.set_dynamics(Dynamics {
+            velocity: Some(Cv::A),
+            ..dynamics
+        }),
+        None
+    );
+    assert_eq!(mapper.set_dynamics(dynamics), Some(()));
+    outs.borrow_mut().clear();
+
+    // The accent stays open until the last loud hit is let go
+    mapper.handle_message(note_on(DRUMS, 36, 127));
+    mapper.handle_message(note_on(DRUMS, 38, 110));
+    mapper.handle_message(note_on(DRUMS, 42, 50));
+    mapper.handle_message(note_off(DRUMS, 36));
+    mapper.handle_message(note_off(DRUMS, 38));
+    // No velocity is a release too
+    mapper.handle_message(note_on(DRUMS, 42, 0));
+    assert_eq!(
+        outs.borrow_mut().drain(..).collect::<Vec<_>>(),
+        vec![
+            OutputRequest::SetVal(Cv::C, 1.0),
+            OutputRequest::GateOn(Gate::Accent),
+            OutputRequest::GateOn(Gate::Kick),
+            OutputRequest::SetVal(Cv::C, 110.0 / 127.0),
+            OutputRequest::GateOn(Gate::Accent),
+            OutputRequest::GateOn(Gate::Snare),
+            OutputRequest::SetVal(Cv::C, 50.0 / 127.0),
+            OutputRequest::GateOn(Gate::ClosedHH),
+            OutputRequest::GateOff(Gate::Kick),
+            OutputRequest::GateOff(Gate::Snare),
+            OutputRequest::GateOff(Gate::Accent),
+            OutputRequest::GateOff(Gate::ClosedHH)
+        ]
+    );
+}
+
+#[test]
+fn hits_choke_the_gates_in_their_groups() {
+    let chokes = Chokes::hi_hats();
+    assert_eq!(
+        chokes.choked_by(Gate::ClosedHH),
+        GateSet::of(&[Gate::OpenHH])
+    );
+    assert!(chokes.choked_by(Gate::Kick).is_empty());
+
+    let (mut mapper, outs) = mapper_with(Config::one_mono());
+    mapper.set_drum_map(DrumMap::general_midi());
+    outs.borrow_mut().clear();
+
+    // Only gates that are still open get closed
+    mapper.handle_message(note_on(DRUMS, 46, 100));
+    mapper.handle_message(note_on(DRUMS, 42, 100));
+    mapper.handle_message(note_on(DRUMS, 44, 100));
+    assert_eq!(
+        outs.borrow_mut().drain(..).collect::<Vec<_>>(),
+        vec![
+            OutputRequest::GateOn(Gate::OpenHH),
+            OutputRequest::GateOff(Gate::OpenHH),
+            OutputRequest::GateOn(Gate::ClosedHH),
+            OutputRequest::GateOn(Gate::ClosedHH)
+        ]
+    );
+
+    let mut chokes = Chokes::none();
+    chokes.groups[1] = GateSet::of(&[Gate::Snare, Gate::Clap]);
+    mapper.set_chokes(chokes);
+    mapper.handle_message(note_on(DRUMS, 46, 100));
+    mapper.handle_message(note_on(DRUMS, 39, 100));
+    mapper.handle_message(note_on(DRUMS, 38, 100));
+    assert_eq!(
+        outs.borrow_mut().drain(..).collect::<Vec<_>>(),
+        vec![
+            OutputRequest::GateOn(Gate::OpenHH),
+            OutputRequest::GateOn(Gate::Clap),
+            OutputRequest::GateOff(Gate::Clap),
+            OutputRequest::GateOn(Gate::Snare)
+        ]
+    );
+}
+
+#[test]
+fn triggered_gates_outlast_their_notes_but_not_chokes() {
+    let mut triggers = Triggers::off();
+    assert_eq!(triggers.set(Gate::GateA, Some(10)), None);
+    assert_eq!(triggers.set(Gate::Kick, Some(0)), None);
+    assert_eq!(triggers.set(Gate::Kick, Some(MAX_TRIGGER_MS + 1)), None);
+    triggers.set(Gate::Kick, Some(5)).unwrap();
+    triggers.set(Gate::OpenHH, Some(200)).unwrap();
+    assert_eq!(
+        triggers.lengths().collect::<Vec<_>>(),
+        vec![(Gate::Kick, 5), (Gate::OpenHH, 200)]
+    );
+
+    let (mut mapper, outs) = mapper_with(Config::one_mono());
+    mapper.set_drum_map(DrumMap::general_midi());
+    mapper.set_triggers(triggers);
+    outs.borrow_mut().clear();
+
+    mapper.handle_message(note_on(DRUMS, 36, 100));
+    mapper.handle_message(note_off(DRUMS, 36));
+    mapper.handle_message(note_on(DRUMS, 38, 100));
+    mapper.handle_message(note_off(DRUMS, 38));
+    // The open hi-hat rings on past its note off until the closed one cuts it
+    mapper.handle_message(note_on(DRUMS, 46, 100));
+    mapper.handle_message(note_off(DRUMS, 46));
+    mapper.handle_message(note_on(DRUMS, 42, 100));
+    assert_eq!(
+        outs.borrow_mut().drain(..).collect::<Vec<_>>(),
+        vec![
+            OutputRequest::Trigger(Gate::Kick, 5),
+            OutputRequest::GateOn(Gate::Snare),
+            OutputRequest::GateOff(Gate::Snare),
+            OutputRequest::Trigger(Gate::OpenHH, 200),
+            OutputRequest::GateOff(Gate::OpenHH),
+            OutputRequest::GateOn(Gate::ClosedHH)
+        ]
+    );
+}
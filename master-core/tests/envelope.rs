@@ -0,0 +1,116 @@
+use master_core::envelope::{Adsr, Envelope, EnvelopeSettings, MAX_STAGE_MS, SUSTAIN_CC};
+use master_core::glide::{Glide, MAX_GLIDE_MS};
+use master_core::lfo::{LfoSettings, Rate, Shape};
+use master_core::midi_mapper::Config;
+use master_core::outs::{Cv, OutputRequest};
+use master_core::utils::midi_utils::cc_to_ms;
+
+mod common;
+
+use common::{cc, close, mapper_with, note_off, note_on};
+
+#[test]
+fn envelope_goes_through_its_stages() {
+    let mut envelope = Envelope::new(Adsr {
+        attack: 10,
+        decay: 20,
+        sustain: 50,
+        release: 100,
+    });
+    assert!(envelope.is_idle());
+
+    envelope.gate(true, 1_000);
+    envelope.advance(6_000);
+    assert!(close(envelope.value(), 0.5));
+    envelope.advance(11_000);
+    assert!(close(envelope.value(), 1.0));
+    envelope.advance(21_000);
+    assert!(close(envelope.value(), 0.75));
+    envelope.advance(500_000);
+    assert!(close(envelope.value(), 0.5));
+
+    // Release takes its time from wherever the level is
+    envelope.gate(false, 500_000);
+    envelope.advance(550_000);
+    assert!(close(envelope.value(), 0.25));
+    envelope.advance(600_000);
+    assert!(close(envelope.value(), 0.0));
+    assert!(envelope.is_idle());
+
+    // No times at all is a plain gate at the sustain level
+    let mut envelope = Envelope::new(Adsr {
+        attack: 0,
+        decay: 0,
+        sustain: 80,
+        release: 0,
+    });
+    envelope.gate(true, 0);
+    assert!(close(envelope.value(), 0.8));
+    envelope.gate(false, 1);
+    assert!(envelope.is_idle());
+
+    // Knobs follow the same curve as the glide time
+    assert_eq!(Adsr::ms_from_cc(0), 0);
+    assert_eq!(Adsr::ms_from_cc(127), MAX_STAGE_MS);
+    assert_eq!(Adsr::ms_from_cc(64), cc_to_ms(64, MAX_STAGE_MS));
+    assert_eq!(Glide::ms_from_cc(64), cc_to_ms(64, MAX_GLIDE_MS));
+}
+
+#[test]
+fn mapper_triggers_envelopes_with_the_gate_they_follow() {
+    let (mut mapper, outs) = mapper_with(Config::one_mono());
+    let settings = EnvelopeSettings {
+        source: Cv::A,
+        adsr: Adsr::new(),
+    };
+
+    assert_eq!(mapper.set_envelope(Cv::B, Some(settings)), None);
+    assert_eq!(
+        mapper.set_envelope(
+            Cv::C,
+            Some(EnvelopeSettings {
+                source: Cv::C,
+                adsr: Adsr::new()
+            })
+        ),
+        None
+    );
+    // Takes over from an LFO on the same port
+    let lfo = LfoSettings {
+        shape: Shape::Saw,
+        rate: Rate::Free(100),
+    };
+    mapper.set_lfo(Cv::C, Some(lfo)).unwrap();
+    assert_eq!(mapper.set_envelope(Cv::C, Some(settings)), Some(()));
+    assert_eq!(mapper.lfo(Cv::C), None);
+    outs.borrow_mut().clear();
+
+    mapper.handle_message(note_on(0, 60, 100));
+    mapper.handle_message(cc(0, SUSTAIN_CC, 127));
+    mapper.handle_message(note_off(0, 60));
+    let sustained = EnvelopeSettings {
+        adsr: Adsr {
+            sustain: 100,
+            ..settings.adsr
+        },
+        ..settings
+    };
+    let requests: Vec<OutputRequest> = outs
+        .borrow_mut()
+        .drain(..)
+        .filter(|request| {
+            matches!(
+                request,
+                OutputRequest::SetEnvelope(..) | OutputRequest::TriggerEnvelope(..)
+            )
+        })
+        .collect();
+    assert_eq!(
+        requests,
+        vec![
+            OutputRequest::TriggerEnvelope(Cv::C, true),
+            OutputRequest::SetEnvelope(Cv::C, Some(sustained)),
+            OutputRequest::TriggerEnvelope(Cv::C, false)
+        ]
+    );
+}
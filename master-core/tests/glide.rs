@@ -0,0 +1,88 @@
+use master_core::glide::{Glide, GlideMode, Portamento, Slew, SWITCH_CC, TIME_CC};
+use master_core::midi_mapper::Config;
+use master_core::outs::{Cv, OutputRequest};
+
+mod common;
+
+use common::{cc, mapper_with, note_off, note_on, Shared};
+
+fn pitches(outs: &Shared<OutputRequest>) -> Vec<OutputRequest> {
+    outs.borrow_mut()
+        .drain(..)
+        .filter(|request| {
+            matches!(
+                request,
+                OutputRequest::SetNote(..) | OutputRequest::GlideNote(..)
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn slews_take_time_or_go_at_a_rate() {
+    let time = Glide {
+        mode: GlideMode::Time,
+        ms: 100,
+    };
+    let slew = Slew::new(1.0, 3.0, 1_000, time);
+    assert_eq!(slew.at(1_000), 1.0);
+    assert_eq!(slew.at(51_000), 2.0);
+    assert!(!slew.is_done(100_999));
+    assert_eq!(slew.at(101_000), 3.0);
+    assert!(slew.is_done(101_000));
+
+    // 100 ms per octave, two octaves down
+    let rate = Glide {
+        mode: GlideMode::Rate,
+        ms: 100,
+    };
+    let slew = Slew::new(3.0, 1.0, 0, rate);
+    assert_eq!(slew.at(100_000), 2.0);
+    assert!(slew.is_done(200_000));
+
+    assert_eq!(Glide::ms_from_cc(0), 0);
+    assert_eq!(Glide::ms_from_cc(127), 2000);
+}
+
+#[test]
+fn mapper_glides_on_legato_notes_only_when_asked() {
+    let (mut mapper, outs) = mapper_with(Config::one_mono());
+    mapper.handle_message(cc(0, TIME_CC, 127));
+
+    // Off until the switch says otherwise
+    mapper.handle_message(note_on(0, 48, 100));
+    mapper.handle_message(cc(0, SWITCH_CC, 127));
+    mapper.handle_message(note_off(0, 48));
+    mapper.handle_message(note_on(0, 60, 100));
+    let glide = mapper.portamento(Cv::A).glide;
+    assert_eq!(glide.ms, 2000);
+    assert_eq!(
+        pitches(&outs),
+        vec![
+            OutputRequest::SetNote(Cv::A, 48),
+            OutputRequest::GlideNote(Cv::A, 60, glide)
+        ]
+    );
+
+    mapper.set_portamento(
+        Cv::A,
+        Portamento {
+            enabled: true,
+            legato: true,
+            glide,
+        },
+    );
+    mapper.handle_message(note_off(0, 60));
+    mapper.handle_message(note_on(0, 62, 100));
+    mapper.handle_message(note_on(0, 64, 100));
+    // Back to the note still held
+    mapper.handle_message(note_off(0, 64));
+    assert_eq!(
+        pitches(&outs),
+        vec![
+            OutputRequest::SetNote(Cv::A, 62),
+            OutputRequest::GlideNote(Cv::A, 64, glide),
+            OutputRequest::GlideNote(Cv::A, 62, glide)
+        ]
+    );
+}
@@ -0,0 +1,103 @@
+use master_core::lfo::{Lfo, LfoSettings, Rate, Shape};
+use master_core::midi_mapper::Config;
+use master_core::outs::{Cv, OutputRequest};
+use midly::live::{LiveEvent, SystemRealtime};
+
+mod common;
+
+use common::{close, mapper_with};
+
+#[test]
+fn free_lfos_follow_their_shape() {
+    // 1 Hz, a quarter of a cycle is 250 ms
+    let lfo_at = |shape: Shape, micros: u64| {
+        let mut lfo = Lfo::new(
+            LfoSettings {
+                shape,
+                rate: Rate::Free(100),
+            },
+            1,
+        );
+        lfo.advance(0);
+        lfo.advance(micros);
+        lfo.value()
+    };
+    assert!(close(lfo_at(Shape::Sine, 0), 0.5));
+    assert!(close(lfo_at(Shape::Sine, 250_000), 1.0));
+    assert!(close(lfo_at(Shape::Sine, 750_000), 0.0));
+    assert!(close(lfo_at(Shape::Triangle, 250_000), 0.5));
+    assert!(close(lfo_at(Shape::Triangle, 750_000), 0.5));
+    assert!(close(lfo_at(Shape::Saw, 750_000), 0.75));
+    assert!(close(lfo_at(Shape::Square, 250_000), 1.0));
+    assert!(close(lfo_at(Shape::Square, 750_000), 0.0));
+    // Wraps around into the next cycle
+    assert!(close(lfo_at(Shape::Saw, 1_250_000), 0.25));
+}
+
+#[test]
+fn synced_lfos_snap_to_the_clock() {
+    // A quarter note at 20 ms a tick
+    let mut lfo = Lfo::new(
+        LfoSettings {
+            shape: Shape::Saw,
+            rate: Rate::from_division(1, 4).unwrap(),
+        },
+        1,
+    );
+    assert_eq!(Rate::from_division(1, 4), Some(Rate::Clock(24)));
+    assert_eq!(Rate::from_division(1, 5), None);
+
+    lfo.clock(0, 0);
+    lfo.clock(1, 20_000);
+    lfo.advance(30_000);
+    assert!(close(lfo.value(), 1.5 / 24.0));
+    // Never runs ahead of the next tick
+    lfo.advance(200_000);
+    assert!(close(lfo.value(), 2.0 / 24.0));
+    lfo.clock(30, 220_000);
+    assert!(close(lfo.value(), 6.0 / 24.0));
+}
+
+#[test]
+fn mapper_gives_lfos_to_free_ports_only() {
+    let (mut mapper, outs) = mapper_with(Config::two_fancy_mono());
+    let synced = LfoSettings {
+        shape: Shape::Sine,
+        rate: Rate::Clock(96),
+    };
+
+    // Voices, velocity and modulation take all four
+    for cv in [Cv::A, Cv::B, Cv::C, Cv::D] {
+        assert_eq!(mapper.set_lfo(cv, Some(synced)), None);
+    }
+
+    mapper.set_config(Config::one_mono());
+    outs.borrow_mut().clear();
+    assert_eq!(mapper.set_lfo(Cv::C, Some(synced)), Some(()));
+    mapper.handle_message(LiveEvent::Realtime(SystemRealtime::Start));
+    mapper.handle_message(LiveEvent::Realtime(SystemRealtime::TimingClock));
+    mapper.handle_message(LiveEvent::Realtime(SystemRealtime::TimingClock));
+    let requests: Vec<OutputRequest> = outs
+        .borrow_mut()
+        .drain(..)
+        .filter(|request| {
+            matches!(
+                request,
+                OutputRequest::SetLfo(..) | OutputRequest::LfoClock(_)
+            )
+        })
+        .collect();
+    assert_eq!(
+        requests,
+        vec![
+            OutputRequest::SetLfo(Cv::C, Some(synced)),
+            OutputRequest::LfoClock(0),
+            OutputRequest::LfoClock(1)
+        ]
+    );
+
+    // Taken back when a config wants the port
+    mapper.set_config(Config::one_duo());
+    assert_eq!(mapper.lfo(Cv::C), None);
+    assert!(outs.borrow().contains(&OutputRequest::SetLfo(Cv::C, None)));
+}
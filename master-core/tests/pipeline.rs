@@ -0,0 +1,476 @@
+use std::rc::Rc;
+
+use master_core::commando_unit::Operation;
+use master_core::midi_mapper::Config;
+use master_core::outs::{Cv, Gate, OutputRequest};
+use master_core::player::{Player, PlayerAction, PlayerMessage, PATTERN_SIZE};
+use master_core::priority::{Order, Priority};
+use master_core::prorgrammer::Programmer;
+use master_core::quantizer::{Quantizer, Scale, ROOT_CC, SCALE_CC};
+use midly::live::LiveEvent;
+use midly::{MidiMessage, PitchBend};
+
+mod common;
+
+use common::{cc, mapper_with, message, note_off, note_on, Shared};
+
+#[test]
+fn player_emits_inserted_note_on_first_tick() {
+    let midi: Shared<LiveEvent<'static>> = Rc::default();
+    let outs: Shared<OutputRequest> = Rc::default();
+    let mut player = Player::new(0, 8, midi.clone(), outs.clone());
+
+    player.handle_message(PlayerMessage::Action(
+        0,
+        PlayerAction::Insert(note_on(0, 60, 100), 0, 0.0),
+    ));
+    player.handle_message(PlayerMessage::Broadcast(PlayerAction::Play));
+    player.handle_message(PlayerMessage::Broadcast(PlayerAction::Tick));
+
+    assert_eq!(*midi.borrow(), vec![note_on(0, 60, 100)]);
+}
+
+#[test]
+fn player_emits_event_on_a_tick_once() {
+    let midi: Shared<LiveEvent<'static>> = Rc::default();
+    let mut player = Player::new(0, 4, midi.clone(), Vec::new());
+
+    // Step 1 starts right on a tick, 24 ticks in at divisor 4
+    player.insert(note_on(0, 62, 100), 1, 0.0);
+    player.handle_message(PlayerMessage::Broadcast(PlayerAction::Play));
+    for _ in 0..30 {
+        player.handle_message(PlayerMessage::Broadcast(PlayerAction::Tick));
+    }
+
+    assert_eq!(*midi.borrow(), vec![note_on(0, 62, 100)]);
+}
+
+#[test]
+fn player_ignores_actions_for_other_channels() {
+    let midi: Shared<LiveEvent<'static>> = Rc::default();
+    let mut player = Player::new(1, 8, midi.clone(), Vec::new());
+
+    player.handle_message(PlayerMessage::Action(
+        0,
+        PlayerAction::Insert(note_on(0, 60, 100), 0, 0.0),
+    ));
+    player.handle_message(PlayerMessage::Broadcast(PlayerAction::Play));
+    player.handle_message(PlayerMessage::Broadcast(PlayerAction::Tick));
+
+    assert!(midi.borrow().is_empty());
+}
+
+#[test]
+fn player_keeps_its_divisor_when_asked_for_one_that_does_not_fit() {
+    let midi: Shared<LiveEvent<'static>> = Rc::default();
+    let mut player = Player::new(0, 8, midi.clone(), Vec::new());
+    for divisor in [0, 5, 200] {
+        player.handle_message(PlayerMessage::Action(0, PlayerAction::SetDivisor(divisor)));
+    }
+    assert_eq!(player.divisor(), 8);
+    player.handle_message(PlayerMessage::Action(0, PlayerAction::SetDivisor(32)));
+    assert_eq!(player.divisor(), 32);
+
+    player.handle_message(PlayerMessage::Broadcast(PlayerAction::Play));
+    player.handle_message(PlayerMessage::Broadcast(PlayerAction::Tick));
+}
+
+#[test]
+fn mapper_sets_note_and_gate() {
+    let (mut mapper, outs) = mapper_with(Config::four_poly());
+
+    mapper.handle_message(note_on(0, 48, 100));
+
+    assert_eq!(
+        *outs.borrow(),
+        vec![
+            OutputRequest::SetNote(Cv::A, 48),
+            OutputRequest::GateOn(Gate::GateA)
+        ]
+    );
+}
+
+#[test]
+fn mapper_quantizes_notes_per_port() {
+    let (mut mapper, outs) = mapper_with(Config::four_indie());
+    mapper.set_quantizer(Cv::A, Quantizer::new(Scale::Major, 2));
+    let notes = |outs: &Shared<OutputRequest>| -> Vec<OutputRequest> {
+        outs.borrow_mut()
+            .drain(..)
+            .filter(|request| matches!(request, OutputRequest::SetNote(..)))
+            .collect()
+    };
+
+    // F is not in D major, F# is as close as E so it goes down
+    mapper.handle_message(note_on(0, 65, 100));
+    mapper.handle_message(note_on(0, 63, 100));
+    // Other ports play what they get
+    mapper.handle_message(note_on(1, 65, 100));
+    assert_eq!(
+        notes(&outs),
+        vec![
+            OutputRequest::SetNote(Cv::A, 64),
+            OutputRequest::SetNote(Cv::A, 62),
+            OutputRequest::SetNote(Cv::B, 65)
+        ]
+    );
+
+    // Minor pentatonic on C for the ports of channel 1
+    mapper.handle_message(cc(1, SCALE_CC, 127));
+    mapper.handle_message(cc(1, ROOT_CC, 12));
+    mapper.handle_message(note_on(1, 62, 100));
+    assert_eq!(notes(&outs), vec![OutputRequest::SetNote(Cv::B, 63)]);
+    assert_eq!(
+        mapper.quantizer(Cv::B),
+        Quantizer::new(Scale::MinorPentatonic, 0)
+    );
+}
+
+#[test]
+fn mapper_bends_every_port_of_the_channel() {
+    let (mut mapper, outs) = mapper_with(Config::two_poly());
+    let bend = |channel: u8, amount: f32| {
+        message(
+            channel,
+            MidiMessage::PitchBend {
+                bend: PitchBend::from_f32(amount),
+            },
+        )
+    };
+
+    mapper.handle_message(bend(1, 0.5));
+    assert_eq!(
+        outs.borrow_mut().drain(..).collect::<Vec<_>>(),
+        vec![
+            OutputRequest::SetBend(Cv::C, 1.0 / 12.0),
+            OutputRequest::SetBend(Cv::D, 1.0 / 12.0)
+        ]
+    );
+
+    mapper.set_bend_range(12);
+    mapper.handle_message(bend(0, -1.0));
+    assert_eq!(
+        outs.borrow_mut().drain(..).collect::<Vec<_>>(),
+        vec![
+            OutputRequest::SetBend(Cv::A, -1.0),
+            OutputRequest::SetBend(Cv::B, -1.0)
+        ]
+    );
+}
+
+#[test]
+fn mapper_sounds_held_keys_by_priority() {
+    let (mut mapper, outs) = mapper_with(Config::two_mono());
+    let pitch_and_gate = |outs: &Shared<OutputRequest>| -> Vec<OutputRequest> {
+        outs.borrow_mut()
+            .drain(..)
+            .filter(|request| !matches!(request, OutputRequest::SetVal(..)))
+            .collect()
+    };
+    let lowest = Priority {
+        order: Order::Lowest,
+        retrigger: false,
+    };
+    assert_eq!(mapper.set_priority(1, lowest), Some(()));
+    assert_eq!(mapper.set_priority(4, lowest), None);
+
+    // A higher key waits under the one held, and lifting it changes nothing
+    mapper.handle_message(note_on(1, 48, 100));
+    mapper.handle_message(note_on(1, 60, 100));
+    mapper.handle_message(note_off(1, 60));
+    mapper.handle_message(note_on(1, 55, 100));
+    mapper.handle_message(note_on(1, 43, 100));
+    mapper.handle_message(note_off(1, 43));
+    assert_eq!(
+        pitch_and_gate(&outs),
+        vec![
+            OutputRequest::SetNote(Cv::C, 48),
+            OutputRequest::GateOn(Gate::GateC),
+            OutputRequest::SetNote(Cv::C, 43),
+            OutputRequest::GateOn(Gate::GateC),
+            OutputRequest::SetNote(Cv::C, 48)
+        ]
+    );
+
+    // Channel 0 keeps last note priority, handing back retriggers the gate
+    mapper.set_priority(
+        0,
+        Priority {
+            order: Order::Last,
+            retrigger: true,
+        },
+    );
+    mapper.handle_message(note_on(0, 60, 100));
+    mapper.handle_message(note_on(0, 48, 100));
+    mapper.handle_message(note_off(0, 48));
+    assert_eq!(
+        pitch_and_gate(&outs),
+        vec![
+            OutputRequest::SetNote(Cv::A, 60),
+            OutputRequest::GateOn(Gate::GateA),
+            OutputRequest::SetNote(Cv::A, 48),
+            OutputRequest::GateOn(Gate::GateA),
+            OutputRequest::GateOff(Gate::GateA),
+            OutputRequest::SetNote(Cv::A, 60),
+            OutputRequest::GateOn(Gate::GateA)
+        ]
+    );
+}
+
+#[test]
+fn mapper_stacks_unison_notes_on_every_port() {
+    let (mut mapper, outs) = mapper_with(Config::two_poly());
+    assert_eq!(mapper.set_unison(1, true), Some(()));
+    assert_eq!(mapper.set_unison(4, true), None);
+    mapper.set_detune(Cv::D, 120);
+    assert_eq!(mapper.detune(Cv::D), 100);
+    outs.borrow_mut().clear();
+
+    mapper.handle_message(note_on(1, 48, 100));
+    mapper.handle_message(note_on(1, 52, 100));
+    mapper.handle_message(note_off(1, 52));
+    mapper.handle_message(note_off(1, 48));
+    assert_eq!(
+        outs.borrow_mut().drain(..).collect::<Vec<_>>(),
+        vec![
+            OutputRequest::SetNote(Cv::C, 48),
+            OutputRequest::GateOn(Gate::GateC),
+            OutputRequest::SetNote(Cv::D, 48),
+            OutputRequest::GateOn(Gate::GateD),
+            OutputRequest::SetNote(Cv::C, 52),
+            OutputRequest::GateOn(Gate::GateC),
+            OutputRequest::SetNote(Cv::D, 52),
+            OutputRequest::GateOn(Gate::GateD),
+            OutputRequest::SetNote(Cv::C, 48),
+            OutputRequest::SetNote(Cv::D, 48),
+            OutputRequest::GateOff(Gate::GateC),
+            OutputRequest::GateOff(Gate::GateD)
+        ]
+    );
+
+    // Channel 0 still hands out one port per note
+    mapper.handle_message(note_on(0, 48, 100));
+    mapper.handle_message(note_on(0, 52, 100));
+    assert_eq!(
+        outs.borrow_mut().drain(..).collect::<Vec<_>>(),
+        vec![
+            OutputRequest::SetNote(Cv::A, 48),
+            OutputRequest::GateOn(Gate::GateA),
+            OutputRequest::SetNote(Cv::B, 52),
+            OutputRequest::GateOn(Gate::GateB)
+        ]
+    );
+}
+
+#[test]
+fn swing_delays_every_second_step() {
+    let note_off = LiveEvent::Midi {
+        channel: 0.into(),
+        message: MidiMessage::NoteOff {
+            key: 62.into(),
+            vel: 0.into(),
+        },
+    };
+    // Which tick every event comes out on, a step is 24 ticks
+    let played = |swing: Option<u8>| {
+        let midi: Shared<LiveEvent<'static>> = Rc::default();
+        let mut player = Player::new(0, 4, midi.clone(), Vec::new());
+        player.handle_message(PlayerMessage::Broadcast(PlayerAction::SetDefaultSwing(75)));
+        player.handle_message(PlayerMessage::Action(0, PlayerAction::SetSwing(swing)));
+        player.insert(note_on(0, 60, 100), 0, 0.0);
+        player.insert(note_on(0, 62, 100), 1, 0.0);
+        player.insert(note_off, 1, 0.5);
+        player.insert(note_on(0, 64, 100), 2, 0.0);
+        player.handle_message(PlayerMessage::Broadcast(PlayerAction::Play));
+        let mut ticks = Vec::new();
+        for tick in 1..=72 {
+            player.handle_message(PlayerMessage::Broadcast(PlayerAction::Tick));
+            for event in midi.borrow_mut().drain(..) {
+                ticks.push((tick, event));
+            }
+        }
+        ticks
+    };
+
+    assert_eq!(
+        played(Some(50)),
+        vec![
+            (1, note_on(0, 60, 100)),
+            (25, note_on(0, 62, 100)),
+            (37, note_off),
+            (49, note_on(0, 64, 100))
+        ]
+    );
+    // Follows the default, the second step and its note off start late
+    assert_eq!(
+        played(None),
+        vec![
+            (1, note_on(0, 60, 100)),
+            (37, note_on(0, 62, 100)),
+            (43, note_off),
+            (49, note_on(0, 64, 100))
+        ]
+    );
+}
+
+#[test]
+fn chance_decides_note_on_and_off_together() {
+    let note_off = LiveEvent::Midi {
+        channel: 0.into(),
+        message: MidiMessage::NoteOff {
+            key: 60.into(),
+            vel: 0.into(),
+        },
+    };
+    // What came out over a number of loops of a two step pattern
+    let played = |chance: u8, seed: u32| {
+        let midi: Shared<LiveEvent<'static>> = Rc::default();
+        let mut player = Player::new(0, 4, midi.clone(), Vec::new());
+        player.seed(seed);
+        player.handle_message(PlayerMessage::Action(0, PlayerAction::SetLength(2)));
+        player.insert(note_on(0, 60, 100), 0, 0.0);
+        player.insert(note_off, 0, 0.5);
+        player.handle_message(PlayerMessage::Action(
+            0,
+            PlayerAction::SetChance(0, 60, chance),
+        ));
+        player.handle_message(PlayerMessage::Broadcast(PlayerAction::Play));
+        for _ in 0..48 * 100 {
+            player.handle_message(PlayerMessage::Broadcast(PlayerAction::Tick));
+        }
+        let events = midi.borrow().clone();
+        events
+    };
+
+    assert!(played(0, 1).is_empty());
+    assert_eq!(played(100, 1).len(), 200);
+
+    let half = played(50, 7);
+    assert_eq!(half, played(50, 7));
+    assert_ne!(half, played(50, 8));
+    let ons = half.iter().filter(|e| **e == note_on(0, 60, 100)).count();
+    assert!((30..70).contains(&ons));
+    // Every note on that played is followed by its note off and nothing else
+    for pair in half.chunks(2) {
+        assert_eq!(pair, [note_on(0, 60, 100), note_off]);
+    }
+}
+
+#[test]
+fn ratchet_repeats_a_step_within_itself() {
+    let note_off = LiveEvent::Midi {
+        channel: 0.into(),
+        message: MidiMessage::NoteOff {
+            key: 60.into(),
+            vel: 0.into(),
+        },
+    };
+    let midi: Shared<LiveEvent<'static>> = Rc::default();
+    let mut player = Player::new(0, 4, midi.clone(), Vec::new());
+    player.handle_message(PlayerMessage::Action(0, PlayerAction::SetLength(2)));
+    player.insert(note_on(0, 60, 100), 0, 0.0);
+    player.insert(note_off, 0, 0.5);
+    player.insert(note_on(0, 60, 100), 1, 0.0);
+    player.insert(note_off, 1, 0.5);
+    player.handle_message(PlayerMessage::Action(0, PlayerAction::SetRatchet(0, 3)));
+    assert_eq!(player.ratchet(0), 3);
+    player.handle_message(PlayerMessage::Broadcast(PlayerAction::Play));
+
+    let mut ticks = Vec::new();
+    for tick in 1..=48 {
+        player.handle_message(PlayerMessage::Broadcast(PlayerAction::Tick));
+        for event in midi.borrow_mut().drain(..) {
+            ticks.push((tick, event));
+        }
+    }
+    // A step is 24 ticks, the first one plays three times as fast
+    assert_eq!(
+        ticks,
+        vec![
+            (1, note_on(0, 60, 100)),
+            (5, note_off),
+            (9, note_on(0, 60, 100)),
+            (13, note_off),
+            (17, note_on(0, 60, 100)),
+            (21, note_off),
+            (25, note_on(0, 60, 100)),
+            (37, note_off),
+        ]
+    );
+}
+
+#[test]
+fn programmer_sets_ratchet_from_insert_mode() {
+    let sent: Shared<PlayerMessage> = Rc::default();
+    let mut programmer = Programmer::new(sent.clone(), Vec::new());
+    programmer.handle_operation(Operation::Begin(60));
+    for _ in 0..3 {
+        programmer.handle_operation(Operation::ModifierSwitch);
+    }
+    programmer.handle_operation(Operation::Modify(62, true));
+
+    assert_eq!(
+        sent.borrow().last(),
+        Some(&PlayerMessage::Action(0, PlayerAction::SetRatchet(0, 3)))
+    );
+}
+
+#[test]
+fn slot_switches_at_the_end_of_the_loop() {
+    let midi: Shared<LiveEvent<'static>> = Rc::default();
+    let mut player = Player::new(0, 4, midi.clone(), Vec::new());
+    player.handle_message(PlayerMessage::Action(0, PlayerAction::SetLength(2)));
+    player.insert(note_on(0, 60, 100), 0, 0.0);
+    player.insert(note_on(0, 61, 100), 1, 0.0);
+    // Stopped players switch right away
+    player.handle_message(PlayerMessage::Action(0, PlayerAction::SelectSlot(1)));
+    assert_eq!(player.slot(), 1);
+    player.insert(note_on(0, 72, 100), 0, 0.0);
+    player.insert(note_on(0, 73, 100), 1, 0.0);
+    player.handle_message(PlayerMessage::Action(0, PlayerAction::SelectSlot(0)));
+    midi.borrow_mut().clear();
+
+    player.handle_message(PlayerMessage::Broadcast(PlayerAction::Play));
+    let mut notes = Vec::new();
+    for tick in 1..=96 {
+        if tick == 30 {
+            player.handle_message(PlayerMessage::Action(0, PlayerAction::SelectSlot(1)));
+            assert_eq!(player.next_slot(), Some(1));
+        }
+        player.handle_message(PlayerMessage::Broadcast(PlayerAction::Tick));
+        for event in midi.borrow_mut().drain(..) {
+            match event {
+                LiveEvent::Midi {
+                    message: MidiMessage::NoteOn { key, .. },
+                    ..
+                } => notes.push((tick, key.as_int())),
+                _ => notes.push((tick, 0)),
+            }
+        }
+    }
+    // The loop that was going on when asked plays out, then all notes off
+    assert_eq!(notes, vec![(1, 60), (25, 61), (48, 0), (49, 72), (73, 73)]);
+    assert_eq!(player.slot(), 1);
+    assert_eq!(player.next_slot(), None);
+}
+
+#[test]
+fn only_the_selected_slot_sets_length_and_divisor() {
+    let midi: Shared<LiveEvent<'static>> = Rc::default();
+    let mut player = Player::new(0, 4, midi.clone(), Vec::new());
+    player.handle_message(PlayerMessage::Action(0, PlayerAction::SetLength(2)));
+    player.insert(note_on(0, 60, 100), 0, 0.0);
+    let mut buf = [0; PATTERN_SIZE];
+    let used = player.export_slot(0, &mut buf);
+    buf[0] = 8;
+    buf[1] = 16;
+
+    player.import_slot(3, &buf[..used]).unwrap();
+    assert_eq!((player.length(), player.divisor()), (2, 4));
+    assert!(midi.borrow().is_empty());
+    assert_eq!(player.events_in(3).count(), 1);
+
+    player.import_slot(0, &buf[..used]).unwrap();
+    assert_eq!((player.length(), player.divisor()), (8, 16));
+    assert_eq!(midi.borrow().len(), 1);
+}
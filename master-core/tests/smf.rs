@@ -0,0 +1,246 @@
+use master_core::outs::OutputRequest;
+use master_core::player::{Player, PlayerAction, PlayerMessage, STEP_CAP};
+use master_core::smf::{self, TICKS_PER_BEAT};
+use midly::live::LiveEvent;
+use midly::num::{u15, u28};
+use midly::{Format, Header, MetaMessage, MidiMessage, Smf, Timing, TrackEvent, TrackEventKind};
+
+type TestPlayer = Player<Vec<LiveEvent<'static>>, Vec<OutputRequest>>;
+
+fn note(key: u8, on: bool) -> MidiMessage {
+    if on {
+        MidiMessage::NoteOn {
+            key: key.into(),
+            vel: 100.into(),
+        }
+    } else {
+        MidiMessage::NoteOff {
+            key: key.into(),
+            vel: 0.into(),
+        }
+    }
+}
+
+fn insert(p: &mut TestPlayer, message: MidiMessage, step: u32, offset: f32) {
+    let channel = p.channel();
+    p.handle_message(PlayerMessage::Action(
+        channel,
+        PlayerAction::Insert(
+            LiveEvent::Midi {
+                channel: channel.into(),
+                message,
+            },
+            step,
+            offset,
+        ),
+    ));
+}
+
+fn sorted_events(p: &TestPlayer) -> Vec<(u32, u32, LiveEvent<'static>)> {
+    let mut events: Vec<_> = p.events().map(|(ts, e)| (ts.step, ts.sub, e)).collect();
+    events.sort_by_key(|(step, sub, e)| (*step, *sub, format!("{:?}", e)));
+    events
+}
+
+fn event(delta: u32, kind: TrackEventKind<'static>) -> TrackEvent<'static> {
+    TrackEvent {
+        delta: u28::new(delta),
+        kind,
+    }
+}
+
+#[test]
+fn steps_land_on_ticks() {
+    let mut p: TestPlayer = Player::new(1, 16, Vec::new(), Vec::new());
+    insert(&mut p, note(60, true), 1, 0.5);
+    let track = smf::to_track(&p, 0);
+    // A sixteenth note is a quarter of a beat, the note is half a step into the second one
+    assert_eq!(
+        track[0].delta.as_int(),
+        TICKS_PER_BEAT / 4 + TICKS_PER_BEAT / 8
+    );
+    let end: u32 = track.iter().map(|e| e.delta.as_int()).sum();
+    assert_eq!(end, TICKS_PER_BEAT * 4);
+    assert_eq!(
+        track.last().unwrap().kind,
+        TrackEventKind::Meta(MetaMessage::EndOfTrack)
+    );
+}
+
+#[test]
+fn round_trip_through_file() {
+    let mut p: TestPlayer = Player::new(2, 8, Vec::new(), Vec::new());
+    p.handle_message(PlayerMessage::Action(2, PlayerAction::SetLength(12)));
+    insert(&mut p, note(40, true), 0, 0.0);
+    insert(&mut p, note(40, false), 0, 1.0);
+    insert(&mut p, note(47, true), 3, 0.25);
+    insert(&mut p, note(47, false), 5, 0.75);
+    insert(&mut p, note(52, true), 11, 0.0);
+    p.handle_message(PlayerMessage::Action(2, PlayerAction::SelectSlot(3)));
+    insert(&mut p, note(64, true), 6, 0.5);
+    p.handle_message(PlayerMessage::Action(2, PlayerAction::SelectSlot(0)));
+
+    let mut bytes = Vec::new();
+    smf::export(std::slice::from_ref(&p))
+        .write_std(&mut bytes)
+        .unwrap();
+    let file = Smf::parse(&bytes).unwrap();
+    // Empty slots other than the first get no track
+    assert_eq!(file.tracks.len(), 2);
+
+    let mut q: TestPlayer = Player::new(2, 16, Vec::new(), Vec::new());
+    assert_eq!(smf::import(&file, &mut q, 8), Some(0));
+    assert_eq!(q.length(), 12);
+    assert_eq!(q.divisor(), 8);
+    assert_eq!(sorted_events(&p), sorted_events(&q));
+    assert_eq!(
+        q.events_in(3).collect::<Vec<_>>(),
+        p.events_in(3).collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn import_from_other_resolution() {
+    // 96 ticks per beat, one bar of sixteenths on channel 3 and something else on channel 0
+    let mut file = Smf::new(Header::new(
+        Format::Parallel,
+        Timing::Metrical(u15::new(96)),
+    ));
+    file.tracks.push(vec![
+        event(
+            0,
+            TrackEventKind::Midi {
+                channel: 0.into(),
+                message: note(30, true),
+            },
+        ),
+        event(0, TrackEventKind::Meta(MetaMessage::EndOfTrack)),
+    ]);
+    file.tracks.push(vec![
+        event(
+            24,
+            TrackEventKind::Midi {
+                channel: 3.into(),
+                message: note(60, true),
+            },
+        ),
+        event(
+            24,
+            TrackEventKind::Midi {
+                channel: 3.into(),
+                message: note(60, false),
+            },
+        ),
+        event(
+            12,
+            TrackEventKind::Midi {
+                channel: 3.into(),
+                message: note(62, true),
+            },
+        ),
+        event(324, TrackEventKind::Meta(MetaMessage::EndOfTrack)),
+    ]);
+
+    let mut p: TestPlayer = Player::new(3, 8, Vec::new(), Vec::new());
+    assert_eq!(smf::import(&file, &mut p, 16), Some(0));
+    assert_eq!(p.length(), 16);
+    assert_eq!(p.divisor(), 16);
+    let events: Vec<_> = sorted_events(&p)
+        .into_iter()
+        .map(|(s, sub, _)| (s, sub))
+        .collect();
+    // The note off right on the step boundary stays in the step of its note on
+    assert_eq!(events, vec![(1, 0), (1, 128), (2, 64)]);
+
+    let mut empty: TestPlayer = Player::new(4, 8, Vec::new(), Vec::new());
+    assert_eq!(smf::import(&file, &mut empty, 16), None);
+    assert_eq!(empty.events().count(), 0);
+}
+
+#[test]
+fn import_drops_what_does_not_fit_a_step() {
+    let mut track: Vec<TrackEvent<'static>> = (0..12)
+        .map(|i| {
+            event(
+                0,
+                TrackEventKind::Midi {
+                    channel: 0.into(),
+                    message: note(40 + i, true),
+                },
+            )
+        })
+        .collect();
+    track.push(event(
+        TICKS_PER_BEAT,
+        TrackEventKind::Midi {
+            channel: 0.into(),
+            message: note(60, true),
+        },
+    ));
+    track.push(event(
+        TICKS_PER_BEAT,
+        TrackEventKind::Meta(MetaMessage::EndOfTrack),
+    ));
+    let mut file = Smf::new(Header::new(
+        Format::SingleTrack,
+        Timing::Metrical(u15::new(TICKS_PER_BEAT as u16)),
+    ));
+    file.tracks.push(track);
+
+    let mut p: TestPlayer = Player::new(0, 8, Vec::new(), Vec::new());
+    assert_eq!(smf::import(&file, &mut p, 16), Some(12 - STEP_CAP));
+    assert_eq!(p.events().filter(|(ts, _)| ts.step == 0).count(), STEP_CAP);
+    assert_eq!(p.events().filter(|(ts, _)| ts.step == 4).count(), 1);
+}
+
+#[test]
+fn import_keeps_notes_and_their_note_offs_together() {
+    let channel = |message| TrackEventKind::Midi {
+        channel: 0.into(),
+        message,
+    };
+    let mut track: Vec<TrackEvent<'static>> = (0..8)
+        .map(|i| event(0, channel(note(40 + i, true))))
+        .collect();
+    // On the step boundary, so they all end up in the first step as well
+    track.push(event(TICKS_PER_BEAT / 4, channel(note(40, false))));
+    for i in 1..8 {
+        track.push(event(0, channel(note(40 + i, false))));
+    }
+    track.push(event(0, TrackEventKind::Meta(MetaMessage::EndOfTrack)));
+    let mut file = Smf::new(Header::new(
+        Format::SingleTrack,
+        Timing::Metrical(u15::new(TICKS_PER_BEAT as u16)),
+    ));
+    file.tracks.push(track);
+
+    let mut p: TestPlayer = Player::new(0, 8, Vec::new(), Vec::new());
+    assert_eq!(smf::import(&file, &mut p, 16), Some(STEP_CAP));
+    let keys = |on: bool| -> Vec<u8> {
+        p.events()
+            .filter_map(|(_, e)| match e {
+                LiveEvent::Midi {
+                    message: MidiMessage::NoteOn { key, .. },
+                    ..
+                } if on => Some(key.as_int()),
+                LiveEvent::Midi {
+                    message: MidiMessage::NoteOff { key, .. },
+                    ..
+                } if !on => Some(key.as_int()),
+                _ => None,
+            })
+            .collect()
+    };
+    assert_eq!(keys(true), vec![40, 41, 42, 43]);
+    assert_eq!(keys(false), vec![40, 41, 42, 43]);
+}
+
+#[test]
+fn rejects_timecode_files() {
+    let file = Smf::new(Header::new(
+        Format::Parallel,
+        Timing::Timecode(midly::Fps::Fps25, 40),
+    ));
+    let mut p: TestPlayer = Player::new(0, 8, Vec::new(), Vec::new());
+    assert_eq!(smf::import(&file, &mut p, 16), None);
+}
@@ -0,0 +1,133 @@
+use master_core::player::{PlayerAction, PlayerMessage};
+use master_core::song::{Arranger, Entry, PLAYERS};
+
+// Every player looping over a bar
+const BARS: [u32; PLAYERS] = [96; PLAYERS];
+
+fn slots(msgs: &[PlayerMessage]) -> Vec<u8> {
+    msgs.iter()
+        .filter_map(|msg| match msg {
+            PlayerMessage::Action(_, PlayerAction::SelectSlot(slot)) => Some(*slot),
+            _ => None,
+        })
+        .collect()
+}
+
+// Two repeats of slot 0 everywhere, then one of slot 1 everywhere
+fn arranger() -> Arranger {
+    let mut arranger = Arranger::new();
+    arranger.append(0, Entry::new([0; 5], 2).unwrap()).unwrap();
+    arranger.append(0, Entry::new([1; 5], 1).unwrap()).unwrap();
+    arranger.set_enabled(true);
+    arranger
+}
+
+fn broadcast(arranger: &mut Arranger, action: PlayerAction) -> Vec<PlayerMessage> {
+    let mut out = Vec::new();
+    arranger.handle_message(PlayerMessage::Broadcast(action), BARS, &mut out);
+    out
+}
+
+#[test]
+fn walks_through_the_chain() {
+    let mut arranger = arranger();
+    assert_eq!(slots(&broadcast(&mut arranger, PlayerAction::Play)), [0; 5]);
+
+    // Every tick of three bars and what was asked for on it
+    let mut asked = Vec::new();
+    for tick in 0..3 * 96 {
+        let out = broadcast(&mut arranger, PlayerAction::Tick);
+        if !out.is_empty() {
+            asked.push((tick, slots(&out)));
+        }
+    }
+    // The next entry is asked for when its last loop starts
+    assert_eq!(asked, vec![(96, vec![1; 5]), (192, vec![0; 5])]);
+    assert_eq!(arranger.position().entry, 0);
+    assert_eq!(arranger.position().repeat, 0);
+}
+
+#[test]
+fn repeats_count_the_longest_loop() {
+    let mut arranger = arranger();
+    let loops = [192, 96, 96, 96, 48];
+    let mut out = Vec::new();
+    arranger.handle_message(
+        PlayerMessage::Broadcast(PlayerAction::Play),
+        loops,
+        &mut out,
+    );
+
+    // Which player was asked for which slot on what tick
+    let mut asked = Vec::new();
+    for tick in 0..3 * 192 {
+        let mut out = Vec::new();
+        arranger.handle_message(
+            PlayerMessage::Broadcast(PlayerAction::Tick),
+            loops,
+            &mut out,
+        );
+        for msg in out {
+            if let PlayerMessage::Action(ch, PlayerAction::SelectSlot(slot)) = msg {
+                asked.push((tick, ch, slot));
+            }
+        }
+        if tick == 200 {
+            assert_eq!(arranger.position().repeat, 1);
+        }
+    }
+    // Each player is asked when its own last loop of the entry starts
+    assert_eq!(
+        asked,
+        vec![
+            (192, 0, 1),
+            (288, 1, 1),
+            (288, 2, 1),
+            (288, 3, 1),
+            (336, 4, 1),
+            (384, 0, 0),
+            (480, 1, 0),
+            (480, 2, 0),
+            (480, 3, 0),
+            (528, 4, 0),
+        ]
+    );
+    assert_eq!(arranger.position().entry, 0);
+}
+
+#[test]
+fn song_position_picks_entry_and_locates_players() {
+    let mut arranger = arranger();
+    // Bar 2 is the start of the second entry, plus a beat
+    let out = broadcast(&mut arranger, PlayerAction::SongPosition(2 * 16 + 4));
+    assert_eq!(slots(&out), [1; 5]);
+    assert_eq!(arranger.position().entry, 1);
+
+    let out = broadcast(&mut arranger, PlayerAction::Continue);
+    assert_eq!(
+        out.last(),
+        Some(&PlayerMessage::Broadcast(PlayerAction::Locate(24)))
+    );
+    // Past the end wraps around
+    broadcast(&mut arranger, PlayerAction::Stop);
+    broadcast(&mut arranger, PlayerAction::SongPosition(3 * 16));
+    assert_eq!(arranger.position().entry, 0);
+}
+
+#[test]
+fn song_select_and_being_off() {
+    let mut arranger = arranger();
+    arranger
+        .append(1, Entry::new([3, 2, 1, 0, 0], 4).unwrap())
+        .unwrap();
+    let out = broadcast(&mut arranger, PlayerAction::SongSelect(1));
+    assert_eq!(slots(&out), [3, 2, 1, 0, 0]);
+    assert_eq!(arranger.song(), 1);
+    assert!(broadcast(&mut arranger, PlayerAction::SongSelect(9)).is_empty());
+
+    arranger.set_enabled(false);
+    assert!(broadcast(&mut arranger, PlayerAction::Play).is_empty());
+    assert!(broadcast(&mut arranger, PlayerAction::Tick).is_empty());
+    assert_eq!(Entry::new([4, 0, 0, 0, 0], 1), None);
+    assert_eq!(Entry::new([0; 5], 0), None);
+}
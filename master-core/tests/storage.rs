@@ -0,0 +1,211 @@
+use std::rc::Rc;
+
+use master_core::outs::{Calibration, OutputRequest, CALIBRATION_SIZE};
+use master_core::player::{Player, PlayerAction, PlayerMessage, PATTERN_SLOTS};
+use master_core::storage::{
+    self, load_calibration, load_patterns, save_calibration, save_patterns, write_slot,
+    FileStorage, Kind, MemoryStorage, SlotError, CALIBRATION_SLOT, HEADER_SIZE,
+};
+use midly::live::LiveEvent;
+
+mod common;
+
+use common::{note_on, Shared};
+
+type TestPlayer = Player<Shared<LiveEvent<'static>>, Vec<OutputRequest>>;
+
+fn player(channel: u8) -> (TestPlayer, Shared<LiveEvent<'static>>) {
+    let midi: Shared<LiveEvent<'static>> = Rc::default();
+    (Player::new(channel, 8, midi.clone(), Vec::new()), midi)
+}
+
+fn programmed(channel: u8) -> TestPlayer {
+    let (mut p, _) = player(channel);
+    p.handle_message(PlayerMessage::Action(
+        channel,
+        PlayerAction::Insert(note_on(channel, 60, 100), 3, 0.25),
+    ));
+    p.handle_message(PlayerMessage::Action(channel, PlayerAction::SetLength(8)));
+    p.handle_message(PlayerMessage::Action(channel, PlayerAction::SetDivisor(16)));
+    p
+}
+
+fn export(p: &TestPlayer) -> Vec<u8> {
+    let mut buf = [0; master_core::player::PATTERN_SIZE];
+    let length = p.export_pattern(&mut buf);
+    buf[..length].to_vec()
+}
+
+#[test]
+fn pattern_survives_a_round_trip() {
+    let mut storage = MemoryStorage::new();
+    let original = programmed(2);
+    save_patterns(&mut storage, &original).unwrap();
+
+    let (mut restored, _) = player(2);
+    load_patterns(&mut storage, &mut restored).unwrap();
+
+    assert_eq!(export(&restored), export(&original));
+    assert_eq!(restored.divisor(), 16);
+}
+
+fn events_in(p: &TestPlayer, slot: usize) -> Vec<(u32, u32, LiveEvent<'static>)> {
+    p.events_in(slot)
+        .map(|(ts, event)| (ts.step, ts.sub, event))
+        .collect()
+}
+
+fn select(p: &mut TestPlayer, slot: u8) {
+    let channel = p.channel();
+    p.handle_message(PlayerMessage::Action(
+        channel,
+        PlayerAction::SelectSlot(slot),
+    ));
+}
+
+#[test]
+fn every_pattern_slot_survives_a_round_trip() {
+    let mut storage = MemoryStorage::new();
+    let mut original = programmed(1);
+    select(&mut original, 3);
+    original.handle_message(PlayerMessage::Action(
+        1,
+        PlayerAction::Insert(note_on(1, 72, 100), 5, 0.0),
+    ));
+    select(&mut original, 2);
+    save_patterns(&mut storage, &original).unwrap();
+
+    // Patterns go back to the slots they came from, not to the selected one
+    let (mut restored, _) = player(1);
+    select(&mut restored, 1);
+    load_patterns(&mut storage, &mut restored).unwrap();
+    for slot in 0..PATTERN_SLOTS {
+        assert_eq!(events_in(&restored, slot), events_in(&original, slot));
+    }
+    assert_eq!(events_in(&restored, 3).len(), 1);
+    assert_eq!(restored.slot(), 1);
+}
+
+#[test]
+fn calibration_survives_a_round_trip() {
+    let mut storage = MemoryStorage::new();
+    assert_eq!(load_calibration(&mut storage), Err(SlotError::Empty));
+
+    let mut calibrations = [Calibration::linear(); 4];
+    calibrations[1].trims = [0, 10, 20, -30, 40, 500];
+    save_calibration(&mut storage, &calibrations).unwrap();
+
+    assert_eq!(load_calibration(&mut storage), Ok(calibrations));
+
+    // Trims out of range, the most negative one included, are not loaded
+    let mut body = [0; CALIBRATION_SIZE * 4];
+    body[..2].copy_from_slice(&i16::MIN.to_le_bytes());
+    write_slot(&mut storage, CALIBRATION_SLOT, Kind::Calibration, 1, &body).unwrap();
+    assert_eq!(load_calibration(&mut storage), Err(SlotError::Corrupt));
+    // Trims are blended between points
+    assert!((calibrations[1].apply(2.5) - 2.495).abs() < 1e-4);
+    assert!((calibrations[1].apply(5.0) - 5.5).abs() < 1e-4);
+}
+
+#[test]
+fn erased_slot_is_empty() {
+    let mut storage = MemoryStorage::new();
+    let (mut p, _) = player(0);
+    assert_eq!(load_patterns(&mut storage, &mut p), Err(SlotError::Empty));
+}
+
+#[test]
+fn corrupted_slot_is_rejected_and_pattern_kept() {
+    let mut storage = MemoryStorage::new();
+    save_patterns(&mut storage, &programmed(1)).unwrap();
+    storage.slot_mut(1)[HEADER_SIZE + 4] ^= 0x10;
+
+    let (mut p, _) = player(1);
+    let before = export(&p);
+    assert_eq!(load_patterns(&mut storage, &mut p), Err(SlotError::Corrupt));
+    assert_eq!(export(&p), before);
+}
+
+#[test]
+fn divisors_that_do_not_fit_the_clock_are_rejected() {
+    let (mut p, _) = player(0);
+    let before = export(&p);
+    assert_eq!(p.import_pattern(&[16, 200, 0, 0]), None);
+    assert_eq!(p.import_pattern(&[16, 7, 0, 0]), None);
+    assert_eq!(export(&p), before);
+    p.handle_message(PlayerMessage::Broadcast(PlayerAction::Play));
+    p.handle_message(PlayerMessage::Broadcast(PlayerAction::Tick));
+
+    assert_eq!(p.import_pattern(&[16, 12, 0, 0]), Some(()));
+    assert_eq!(p.divisor(), 12);
+}
+
+#[test]
+fn newer_version_is_not_loaded() {
+    let mut storage = MemoryStorage::new();
+    save_patterns(&mut storage, &programmed(0)).unwrap();
+    storage.slot_mut(0)[5] = 99;
+
+    let (mut p, _) = player(0);
+    assert_eq!(
+        load_patterns(&mut storage, &mut p),
+        Err(SlotError::UnknownVersion(99))
+    );
+}
+
+#[test]
+fn version_2_patterns_go_to_the_first_slot() {
+    let mut storage = MemoryStorage::new();
+    write_slot(&mut storage, 2, Kind::Pattern, 2, &export(&programmed(2))).unwrap();
+
+    let (mut p, _) = player(2);
+    select(&mut p, 3);
+    load_patterns(&mut storage, &mut p).unwrap();
+    assert!(p.events().next().is_none());
+    // Length and divisor only come along into the selected slot
+    assert_eq!((p.length(), p.divisor()), (16, 8));
+    select(&mut p, 0);
+    assert_eq!(export(&p)[2..], export(&programmed(2))[2..]);
+}
+
+#[test]
+fn version_1_patterns_always_play() {
+    let mut storage = MemoryStorage::new();
+    // Length 8, divisor 16, one note on at step 3 without a chance byte
+    let body = [8, 16, 1, 0, 3, 32, 0x92, 60, 100];
+    write_slot(&mut storage, 2, Kind::Pattern, 1, &body).unwrap();
+
+    let (mut p, _) = player(2);
+    load_patterns(&mut storage, &mut p).unwrap();
+    assert_eq!(export(&p), export(&programmed(2)));
+}
+
+#[test]
+fn save_and_load_actions_only_reach_addressed_player() {
+    let mut storage = MemoryStorage::new();
+    let mut source = programmed(3);
+    let msg = PlayerMessage::Action(3, PlayerAction::Save);
+    assert_eq!(
+        storage::handle_message(&mut storage, &mut source, msg),
+        Some(Ok(()))
+    );
+
+    let (mut other, _) = player(4);
+    let msg = PlayerMessage::Action(3, PlayerAction::Load);
+    assert_eq!(storage::handle_message(&mut storage, &mut other, msg), None);
+}
+
+#[test]
+fn file_storage_keeps_patterns_between_opens() {
+    let path = std::env::temp_dir().join(format!("master-core-{}.bin", std::process::id()));
+    {
+        let mut storage = FileStorage::open(&path).unwrap();
+        save_patterns(&mut storage, &programmed(4)).unwrap();
+    }
+    let mut storage = FileStorage::open(&path).unwrap();
+    let (mut p, _) = player(4);
+    load_patterns(&mut storage, &mut p).unwrap();
+    std::fs::remove_file(&path).ok();
+
+    assert_eq!(export(&p), export(&programmed(4)));
+}
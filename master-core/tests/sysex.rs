@@ -0,0 +1,186 @@
+use master_core::outs::OutputRequest;
+use master_core::player::{
+    Player, PlayerAction, PlayerMessage, TimeStamp, PATTERN_SIZE, PATTERN_SLOTS,
+};
+use master_core::storage::crc32;
+use master_core::sysex::{self, Reading, SysExReader, MAX_DUMPS_SIZE, MAX_SYSEX_SIZE};
+use midly::live::LiveEvent;
+use midly::MidiMessage;
+
+type TestPlayer = Player<Vec<LiveEvent<'static>>, Vec<OutputRequest>>;
+
+fn programmed(channel: u8) -> TestPlayer {
+    let mut p = Player::new(channel, 8, Vec::new(), Vec::new());
+    for step in 0..16 {
+        p.handle_message(PlayerMessage::Action(
+            channel,
+            PlayerAction::Insert(
+                LiveEvent::Midi {
+                    channel: channel.into(),
+                    message: MidiMessage::NoteOn {
+                        key: (40 + step as u8).into(),
+                        vel: 127.into(),
+                    },
+                },
+                step,
+                1.0,
+            ),
+        ));
+    }
+    p.handle_message(PlayerMessage::Action(channel, PlayerAction::SetDivisor(4)));
+    p
+}
+
+fn reply_to(channel: u8, restored: bool) -> [u8; 5] {
+    [
+        0xf0,
+        0x7d,
+        if restored { 0x03 } else { 0x04 },
+        channel,
+        0xf7,
+    ]
+}
+
+fn export(p: &TestPlayer) -> Vec<u8> {
+    let mut buf = [0; PATTERN_SIZE];
+    let length = p.export_pattern(&mut buf);
+    buf[..length].to_vec()
+}
+
+#[test]
+fn packing_keeps_the_top_bits() {
+    let data: Vec<u8> = (0..=255).collect();
+    let mut packed = [0; 300];
+    let length = sysex::pack(&data, &mut packed);
+    assert!(packed[..length].iter().all(|b| *b < 0x80));
+
+    let mut unpacked = [0; 256];
+    assert_eq!(sysex::unpack(&packed[..length], &mut unpacked), Some(256));
+    assert_eq!(unpacked.to_vec(), data);
+}
+
+fn events_in(p: &TestPlayer, slot: usize) -> Vec<(TimeStamp, LiveEvent<'static>)> {
+    p.events_in(slot).collect()
+}
+
+// The messages in a stream of them
+fn messages(stream: &[u8]) -> Vec<Vec<u8>> {
+    let mut reader = SysExReader::new();
+    let mut found = Vec::new();
+    for byte in stream {
+        if reader.push(*byte) == Reading::Done {
+            found.push(reader.message().to_vec());
+        }
+    }
+    found
+}
+
+#[test]
+fn dump_request_is_answered_and_restores() {
+    let mut second = programmed(2);
+    second.handle_message(PlayerMessage::Action(2, PlayerAction::SelectSlot(2)));
+    second.handle_message(PlayerMessage::Action(
+        2,
+        PlayerAction::Insert(
+            LiveEvent::Midi {
+                channel: 2.into(),
+                message: MidiMessage::NoteOn {
+                    key: 90.into(),
+                    vel: 64.into(),
+                },
+            },
+            7,
+            0.0,
+        ),
+    ));
+    let mut players = [programmed(0), second];
+    let mut request = [0; 8];
+    let length = sysex::write_request(2, &mut request);
+
+    let mut reply = [0; MAX_DUMPS_SIZE];
+    let reply_length = sysex::handle(&mut players, &request[..length], &mut reply).unwrap();
+    let dumps = messages(&reply[..reply_length]);
+    assert_eq!(dumps.len(), PATTERN_SLOTS);
+    assert!(dumps
+        .iter()
+        .all(|d| d[1..d.len() - 1].iter().all(|b| *b < 0x80)));
+
+    // Every slot goes back where it came from
+    let mut fresh = [Player::new(2, 8, Vec::new(), Vec::new())];
+    for dump in dumps {
+        let mut ack = [0; 8];
+        let ack_length = sysex::handle(&mut fresh, &dump, &mut ack).unwrap();
+        assert_eq!(&ack[..ack_length], &reply_to(2, true));
+    }
+    for slot in 0..PATTERN_SLOTS {
+        assert_eq!(events_in(&fresh[0], slot), events_in(&players[1], slot));
+    }
+    assert_eq!(events_in(&fresh[0], 2).len(), 1);
+    assert_eq!(fresh[0].slot(), 0);
+    assert_eq!(fresh[0].divisor(), 4);
+}
+
+#[test]
+fn dumps_from_before_slots_go_to_the_first_slot() {
+    let original = programmed(1);
+    let mut body = [0; PATTERN_SIZE + 4];
+    let length = original.export_pattern(&mut body);
+    let crc = crc32(&body[..length]);
+    body[length..length + 4].copy_from_slice(&crc.to_le_bytes());
+    let mut dump = vec![0xf0, 0x7d, 0x02, 1];
+    let mut packed = [0; MAX_SYSEX_SIZE];
+    let packed_length = sysex::pack(&body[..length + 4], &mut packed);
+    dump.extend_from_slice(&packed[..packed_length]);
+    dump.push(0xf7);
+
+    let mut players = [Player::new(1, 8, Vec::new(), Vec::new())];
+    players[0].handle_message(PlayerMessage::Action(1, PlayerAction::SelectSlot(3)));
+    let mut ack = [0; 8];
+    let ack_length = sysex::handle(&mut players, &dump, &mut ack).unwrap();
+    assert_eq!(&ack[..ack_length], &reply_to(1, true));
+    assert_eq!(events_in(&players[0], 0), events_in(&original, 0));
+    assert!(events_in(&players[0], 3).is_empty());
+}
+
+#[test]
+fn corrupted_dump_is_ignored() {
+    let mut reply = [0; MAX_SYSEX_SIZE];
+    let length = sysex::write_dump(&programmed(1), 0, &mut reply);
+    reply[10] ^= 0x01;
+
+    let mut players = [Player::new(1, 8, Vec::new(), Vec::new())];
+    let before = export(&players[0]);
+    let mut nak = [0; 8];
+    let nak_length = sysex::handle(&mut players, &reply[..length], &mut nak).unwrap();
+    assert_eq!(&nak[..nak_length], &reply_to(1, false));
+    assert_eq!(export(&players[0]), before);
+}
+
+#[test]
+fn dump_for_a_missing_player_is_refused() {
+    let mut reply = [0; MAX_SYSEX_SIZE];
+    let length = sysex::write_dump(&programmed(3), 0, &mut reply);
+
+    let mut players = [Player::new(1, 8, Vec::new(), Vec::new())];
+    let mut nak = [0; 8];
+    let nak_length = sysex::handle(&mut players, &reply[..length], &mut nak).unwrap();
+    assert_eq!(&nak[..nak_length], &reply_to(3, false));
+}
+
+#[test]
+fn reader_collects_message_across_chunks_and_passes_clock() {
+    let mut reader = SysExReader::new();
+    let mut msg = [0; 8];
+    let length = sysex::write_request(3, &mut msg);
+
+    let mut stream = vec![0x90, 60, 100];
+    stream.extend_from_slice(&msg[..2]);
+    stream.push(0xf8);
+    stream.extend_from_slice(&msg[2..length]);
+
+    let results: Vec<Reading> = stream.iter().map(|b| reader.push(*b)).collect();
+    assert_eq!(&results[..3], &[Reading::NotMine; 3]);
+    assert_eq!(results[5], Reading::NotMine);
+    assert_eq!(*results.last().unwrap(), Reading::Done);
+    assert_eq!(reader.message(), &msg[..length]);
+}
@@ -0,0 +1,100 @@
+use master_core::outs::OutputRequest;
+use master_core::player::Player;
+use master_core::sink::{Sink, Tee};
+use master_core::sysex::{self, MAX_DUMPS_SIZE};
+use master_core::usb_midi::{self, cable, event_packet, packet_bytes, unpack_packets, Packer};
+use midly::live::{LiveEvent, SystemRealtime};
+use midly::MidiMessage;
+
+#[test]
+fn channel_and_realtime_events() {
+    let note = LiveEvent::Midi {
+        channel: 2.into(),
+        message: MidiMessage::NoteOn {
+            key: 60.into(),
+            vel: 100.into(),
+        },
+    };
+    assert_eq!(event_packet(0, note), Some([0x09, 0x92, 60, 100]));
+    let program = LiveEvent::Midi {
+        channel: 0.into(),
+        message: MidiMessage::ProgramChange { program: 5.into() },
+    };
+    assert_eq!(event_packet(1, program), Some([0x1c, 0xc0, 5, 0]));
+    let clock = LiveEvent::Realtime(SystemRealtime::TimingClock);
+    assert_eq!(event_packet(0, clock), Some([0x0f, 0xf8, 0, 0]));
+
+    let packet = event_packet(3, note).unwrap();
+    assert_eq!(cable(&packet), 3);
+    assert_eq!(packet_bytes(&packet), &[0x92, 60, 100]);
+    assert_eq!(packet_bytes(&[0x1c, 0xc0, 5, 0]), &[0xc0, 5]);
+}
+
+#[test]
+fn sysex_is_split_and_terminated() {
+    let short: Vec<_> = Packer::new(0)
+        .packets(&[0xf0, 0x7d, 0x01, 0x02, 0xf7])
+        .collect();
+    assert_eq!(short, vec![[0x04, 0xf0, 0x7d, 0x01], [0x06, 0x02, 0xf7, 0]]);
+
+    let exact: Vec<_> = Packer::new(0)
+        .packets(&[0xf0, 0x7d, 0x01, 0x02, 0x03, 0xf7])
+        .collect();
+    assert_eq!(
+        exact,
+        vec![[0x04, 0xf0, 0x7d, 0x01], [0x07, 0x02, 0x03, 0xf7]]
+    );
+
+    let with_clock: Vec<_> = Packer::new(0)
+        .packets(&[0xf0, 0x7d, 0xf8, 0x01, 0xf7, 0x90, 1, 2])
+        .collect();
+    assert_eq!(
+        with_clock,
+        vec![
+            [0x0f, 0xf8, 0, 0],
+            [0x04, 0xf0, 0x7d, 0x01],
+            [0x05, 0xf7, 0, 0],
+            [0x09, 0x90, 1, 2]
+        ]
+    );
+}
+
+#[test]
+fn sysex_goes_on_across_calls() {
+    let mut packer = Packer::new(1);
+    assert_eq!(packer.packets(&[0xf0, 0x7d]).count(), 0);
+    let rest: Vec<_> = packer.packets(&[0x01, 0xf7]).collect();
+    assert_eq!(rest, vec![[0x14, 0xf0, 0x7d, 0x01], [0x15, 0xf7, 0, 0]]);
+
+    // A new message throws away what was held
+    assert_eq!(packer.packets(&[0xf0, 0x7d]).count(), 0);
+    let cut: Vec<_> = packer.packets(&[0x90, 1, 2]).collect();
+    assert_eq!(cut, vec![[0x19, 0x90, 1, 2]]);
+}
+
+#[test]
+fn dump_survives_the_trip() {
+    let player: Player<Vec<LiveEvent<'static>>, Vec<OutputRequest>> =
+        Player::new(2, 8, Vec::new(), Vec::new());
+    let mut dump = [0; MAX_DUMPS_SIZE];
+    let length = sysex::write_dumps(&player, &mut dump);
+
+    let mut wire = Vec::new();
+    for packet in Packer::new(0).packets(&dump[..length]) {
+        wire.extend_from_slice(&packet);
+    }
+    assert_eq!(wire.len() % usb_midi::PACKET_SIZE, 0);
+    let mut back = vec![0; wire.len()];
+    let back_length = unpack_packets(&wire, &mut back);
+    assert_eq!(&back[..back_length], &dump[..length]);
+}
+
+#[test]
+fn tee_copies_to_both() {
+    let mut first: heapless::Deque<u8, 1> = heapless::Deque::new();
+    let mut second: Vec<u8> = Vec::new();
+    let mut tee = Tee(&mut first, &mut second);
+    assert_eq!(tee.try_send(1), Ok(()));
+    assert_eq!(tee.try_send(2), Err(2));
+    assert_eq!(second, vec![1, 2]);
+}
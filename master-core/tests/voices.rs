@@ -0,0 +1,230 @@
+use master_core::midi_mapper::{Allocation, Assign, Config, MidiMapper, Steal};
+use master_core::outs::{Cv, Gate, OutputRequest};
+use master_core::utils::random::Random;
+
+mod common;
+
+use common::{mapper_with, note_off, note_on, Shared};
+
+const GATES: [Gate; 4] = [Gate::GateA, Gate::GateB, Gate::GateC, Gate::GateD];
+
+fn gate_index(gate: Gate) -> usize {
+    GATES.iter().position(|g| *g == gate).unwrap()
+}
+
+// The port a note went to, from the SetNote and GateOn it caused
+fn struck(requests: &[OutputRequest]) -> Option<usize> {
+    match requests {
+        [OutputRequest::SetNote(cv, _), OutputRequest::GateOn(gate)] => {
+            assert_eq!(cv.index(), gate_index(*gate));
+            Some(cv.index())
+        }
+        [] => None,
+        _ => panic!("unexpected requests {:?}", requests),
+    }
+}
+
+fn allocate(
+    config: Config,
+    allocation: Allocation,
+    notes: &[(u8, u8)],
+) -> (
+    MidiMapper<Shared<OutputRequest>>,
+    Shared<OutputRequest>,
+    Vec<Option<usize>>,
+) {
+    let (mut mapper, outs) = mapper_with(config);
+    mapper.set_allocation(0, allocation).unwrap();
+    let mut ports = Vec::new();
+    for (key, vel) in notes {
+        if *vel == 0 {
+            mapper.handle_message(note_off(0, *key));
+        } else {
+            mapper.handle_message(note_on(0, *key, *vel));
+        }
+        let requests: Vec<OutputRequest> = outs.borrow_mut().drain(..).collect();
+        if *vel > 0 {
+            ports.push(struck(&requests));
+        }
+    }
+    (mapper, outs, ports)
+}
+
+#[test]
+fn free_ports_go_round_robin_or_to_the_same_key() {
+    let round_robin = Allocation::new();
+    let played = [(60, 100), (60, 0), (62, 100), (62, 0), (60, 100), (60, 0)];
+    let (_, _, ports) = allocate(Config::four_poly(), round_robin, &played);
+    assert_eq!(ports, vec![Some(0), Some(1), Some(2)]);
+
+    let reuse = Allocation {
+        assign: Assign::ReuseKey,
+        ..round_robin
+    };
+    let (_, _, ports) = allocate(Config::four_poly(), reuse, &played);
+    assert_eq!(ports, vec![Some(0), Some(1), Some(0)]);
+
+    // A key that is still down is not free to reuse
+    let (_, _, ports) = allocate(Config::two_poly(), reuse, &[(60, 100), (60, 100)]);
+    assert_eq!(ports, vec![Some(0), Some(1)]);
+}
+
+#[test]
+fn busy_ports_are_stolen_from_the_oldest_or_quietest_note() {
+    let held = [(60, 90), (62, 30), (64, 60), (65, 100), (67, 100)];
+    let (_, _, ports) = allocate(Config::four_poly(), Allocation::new(), &held);
+    assert_eq!(ports[4], Some(0));
+
+    let quietest = Allocation {
+        assign: Assign::RoundRobin,
+        steal: Steal::Quietest,
+    };
+    let (mut mapper, outs, ports) = allocate(Config::four_poly(), quietest, &held);
+    assert_eq!(ports[4], Some(1));
+
+    // The stolen key is gone, lifting it leaves the new note alone
+    mapper.handle_message(note_off(0, 62));
+    assert!(outs.borrow().is_empty());
+}
+
+#[test]
+fn mono_ports_hold_more_keys_than_the_set_tracks() {
+    let (mut mapper, outs) = mapper_with(Config::one_mono());
+    for key in 40..=56 {
+        mapper.handle_message(note_on(0, key, 100));
+    }
+    assert!(outs.borrow().contains(&OutputRequest::SetNote(Cv::A, 56)));
+    for key in 40..=56 {
+        mapper.handle_message(note_off(0, key));
+    }
+    let gates: Vec<OutputRequest> = outs
+        .borrow()
+        .iter()
+        .filter(|request| matches!(request, OutputRequest::GateOff(_)))
+        .copied()
+        .collect();
+    assert_eq!(gates, vec![OutputRequest::GateOff(Gate::GateA)]);
+}
+
+#[test]
+fn dropping_the_oldest_unison_note_closes_all_its_gates() {
+    let (mut mapper, outs) = mapper_with(Config::two_poly());
+    mapper.set_unison(0, true).unwrap();
+    mapper.set_unison(1, true).unwrap();
+    outs.borrow_mut().clear();
+    mapper.handle_message(note_on(0, 40, 100));
+    // Unison channels hold every key that is down until the set is full
+    for key in 50..66 {
+        mapper.handle_message(note_on(1, key, 100));
+    }
+    let gates: Vec<OutputRequest> = outs
+        .borrow()
+        .iter()
+        .filter(|request| matches!(request, OutputRequest::GateOff(_)))
+        .copied()
+        .collect();
+    assert_eq!(
+        gates,
+        vec![
+            OutputRequest::GateOff(Gate::GateA),
+            OutputRequest::GateOff(Gate::GateB)
+        ]
+    );
+}
+
+/*
+Random playing checked against find_port, remove and RECEIVE in voices.vdmsl:
+a note goes to a free port of its channel when there is one and takes the
+oldest (or quietest) voice otherwise, a lifted key is not left sounding
+anywhere, and channels without pitch ports change nothing.
+*/
+#[test]
+fn allocation_keeps_the_model_invariants() {
+    let strategies = [
+        Allocation::new(),
+        Allocation {
+            assign: Assign::ReuseKey,
+            steal: Steal::Oldest,
+        },
+        Allocation {
+            assign: Assign::RoundRobin,
+            steal: Steal::Quietest,
+        },
+        Allocation {
+            assign: Assign::ReuseKey,
+            steal: Steal::Quietest,
+        },
+    ];
+    let configs = [
+        (Config::four_poly(), vec![0, 1, 2, 3]),
+        (Config::two_poly(), vec![0, 1]),
+    ];
+    let mut random = Random::new(7);
+    for allocation in strategies {
+        for (config, channel_ports) in configs.iter() {
+            let (mut mapper, outs) = mapper_with(*config);
+            mapper.set_allocation(0, allocation).unwrap();
+            // What each port sounds: key, when it was struck and how hard
+            let mut voices: [Option<(u8, u32, u8)>; 4] = [None; 4];
+
+            for time in 0..2000 {
+                let key = 60 + (random.next_u32() % 8) as u8;
+                let vel = 1 + (random.next_u32() % 127) as u8;
+                match random.next_u32() % 5 {
+                    0 | 1 => {
+                        mapper.handle_message(note_on(0, key, vel));
+                        let requests: Vec<OutputRequest> = outs.borrow_mut().drain(..).collect();
+                        let port = struck(&requests).unwrap();
+                        assert!(channel_ports.contains(&port));
+                        let free: Vec<usize> = channel_ports
+                            .iter()
+                            .copied()
+                            .filter(|p| voices[*p].is_none())
+                            .collect();
+                        if !free.is_empty() {
+                            assert!(free.contains(&port));
+                        } else {
+                            let wanted = channel_ports
+                                .iter()
+                                .copied()
+                                .min_by_key(|p| {
+                                    let (_, ts, vel) = voices[*p].unwrap();
+                                    match allocation.steal {
+                                        Steal::Oldest => (0, ts),
+                                        Steal::Quietest => (vel, ts),
+                                    }
+                                })
+                                .unwrap();
+                            assert_eq!(port, wanted);
+                        }
+                        voices[port] = Some((key, time, vel));
+                    }
+                    2 | 3 => {
+                        mapper.handle_message(note_off(0, key));
+                        let mut closed: Vec<usize> = outs
+                            .borrow_mut()
+                            .drain(..)
+                            .map(|request| match request {
+                                OutputRequest::GateOff(gate) => gate_index(gate),
+                                _ => panic!("unexpected request {:?}", request),
+                            })
+                            .collect();
+                        closed.sort();
+                        let sounding: Vec<usize> = (0..4)
+                            .filter(|p| matches!(voices[*p], Some((k, _, _)) if k == key))
+                            .collect();
+                        assert_eq!(closed, sounding);
+                        for p in sounding {
+                            voices[p] = None;
+                        }
+                    }
+                    _ => {
+                        mapper.handle_message(note_on(7, key, vel));
+                        mapper.handle_message(note_off(7, key));
+                        assert!(outs.borrow().is_empty());
+                    }
+                }
+            }
+        }
+    }
+}
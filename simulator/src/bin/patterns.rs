@@ -0,0 +1,118 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::process::exit;
+
+use master_core::outs::OutputRequest;
+use master_core::player::{is_divisor, Player};
+use master_core::smf;
+use master_core::sysex::{self, Reading, SysExReader, MAX_DUMPS_SIZE};
+use midly::live::LiveEvent;
+use midly::Smf;
+
+const USAGE: &str = "usage: patterns to-midi <dump.syx> <patterns.mid>
+       patterns from-midi <patterns.mid> <dump.syx> [--divisor N]
+
+Converts between the SysEx pattern dumps of the module and Standard MIDI Files.
+
+  to-midi     writes a type 1 file with a track named \"slot N\" for every
+              pattern slot in the dump that has notes, and always slot 0
+  from-midi   makes a dump with every pattern slot of the channels 0 to 4 that
+              have notes in the file. Tracks named \"slot N\" go to that slot,
+              others to slot 0. Each step is a 1/N note (default 16)";
+
+const PLAYERS: u8 = 5;
+
+type HostPlayer = Player<Vec<LiveEvent<'static>>, Vec<OutputRequest>>;
+
+fn fail(msg: &str) -> ! {
+    eprintln!("{}\n\n{}", msg, USAGE);
+    exit(1)
+}
+
+fn players() -> Vec<HostPlayer> {
+    (0..PLAYERS)
+        .map(|channel| Player::new(channel, 16, Vec::new(), Vec::new()))
+        .collect()
+}
+
+fn to_midi(dump: &str, out: &str) -> io::Result<()> {
+    let bytes = std::fs::read(dump)?;
+    let mut players = players();
+    let mut restored = Vec::new();
+    let mut reader = SysExReader::new();
+    for byte in bytes {
+        if reader.push(byte) != Reading::Done {
+            continue;
+        }
+        if let Some(sysex::Request::Restore(channel, slot, data)) = sysex::parse(reader.message()) {
+            let player = players.iter_mut().find(|p| p.channel() == channel);
+            match player.and_then(|p| sysex::restore(p, slot, data)) {
+                Some(()) => {
+                    restored.retain(|c| *c != channel);
+                    restored.push(channel);
+                }
+                None => eprintln!("skipping bad dump for channel {} slot {}", channel, slot),
+            }
+        }
+    }
+    if restored.is_empty() {
+        fail("no patterns in the dump");
+    }
+    restored.sort();
+    players.retain(|p| restored.contains(&p.channel()));
+    eprintln!("writing patterns for channels {:?}", restored);
+    smf::export(&players).save(out)
+}
+
+fn from_midi(song: &str, out: &str, divisor: u8) -> io::Result<()> {
+    let bytes = std::fs::read(song)?;
+    let file = Smf::parse(&bytes).unwrap_or_else(|e| fail(&format!("bad midi file: {}", e)));
+    let mut out = BufWriter::new(File::create(out)?);
+    let mut dump = [0; MAX_DUMPS_SIZE];
+    let mut written = Vec::new();
+    for mut player in players() {
+        match smf::import(&file, &mut player, divisor) {
+            Some(0) => {}
+            Some(dropped) => eprintln!(
+                "dropped {} events on channel {} that did not fit",
+                dropped,
+                player.channel()
+            ),
+            None => continue,
+        }
+        let length = sysex::write_dumps(&player, &mut dump);
+        out.write_all(&dump[..length])?;
+        written.push(player.channel());
+    }
+    if written.is_empty() {
+        fail("nothing on channels 0 to 4");
+    }
+    eprintln!("wrote patterns for channels {:?}", written);
+    out.flush()
+}
+
+fn main() -> io::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args
+        .iter()
+        .map(|a| a.as_str())
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        ["to-midi", dump, out] => to_midi(dump, out),
+        ["from-midi", song, out] => from_midi(song, out, 16),
+        ["from-midi", song, out, "--divisor", n] => {
+            let divisor = n
+                .parse()
+                .ok()
+                .filter(|n| is_divisor(*n))
+                .unwrap_or_else(|| fail("--divisor needs a divisor of 96"));
+            from_midi(song, out, divisor)
+        }
+        ["-h"] | ["--help"] => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        _ => fail("bad arguments"),
+    }
+}
@@ -0,0 +1,143 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::process::exit;
+
+use master_core::midi_mapper::Config;
+use master_core::player::{is_divisor, PlayerAction, PlayerMessage, MAX_SWING, STRAIGHT};
+use midly::Smf;
+
+mod rig;
+mod song;
+mod timeline;
+
+use rig::Rig;
+
+const USAGE: &str = "usage: simulator <song.mid> [--config NAME] [--patterns FILE] [--divisor N]
+                 [--swing N] [--vcd] [--out FILE]
+
+Plays a Standard MIDI File through the mapper and the players and writes every
+output request as CSV (default) or VCD.
+
+  --config NAME     four_poly, four_indie, two_poly, two_mono, two_fancy_mono,
+                    one_duo or one_mono (default two_fancy_mono)
+  --patterns FILE   load player patterns from a MIDI file first, one pattern
+                    per channel 0 to 4
+  --divisor N       steps per whole note for loaded patterns, a divisor of 96
+                    (default 16)
+  --swing N         percent of every pair of steps given to the first one,
+                    50 to 75 (default 50)
+  --vcd             write a VCD instead of CSV
+  --out FILE        write to FILE instead of stdout";
+
+struct Args {
+    song: String,
+    config: Config,
+    patterns: Option<String>,
+    divisor: u8,
+    swing: u8,
+    vcd: bool,
+    out: Option<String>,
+}
+
+fn fail(msg: &str) -> ! {
+    eprintln!("{}\n\n{}", msg, USAGE);
+    exit(1)
+}
+
+fn parse_args() -> Args {
+    let mut song = None;
+    let mut config = Config::two_fancy_mono();
+    let mut patterns = None;
+    let mut divisor = 16;
+    let mut swing = STRAIGHT;
+    let mut vcd = false;
+    let mut out = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => {
+                let name = args.next().unwrap_or_else(|| fail("--config needs a name"));
+                config = Config::from_name(&name)
+                    .unwrap_or_else(|| fail(&format!("unknown config {}", name)));
+            }
+            "--patterns" => {
+                patterns = Some(
+                    args.next()
+                        .unwrap_or_else(|| fail("--patterns needs a file")),
+                )
+            }
+            "--divisor" => {
+                divisor = args
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .filter(|n| is_divisor(*n))
+                    .unwrap_or_else(|| fail("--divisor needs a divisor of 96"))
+            }
+            "--swing" => {
+                swing = args
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .filter(|n| (STRAIGHT..=MAX_SWING).contains(n))
+                    .unwrap_or_else(|| fail("--swing needs a number from 50 to 75"))
+            }
+            "--vcd" => vcd = true,
+            "--out" => out = Some(args.next().unwrap_or_else(|| fail("--out needs a file"))),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                exit(0)
+            }
+            _ if song.is_none() => song = Some(arg),
+            _ => fail(&format!("unexpected argument {}", arg)),
+        }
+    }
+    Args {
+        song: song.unwrap_or_else(|| fail("no song given")),
+        config,
+        patterns,
+        divisor,
+        swing,
+        vcd,
+        out,
+    }
+}
+
+fn main() -> io::Result<()> {
+    let args = parse_args();
+    let bytes = std::fs::read(&args.song)?;
+    let smf = Smf::parse(&bytes).unwrap_or_else(|e| fail(&format!("bad midi file: {}", e)));
+
+    let mut rig = Rig::new(args.config);
+    rig.send(PlayerMessage::Broadcast(PlayerAction::SetDefaultSwing(
+        args.swing,
+    )));
+    if let Some(path) = &args.patterns {
+        let bytes = std::fs::read(path)?;
+        let patterns =
+            Smf::parse(&bytes).unwrap_or_else(|e| fail(&format!("bad pattern file: {}", e)));
+        let loaded = rig.load_patterns(&patterns, args.divisor);
+        let channels: Vec<u8> = loaded.iter().map(|(channel, _)| *channel).collect();
+        eprintln!("loaded patterns for channels {:?}", channels);
+        for (channel, dropped) in loaded {
+            if dropped > 0 {
+                eprintln!(
+                    "dropped {} events on channel {} that did not fit",
+                    dropped, channel
+                );
+            }
+        }
+    }
+    for (time, event) in song::render(&smf) {
+        rig.receive(time, event);
+    }
+
+    let mut out: Box<dyn Write> = match args.out {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(BufWriter::new(io::stdout())),
+    };
+    if args.vcd {
+        rig.timeline.write_vcd(&mut out)?;
+    } else {
+        rig.timeline.write_csv(&mut out)?;
+    }
+    out.flush()
+}
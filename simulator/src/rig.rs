@@ -0,0 +1,98 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use master_core::midi_mapper::{Config, MidiMapper};
+use master_core::outs::OutputRequest;
+use master_core::player::{Player, PlayerAction, PlayerMessage};
+use master_core::smf;
+use midly::live::{LiveEvent, SystemRealtime};
+use midly::Smf;
+
+use crate::timeline::{Time, Timeline};
+
+type Shared<T> = Rc<RefCell<Vec<T>>>;
+type SimPlayer = Player<Shared<LiveEvent<'static>>, Shared<OutputRequest>>;
+
+// Same wiring as the tasks in the firmware, except that every incoming event
+// is run to completion before the next one instead of going through channels.
+pub struct Rig {
+    mapper: MidiMapper<Shared<OutputRequest>>,
+    players: [SimPlayer; 5],
+    player_midi: Shared<LiveEvent<'static>>,
+    outputs: Shared<OutputRequest>,
+    pub timeline: Timeline,
+}
+
+impl Rig {
+    pub fn new(config: Config) -> Self {
+        let player_midi: Shared<LiveEvent<'static>> = Rc::default();
+        let outputs: Shared<OutputRequest> = Rc::default();
+        let make_player = |channel| Player::new(channel, 8, player_midi.clone(), outputs.clone());
+        Rig {
+            mapper: MidiMapper::new(config, outputs.clone()),
+            players: [
+                make_player(0),
+                make_player(1),
+                make_player(2),
+                make_player(3),
+                make_player(4),
+            ],
+            player_midi,
+            outputs,
+            timeline: Timeline::new(),
+        }
+    }
+
+    // Returns the channels that got a pattern and how many events each dropped
+    pub fn load_patterns(&mut self, patterns: &Smf, divisor: u8) -> Vec<(u8, usize)> {
+        let mut loaded = Vec::new();
+        for player in self.players.iter_mut() {
+            if let Some(dropped) = smf::import(patterns, player, divisor) {
+                loaded.push((player.channel(), dropped));
+            }
+        }
+        // Importing flushes notes, which should not end up on the timeline
+        self.player_midi.borrow_mut().clear();
+        loaded
+    }
+
+    pub fn receive(&mut self, now: Time, event: LiveEvent<'static>) {
+        self.mapper.handle_message(event);
+        match event {
+            LiveEvent::Realtime(SystemRealtime::TimingClock) => {
+                self.send(PlayerMessage::Broadcast(PlayerAction::Tick))
+            }
+            LiveEvent::Realtime(SystemRealtime::Start) => {
+                self.send(PlayerMessage::Broadcast(PlayerAction::Play))
+            }
+            LiveEvent::Realtime(SystemRealtime::Stop) => {
+                self.send(PlayerMessage::Broadcast(PlayerAction::Stop))
+            }
+            _ => {}
+        }
+        self.settle(now);
+    }
+
+    pub fn send(&mut self, msg: PlayerMessage) {
+        for player in self.players.iter_mut() {
+            player.handle_message(msg);
+        }
+    }
+
+    // Feeds whatever the players emitted back into the mapper until nothing
+    // more happens, then records the resulting output requests.
+    pub fn settle(&mut self, now: Time) {
+        loop {
+            let emitted: Vec<_> = self.player_midi.borrow_mut().drain(..).collect();
+            if emitted.is_empty() {
+                break;
+            }
+            for event in emitted {
+                self.mapper.handle_message(event);
+            }
+        }
+        for req in self.outputs.borrow_mut().drain(..) {
+            self.timeline.record(now, req);
+        }
+    }
+}
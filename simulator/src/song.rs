@@ -0,0 +1,119 @@
+use midly::live::{LiveEvent, SystemRealtime};
+use midly::{MetaMessage, Smf, Timing, TrackEventKind};
+
+use crate::timeline::Time;
+
+const PPQ: u64 = 24;
+const DEFAULT_TEMPO: u64 = 500_000; // Microseconds per beat, 120 BPM
+
+enum Item {
+    Tempo(u64),
+    Event(LiveEvent<'static>),
+}
+
+// Flattens all tracks of the file into one list of timestamped live events,
+// with Start, 24 PPQ TimingClock and Stop added as if a DAW was playing it.
+pub fn render(smf: &Smf) -> Vec<(Time, LiveEvent<'static>)> {
+    let mut items: Vec<(u64, Item)> = Vec::new();
+    for track in smf.tracks.iter() {
+        let mut tick = 0u64;
+        for event in track.iter() {
+            tick += event.delta.as_int() as u64;
+            match event.kind {
+                TrackEventKind::Meta(MetaMessage::Tempo(tempo)) => {
+                    items.push((tick, Item::Tempo(tempo.as_int() as u64)))
+                }
+                TrackEventKind::Midi { channel, message } => {
+                    items.push((tick, Item::Event(LiveEvent::Midi { channel, message })))
+                }
+                _ => {}
+            }
+        }
+    }
+    items.sort_by_key(|(tick, _)| *tick);
+    let end = items.last().map(|(tick, _)| *tick).unwrap_or(0);
+
+    let mut clock = match smf.header.timing {
+        Timing::Metrical(tpb) => TempoClock::metrical(tpb.as_int() as u64),
+        Timing::Timecode(fps, sub) => TempoClock::timecode(fps.as_f32() as f64 * sub as f64),
+    };
+
+    let mut out = Vec::new();
+    out.push((0, LiveEvent::Realtime(SystemRealtime::Start)));
+    let mut pulse = 0u64;
+    let mut items = items.into_iter().peekable();
+    loop {
+        let pulse_tick = clock.pulse_tick(pulse);
+        let next_item = items.peek().map(|(tick, _)| *tick as f64);
+        match (pulse_tick, next_item) {
+            (Some(p), next) if p <= end as f64 && next.is_none_or(|n| p < n) => {
+                let time = clock.time_at(p) as Time;
+                out.push((time, LiveEvent::Realtime(SystemRealtime::TimingClock)));
+                pulse += 1;
+            }
+            (_, Some(_)) => {
+                let (tick, item) = items.next().unwrap();
+                clock.advance(tick as f64);
+                match item {
+                    Item::Tempo(tempo) => clock.tempo = tempo,
+                    Item::Event(e) => out.push((clock.time as Time, e)),
+                }
+            }
+            _ => break,
+        }
+    }
+    let end_time = out.last().map(|(time, _)| *time).unwrap_or(0);
+    out.push((end_time, LiveEvent::Realtime(SystemRealtime::Stop)));
+    out
+}
+
+struct TempoClock {
+    ticks_per_beat: u64,
+    tick_time: Option<f64>, // Timecode files have a fixed time per tick
+    tempo: u64,
+    tick: f64,
+    time: f64,
+}
+
+impl TempoClock {
+    fn metrical(ticks_per_beat: u64) -> Self {
+        TempoClock {
+            ticks_per_beat,
+            tick_time: None,
+            tempo: DEFAULT_TEMPO,
+            tick: 0.0,
+            time: 0.0,
+        }
+    }
+
+    fn timecode(ticks_per_second: f64) -> Self {
+        TempoClock {
+            ticks_per_beat: 0,
+            tick_time: Some(1_000_000.0 / ticks_per_second),
+            tempo: DEFAULT_TEMPO,
+            tick: 0.0,
+            time: 0.0,
+        }
+    }
+
+    // There is no notion of beats in timecode files so no clock is sent
+    fn pulse_tick(&self, pulse: u64) -> Option<f64> {
+        match self.tick_time {
+            Some(_) => None,
+            None => Some(pulse as f64 * self.ticks_per_beat as f64 / PPQ as f64),
+        }
+    }
+
+    fn time_at(&self, tick: f64) -> f64 {
+        let delta = tick - self.tick;
+        match self.tick_time {
+            Some(tick_time) => self.time + delta * tick_time,
+            None => self.time + delta * self.tempo as f64 / self.ticks_per_beat as f64,
+        }
+    }
+
+    fn advance(&mut self, tick: f64) {
+        self.time = self.time_at(tick);
+        self.tick = tick;
+    }
+}
@@ -0,0 +1,358 @@
+use std::io::{self, Write};
+
+use master_core::envelope::Envelope;
+use master_core::lfo::Lfo;
+use master_core::outs::{
+    cents_to_voltage, note_to_voltage, val_to_voltage, Cv, Gate, OutputRequest,
+};
+
+pub type Time = u64; // Microseconds
+
+// Same as the flash time of the OutputHandler in the firmware
+const FLASH_TIME: Time = 20_000;
+// Same as the update step of the OutputHandler in the firmware
+const UPDATE_STEP: Time = 1_000;
+
+const GATES: [Gate; 14] = [
+    Gate::Kick,
+    Gate::OpenHH,
+    Gate::Clap,
+    Gate::Snare,
+    Gate::FX,
+    Gate::ClosedHH,
+    Gate::Accent,
+    Gate::Start,
+    Gate::Stop,
+    Gate::Clock,
+    Gate::GateA,
+    Gate::GateB,
+    Gate::GateC,
+    Gate::GateD,
+];
+const CVS: [Cv; 4] = [Cv::A, Cv::B, Cv::C, Cv::D];
+
+pub struct Timeline {
+    entries: Vec<(Time, OutputRequest)>,
+}
+
+impl Timeline {
+    pub fn new() -> Self {
+        Timeline {
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, time: Time, req: OutputRequest) {
+        self.entries.push((time, req));
+    }
+
+    pub fn write_csv<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "time_us,request,target,note,volts")?;
+        for (time, req) in self.entries.iter() {
+            match *req {
+                OutputRequest::GateOn(g) => writeln!(out, "{},gate_on,{:?},,", time, g)?,
+                OutputRequest::GateOff(g) => writeln!(out, "{},gate_off,{:?},,", time, g)?,
+                OutputRequest::Flash(g) => writeln!(out, "{},flash,{:?},,", time, g)?,
+                OutputRequest::Trigger(g, ms) => writeln!(out, "{},trigger,{:?},,{}", time, g, ms)?,
+                OutputRequest::SetNote(cv, note) => writeln!(
+                    out,
+                    "{},set_note,{:?},{},{}",
+                    time,
+                    cv,
+                    note,
+                    note_to_voltage(note)
+                )?,
+                OutputRequest::SetVal(cv, val) => {
+                    writeln!(out, "{},set_val,{:?},,{}", time, cv, val_to_voltage(val))?
+                }
+                OutputRequest::GlideNote(cv, note, _) => writeln!(
+                    out,
+                    "{},glide_note,{:?},{},{}",
+                    time,
+                    cv,
+                    note,
+                    note_to_voltage(note)
+                )?,
+                OutputRequest::SetBend(cv, volts) => {
+                    writeln!(out, "{},set_bend,{:?},,{}", time, cv, volts)?
+                }
+                OutputRequest::SetDetune(cv, cents) => writeln!(
+                    out,
+                    "{},set_detune,{:?},,{}",
+                    time,
+                    cv,
+                    cents_to_voltage(cents)
+                )?,
+                OutputRequest::SetLfo(cv, Some(settings)) => writeln!(
+                    out,
+                    "{},set_lfo,{:?},,{:?} {:?}",
+                    time, cv, settings.shape, settings.rate
+                )?,
+                OutputRequest::SetLfo(cv, None) => writeln!(out, "{},clear_lfo,{:?},,", time, cv)?,
+                OutputRequest::LfoClock(ticks) => writeln!(out, "{},lfo_clock,,{},", time, ticks)?,
+                OutputRequest::SetEnvelope(cv, Some(settings)) => writeln!(
+                    out,
+                    "{},set_envelope,{:?},,{:?} {} {} {} {}",
+                    time,
+                    cv,
+                    settings.source,
+                    settings.adsr.attack,
+                    settings.adsr.decay,
+                    settings.adsr.sustain,
+                    settings.adsr.release
+                )?,
+                OutputRequest::SetEnvelope(cv, None) => {
+                    writeln!(out, "{},clear_envelope,{:?},,", time, cv)?
+                }
+                OutputRequest::TriggerEnvelope(cv, true) => {
+                    writeln!(out, "{},envelope_open,{:?},,", time, cv)?
+                }
+                OutputRequest::TriggerEnvelope(cv, false) => {
+                    writeln!(out, "{},envelope_close,{:?},,", time, cv)?
+                }
+                OutputRequest::Calibrate(cv, calibration) => {
+                    let trims: Vec<String> = calibration.trims.iter().map(i16::to_string).collect();
+                    writeln!(out, "{},calibrate,{:?},,{}", time, cv, trims.join(" "))?
+                }
+                OutputRequest::Reference(cv, Some(volts)) => {
+                    writeln!(out, "{},reference,{:?},,{}", time, cv, volts)?
+                }
+                OutputRequest::Reference(cv, None) => {
+                    writeln!(out, "{},clear_reference,{:?},,", time, cv)?
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write_vcd<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "$timescale 1us $end")?;
+        writeln!(out, "$scope module master $end")?;
+        for (i, gate) in GATES.iter().enumerate() {
+            writeln!(out, "$var wire 1 {} {:?} $end", gate_id(i), gate)?;
+        }
+        for (i, cv) in CVS.iter().enumerate() {
+            writeln!(out, "$var real 64 {} Cv{:?} $end", cv_id(i), cv)?;
+        }
+        writeln!(out, "$upscope $end")?;
+        writeln!(out, "$enddefinitions $end")?;
+
+        let mut gates = [false; GATES.len()];
+        let mut cvs = [0.0f32; CVS.len()];
+        let mut flash_ends: [Option<Time>; GATES.len()] = [None; GATES.len()];
+        let mut ports = Ports::new();
+        let mut changes: Vec<(Time, String)> = Vec::new();
+
+        changes.push((0, String::from("$dumpvars")));
+        for i in 0..GATES.len() {
+            changes.push((0, format!("0{}", gate_id(i))));
+        }
+        for i in 0..CVS.len() {
+            changes.push((0, format!("r0 {}", cv_id(i))));
+        }
+        changes.push((0, String::from("$end")));
+
+        for (time, req) in self.entries.iter() {
+            for (i, flash_end) in flash_ends.iter_mut().enumerate() {
+                match *flash_end {
+                    Some(end) if end <= *time => {
+                        *flash_end = None;
+                        set_gate(&mut changes, &mut gates, i, end, false);
+                    }
+                    _ => {}
+                }
+            }
+            ports.run_until(&mut changes, &mut cvs, *time);
+            match *req {
+                OutputRequest::GateOn(g) => {
+                    set_gate(&mut changes, &mut gates, gate_index(g), *time, true)
+                }
+                OutputRequest::GateOff(g) => {
+                    set_gate(&mut changes, &mut gates, gate_index(g), *time, false)
+                }
+                OutputRequest::Flash(g) => {
+                    set_gate(&mut changes, &mut gates, gate_index(g), *time, true);
+                    flash_ends[gate_index(g)] = Some(*time + FLASH_TIME);
+                }
+                OutputRequest::Trigger(g, ms) => {
+                    set_gate(&mut changes, &mut gates, gate_index(g), *time, true);
+                    flash_ends[gate_index(g)] = Some(*time + ms as Time * 1000);
+                }
+                // Drawn as a jump, the glide is up to the hardware
+                OutputRequest::SetNote(cv, note) | OutputRequest::GlideNote(cv, note, _) => {
+                    ports.notes[cv.index()] = Some(note_to_voltage(note));
+                    ports.draw(&mut changes, &mut cvs, cv, *time, note_to_voltage(note));
+                }
+                OutputRequest::SetVal(cv, val) => {
+                    ports.notes[cv.index()] = None;
+                    ports.draw(&mut changes, &mut cvs, cv, *time, val_to_voltage(val));
+                }
+                OutputRequest::SetLfo(cv, settings) => {
+                    let i = cv.index();
+                    ports.lfos[i] =
+                        settings.map(|settings| Lfo::new(settings, *time as u32 ^ i as u32));
+                    ports.envelopes[i] = None;
+                    ports.notes[i] = None;
+                    if settings.is_none() {
+                        ports.draw(&mut changes, &mut cvs, cv, *time, val_to_voltage(0.0));
+                    }
+                }
+                OutputRequest::LfoClock(ticks) => {
+                    for lfo in ports.lfos.iter_mut().flatten() {
+                        lfo.clock(ticks, *time);
+                    }
+                }
+                OutputRequest::SetEnvelope(cv, settings) => {
+                    let i = cv.index();
+                    match (settings, ports.envelopes[i].as_mut()) {
+                        (Some(settings), Some(envelope)) => envelope.set_adsr(settings.adsr),
+                        (Some(settings), None) => {
+                            ports.envelopes[i] = Some(Envelope::new(settings.adsr));
+                            ports.lfos[i] = None;
+                            ports.notes[i] = None;
+                        }
+                        (None, _) => {
+                            ports.envelopes[i] = None;
+                            ports.draw(&mut changes, &mut cvs, cv, *time, val_to_voltage(0.0));
+                        }
+                    }
+                }
+                OutputRequest::TriggerEnvelope(cv, open) => {
+                    if let Some(envelope) = ports.envelopes[cv.index()].as_mut() {
+                        envelope.gate(open, *time);
+                    }
+                }
+                // The reference volt itself, the trims only show on a meter
+                OutputRequest::Reference(cv, point) => {
+                    ports.references[cv.index()] = point;
+                    match (point, ports.notes[cv.index()]) {
+                        (Some(volts), _) => set_cv(&mut changes, &mut cvs, cv, *time, volts as f32),
+                        (None, Some(volts)) => ports.draw(&mut changes, &mut cvs, cv, *time, volts),
+                        (None, None) => {}
+                    }
+                }
+                // Only the notes themselves are drawn
+                OutputRequest::SetBend(..)
+                | OutputRequest::SetDetune(..)
+                | OutputRequest::Calibrate(..) => {}
+            }
+        }
+        for (i, flash_end) in flash_ends.iter().enumerate() {
+            if let Some(end) = *flash_end {
+                set_gate(&mut changes, &mut gates, i, end, false);
+            }
+        }
+
+        changes.sort_by_key(|(time, _)| *time);
+        let mut current = None;
+        for (time, change) in changes {
+            if current != Some(time) {
+                writeln!(out, "#{}", time)?;
+                current = Some(time);
+            }
+            writeln!(out, "{}", change)?;
+        }
+        Ok(())
+    }
+}
+
+// What the output handler keeps for each CV port, with its LFOs and envelopes
+// stepped along like its update timer does
+struct Ports {
+    notes: [Option<f32>; CVS.len()],
+    lfos: [Option<Lfo>; CVS.len()],
+    envelopes: [Option<Envelope>; CVS.len()],
+    references: [Option<u8>; CVS.len()],
+    updated: Time,
+}
+
+impl Ports {
+    fn new() -> Self {
+        Ports {
+            notes: [None; CVS.len()],
+            lfos: [None; CVS.len()],
+            envelopes: [None; CVS.len()],
+            references: [None; CVS.len()],
+            updated: 0,
+        }
+    }
+
+    fn is_running(&self) -> bool {
+        self.lfos.iter().any(|lfo| lfo.is_some())
+            || self
+                .envelopes
+                .iter()
+                .flatten()
+                .any(|envelope| !envelope.is_idle())
+    }
+
+    // A port held at a reference volt ignores everything else
+    fn draw(
+        &self,
+        changes: &mut Vec<(Time, String)>,
+        cvs: &mut [f32],
+        cv: Cv,
+        time: Time,
+        volts: f32,
+    ) {
+        if self.references[cv.index()].is_none() {
+            set_cv(changes, cvs, cv, time, volts);
+        }
+    }
+
+    fn run_until(&mut self, changes: &mut Vec<(Time, String)>, cvs: &mut [f32], time: Time) {
+        if !self.is_running() {
+            self.updated = time;
+            return;
+        }
+        while self.updated + UPDATE_STEP <= time {
+            self.updated += UPDATE_STEP;
+            let now = self.updated;
+            for (i, cv) in CVS.iter().enumerate() {
+                if let Some(lfo) = self.lfos[i].as_mut() {
+                    lfo.advance(now);
+                    let volts = val_to_voltage(lfo.value());
+                    self.draw(changes, cvs, *cv, now, volts);
+                }
+                if let Some(envelope) = self.envelopes[i].as_mut() {
+                    envelope.advance(now);
+                    let volts = val_to_voltage(envelope.value());
+                    self.draw(changes, cvs, *cv, now, volts);
+                }
+            }
+        }
+    }
+}
+
+fn gate_index(gate: Gate) -> usize {
+    GATES.iter().position(|g| *g == gate).unwrap()
+}
+
+fn gate_id(index: usize) -> char {
+    (b'!' + index as u8) as char
+}
+
+fn cv_id(index: usize) -> char {
+    (b'!' + (GATES.len() + index) as u8) as char
+}
+
+fn set_gate(
+    changes: &mut Vec<(Time, String)>,
+    gates: &mut [bool],
+    i: usize,
+    time: Time,
+    state: bool,
+) {
+    if gates[i] != state {
+        gates[i] = state;
+        changes.push((time, format!("{}{}", if state { 1 } else { 0 }, gate_id(i))));
+    }
+}
+
+fn set_cv(changes: &mut Vec<(Time, String)>, cvs: &mut [f32], cv: Cv, time: Time, volts: f32) {
+    let i = CVS.iter().position(|c| *c == cv).unwrap();
+    if cvs[i] != volts {
+        cvs[i] = volts;
+        changes.push((time, format!("r{} {}", volts, cv_id(i))));
+    }
+}